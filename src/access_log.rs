@@ -0,0 +1,276 @@
+//! Access logging of guest memory regions.
+//!
+//! A traced mapping has all its permissions dropped, so that every guest access to it faults.
+//! [AccessTracer::handle_exit] records each faulting access (PC, address, size, direction and
+//! value), emulates it against the backing memory and resumes the guest after the instruction.
+//! This is mostly useful to reverse-engineer how unknown guest drivers use a shared region.
+//!
+//! Only accesses with a valid instruction syndrome (single register loads and stores) can be
+//! emulated. On any other access (load/store pair, SIMD, atomics, ...) and on instruction fetches,
+//! the original permission of the mapping is restored and the instruction single stepped, after
+//! which the mapping is traced again. The vCPU must trap debug exceptions
+//! ([VirtualCpu::set_trap_debug_exceptions]) so that the software step exits to the host.
+
+use crate::bindings::hv_ipa_t;
+use crate::debug::set_software_step;
+use crate::err::Result;
+use crate::reg::{GENERAL_PURPOSE_REGISTERS, Register};
+use crate::vcpu::{ExceptionClass, ExceptionExit, VirtualCpu};
+use crate::virtual_machine::{MappingHandle, MemoryPermission, VirtualMachine};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Permission of a traced mapping.
+const NO_ACCESS: MemoryPermission = MemoryPermission::new(false, false, false);
+
+/// A guest access to a traced region.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AccessRecord {
+    /// The PC of the accessing instruction.
+    pub pc: u64,
+
+    /// The guest physical address accessed.
+    pub address: hv_ipa_t,
+
+    /// The size of the access in bytes.
+    pub size: u8,
+
+    /// The access is a write.
+    pub is_write: bool,
+
+    /// The access is an instruction fetch.
+    pub is_fetch: bool,
+
+    /// The value read or written, if the access was emulated.
+    pub value: Option<u64>,
+}
+
+/// A traced mapping.
+#[derive(Copy, Clone, Debug)]
+struct TracedMapping {
+    /// The mapping handle.
+    handle: MappingHandle,
+
+    /// The guest address of the mapping.
+    address: hv_ipa_t,
+
+    /// The size of the mapping.
+    size: usize,
+
+    /// The permission to restore.
+    permission: MemoryPermission,
+}
+
+/// Records the guest accesses to traced mappings.
+#[derive(Clone, Debug, Default)]
+pub struct AccessTracer {
+    /// List of all traced mappings.
+    mappings: Vec<TracedMapping>,
+
+    /// Recorded accesses, in order.
+    log: Vec<AccessRecord>,
+
+    /// The mapping to trace again once the retried instruction was stepped, if any.
+    stepping: Option<MappingHandle>,
+}
+
+impl AccessTracer {
+    /// Create a new tracer without any traced mapping.
+    pub fn new() -> Self {
+        AccessTracer::default()
+    }
+
+    /// Start tracing a mapping.
+    pub fn trace(&mut self, vm: &mut VirtualMachine, handle: MappingHandle) -> Result<()> {
+        let mapping = vm.get_mapping_info(handle)?;
+
+        if self.mappings.iter().any(|traced| traced.handle == handle) {
+            return Ok(());
+        }
+
+        vm.reprotect(handle, NO_ACCESS)?;
+
+        self.mappings.push(TracedMapping {
+            handle,
+            address: mapping.address,
+            size: mapping.size,
+            permission: mapping.permission,
+        });
+
+        Ok(())
+    }
+
+    /// Stop tracing a mapping, restoring its original permission.
+    pub fn untrace(&mut self, vm: &mut VirtualMachine, handle: MappingHandle) -> Result<()> {
+        if let Some(index) = self
+            .mappings
+            .iter()
+            .position(|traced| traced.handle == handle)
+        {
+            let traced = self.mappings.remove(index);
+
+            // While stepping, the original permission is already in place.
+            if self.stepping == Some(handle) {
+                self.stepping = None;
+            } else {
+                vm.reprotect(traced.handle, traced.permission)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the recorded accesses.
+    pub fn log(&self) -> &[AccessRecord] {
+        &self.log
+    }
+
+    /// Take the recorded accesses, clearing the log.
+    pub fn take_log(&mut self) -> Vec<AccessRecord> {
+        core::mem::take(&mut self.log)
+    }
+
+    /// Restore the permission of a traced mapping and single step the faulting instruction.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    fn step_untraced(
+        &mut self,
+        vm: &mut VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        traced: &TracedMapping,
+    ) -> Result<()> {
+        vm.reprotect(traced.handle, traced.permission)?;
+
+        set_software_step(vcpu, true)?;
+
+        self.stepping = Some(traced.handle);
+
+        Ok(())
+    }
+
+    /// Trace a mapping again after the software step started by [AccessTracer::step_untraced].
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    fn rearm(&mut self, vm: &mut VirtualMachine, vcpu: &mut VirtualCpu) -> Result<bool> {
+        let Some(handle) = self.stepping.take() else {
+            return Ok(false);
+        };
+
+        set_software_step(vcpu, false)?;
+        vm.reprotect(handle, NO_ACCESS)?;
+
+        Ok(true)
+    }
+
+    /// Handle an exception exit of a vCPU.
+    ///
+    /// Returns `false` if the exception isn't an access to a traced mapping or the software step
+    /// of a retried instruction, in which case the vCPU is left untouched.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn handle_exit(
+        &mut self,
+        vm: &mut VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        exception: &ExceptionExit,
+    ) -> Result<bool> {
+        let is_fetch = match exception.class() {
+            ExceptionClass::DataAbort => false,
+            ExceptionClass::InstructionAbort => true,
+            ExceptionClass::SoftwareStep => return self.rearm(vm, vcpu),
+            _ => return Ok(false),
+        };
+
+        let address = exception.physical_address;
+
+        let Some(traced) = self
+            .mappings
+            .iter()
+            .find(|traced| {
+                address >= traced.address && address - traced.address < traced.size as u64
+            })
+            .copied()
+        else {
+            return Ok(false);
+        };
+
+        let iss = exception.iss();
+        let pc = vcpu.get_register(Register::PC)?;
+
+        // Fetches and accesses without a valid syndrome can't be emulated: retry untraced.
+        if is_fetch || iss & (1 << 24) == 0 {
+            self.log.push(AccessRecord {
+                pc,
+                address,
+                size: if is_fetch {
+                    exception.instruction_length() as u8
+                } else {
+                    1 << ((iss >> 22) & 0b11)
+                },
+                is_write: !is_fetch && iss & (1 << 6) != 0,
+                is_fetch,
+                value: None,
+            });
+
+            self.step_untraced(vm, vcpu, &traced)?;
+
+            return Ok(true);
+        }
+
+        let size = 1u8 << ((iss >> 22) & 0b11);
+        let is_write = iss & (1 << 6) != 0;
+
+        let register = ((iss >> 16) & 0x1f) as usize;
+        let mask = u64::MAX >> (64 - u32::from(size) * 8);
+        let mut bytes = [0; 8];
+
+        let value = if is_write {
+            let value = match register {
+                31 => 0,
+                _ => vcpu.get_register(GENERAL_PURPOSE_REGISTERS[register])? & mask,
+            };
+
+            vm.write_memory(address, &value.to_le_bytes()[..size as usize])?;
+
+            value
+        } else {
+            vm.read_memory(address, &mut bytes[..size as usize])?;
+
+            let value = u64::from_le_bytes(bytes);
+            let sign_extend = iss & (1 << 21) != 0;
+            let is_64bit = iss & (1 << 15) != 0;
+
+            let mut result = value;
+
+            if sign_extend && size < 8 {
+                let shift = 64 - u32::from(size) * 8;
+
+                result = (((value << shift) as i64) >> shift) as u64;
+            }
+
+            if !is_64bit {
+                result &= 0xFFFF_FFFF;
+            }
+
+            if register != 31 {
+                vcpu.set_register(GENERAL_PURPOSE_REGISTERS[register], result)?;
+            }
+
+            value
+        };
+
+        self.log.push(AccessRecord {
+            pc,
+            address,
+            size,
+            is_write,
+            is_fetch: false,
+            value: Some(value),
+        });
+
+        vcpu.set_register(Register::PC, pc + exception.instruction_length())?;
+
+        Ok(true)
+    }
+}
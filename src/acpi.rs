@@ -0,0 +1,415 @@
+//! ACPI table generation.
+//!
+//! An alternative to the device tree for guests booting through ACPI (hardware-reduced profile,
+//! PSCI over HVC, GICv3). The tables are laid out in a single blob to load at a fixed guest
+//! address, starting with the RSDP. The firmware is responsible for publishing the RSDP address
+//! to the guest (usually through the EFI configuration table).
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::machine::MemoryRegion;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// OEM identifier of the generated tables.
+const OEM_ID: &[u8; 6] = b"AHVF  ";
+
+/// OEM table identifier of the generated tables.
+const OEM_TABLE_ID: &[u8; 8] = b"AHVFMACH";
+
+/// Creator identifier of the generated tables.
+const CREATOR_ID: &[u8; 4] = b"AHVF";
+
+/// Size of a system description table header.
+const HEADER_SIZE: usize = 36;
+
+/// Size of the RSDP (ACPI 2.0+).
+const RSDP_SIZE: usize = 36;
+
+/// Size of the FADT (ACPI 6.3).
+const FADT_SIZE: usize = 276;
+
+/// FADT flag: hardware-reduced ACPI.
+const FADT_HW_REDUCED_ACPI: u32 = 1 << 20;
+
+/// FADT ARM boot architecture flag: PSCI is implemented.
+const ARM_BOOT_ARCH_PSCI_COMPLIANT: u16 = 1 << 0;
+
+/// FADT ARM boot architecture flag: PSCI uses HVC instead of SMC.
+const ARM_BOOT_ARCH_PSCI_USE_HVC: u16 = 1 << 1;
+
+/// MADT GIC CPU interface structure type.
+const MADT_GICC: u8 = 0x0B;
+
+/// MADT GIC distributor structure type.
+const MADT_GICD: u8 = 0x0C;
+
+/// MADT GIC redistributor structure type.
+const MADT_GICR: u8 = 0x0E;
+
+/// GTDT value of an absent counter block address.
+const GTDT_NO_COUNTER_BLOCK: u64 = u64::MAX;
+
+/// Secure EL1 physical timer interrupt (PPI INTID).
+pub const SECURE_PHYSICAL_TIMER_INTID: u32 = 29;
+
+/// Non-secure EL1 physical timer interrupt (PPI INTID).
+pub const PHYSICAL_TIMER_INTID: u32 = 30;
+
+/// EL1 virtual timer interrupt (PPI INTID).
+pub const VIRTUAL_TIMER_INTID: u32 = 27;
+
+/// EL2 physical timer interrupt (PPI INTID).
+pub const HYPERVISOR_TIMER_INTID: u32 = 26;
+
+/// First SPI INTID, to convert SPI numbers into GSIVs.
+pub const SPI_BASE: u32 = 32;
+
+/// AML zero opcode.
+const AML_ZERO: u8 = 0x00;
+
+/// AML one opcode.
+const AML_ONE: u8 = 0x01;
+
+/// AML name opcode.
+const AML_NAME: u8 = 0x08;
+
+/// AML byte constant prefix.
+const AML_BYTE_PREFIX: u8 = 0x0A;
+
+/// AML word constant prefix.
+const AML_WORD_PREFIX: u8 = 0x0B;
+
+/// AML double word constant prefix.
+const AML_DWORD_PREFIX: u8 = 0x0C;
+
+/// AML string prefix.
+const AML_STRING_PREFIX: u8 = 0x0D;
+
+/// AML scope opcode.
+const AML_SCOPE: u8 = 0x10;
+
+/// AML buffer opcode.
+const AML_BUFFER: u8 = 0x11;
+
+/// AML extended opcode prefix.
+const AML_EXT_PREFIX: u8 = 0x5B;
+
+/// AML device opcode (extended).
+const AML_DEVICE: u8 = 0x82;
+
+/// A device described in the DSDT.
+#[derive(Copy, Clone, Debug)]
+pub struct AcpiDevice<'a> {
+    /// The AML name of the device (up to 4 characters among `A-Z`, `0-9` and `_`).
+    pub name: &'a str,
+
+    /// The hardware identifier (`_HID`), for example `ARMH0011` for a PL011 UART.
+    pub hid: &'a str,
+
+    /// The unique identifier (`_UID`).
+    pub uid: u32,
+
+    /// The MMIO region of the device.
+    pub region: MemoryRegion,
+
+    /// The level-triggered interrupt of the device (GSIV), if any.
+    pub interrupt: Option<u32>,
+}
+
+/// Compute the byte that makes the sum of a table zero.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |sum, value| sum.wrapping_add(*value))
+        .wrapping_neg()
+}
+
+/// Build a system description table from its signature, revision and body.
+fn table(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(HEADER_SIZE + body.len());
+
+    result.extend_from_slice(signature);
+    result.extend_from_slice(&((HEADER_SIZE + body.len()) as u32).to_le_bytes());
+    result.push(revision);
+    result.push(0);
+    result.extend_from_slice(OEM_ID);
+    result.extend_from_slice(OEM_TABLE_ID);
+    result.extend_from_slice(&1u32.to_le_bytes());
+    result.extend_from_slice(CREATOR_ID);
+    result.extend_from_slice(&1u32.to_le_bytes());
+    result.extend_from_slice(body);
+
+    result[9] = checksum(&result);
+
+    result
+}
+
+/// Build the MADT describing a GICv3 and one CPU interface per MPIDR.
+pub fn madt(gic_distributor: hv_ipa_t, gic_redistributor: MemoryRegion, mpidrs: &[u64]) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    // Local interrupt controller address and flags.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+
+    for (index, mpidr) in mpidrs.iter().enumerate() {
+        let mut gicc = [0u8; 80];
+
+        gicc[0] = MADT_GICC;
+        gicc[1] = gicc.len() as u8;
+        gicc[4..8].copy_from_slice(&(index as u32).to_le_bytes());
+        gicc[8..12].copy_from_slice(&(index as u32).to_le_bytes());
+        // Enabled.
+        gicc[12..16].copy_from_slice(&1u32.to_le_bytes());
+        gicc[68..76].copy_from_slice(&mpidr.to_le_bytes());
+
+        body.extend_from_slice(&gicc);
+    }
+
+    let mut gicd = [0u8; 24];
+
+    gicd[0] = MADT_GICD;
+    gicd[1] = gicd.len() as u8;
+    gicd[8..16].copy_from_slice(&gic_distributor.to_le_bytes());
+    gicd[20] = 3;
+
+    body.extend_from_slice(&gicd);
+
+    let mut gicr = [0u8; 16];
+
+    gicr[0] = MADT_GICR;
+    gicr[1] = gicr.len() as u8;
+    gicr[4..12].copy_from_slice(&gic_redistributor.base.to_le_bytes());
+    gicr[12..16].copy_from_slice(&(gic_redistributor.size as u32).to_le_bytes());
+
+    body.extend_from_slice(&gicr);
+
+    table(b"APIC", 5, &body)
+}
+
+/// Build the GTDT describing the architected timers (level-triggered, active high).
+pub fn gtdt() -> Vec<u8> {
+    let mut body = Vec::new();
+
+    body.extend_from_slice(&GTDT_NO_COUNTER_BLOCK.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+
+    for intid in [
+        SECURE_PHYSICAL_TIMER_INTID,
+        PHYSICAL_TIMER_INTID,
+        VIRTUAL_TIMER_INTID,
+        HYPERVISOR_TIMER_INTID,
+    ] {
+        body.extend_from_slice(&intid.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+    }
+
+    body.extend_from_slice(&GTDT_NO_COUNTER_BLOCK.to_le_bytes());
+
+    // No platform timers.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+
+    table(b"GTDT", 2, &body)
+}
+
+/// Build the FADT of a hardware-reduced platform using PSCI over HVC.
+fn fadt(dsdt: hv_ipa_t) -> Vec<u8> {
+    let mut body = [0u8; FADT_SIZE - HEADER_SIZE];
+    let offset = |field: usize| field - HEADER_SIZE;
+
+    body[offset(112)..offset(116)].copy_from_slice(&FADT_HW_REDUCED_ACPI.to_le_bytes());
+    body[offset(129)..offset(131)].copy_from_slice(
+        &(ARM_BOOT_ARCH_PSCI_COMPLIANT | ARM_BOOT_ARCH_PSCI_USE_HVC).to_le_bytes(),
+    );
+    // FADT minor version (ACPI 6.3).
+    body[offset(131)] = 3;
+    body[offset(140)..offset(148)].copy_from_slice(&dsdt.to_le_bytes());
+
+    table(b"FACP", 6, &body)
+}
+
+/// Encode an AML package length followed by its contents.
+fn aml_package(contents: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(contents.len() + 4);
+
+    if contents.len() + 1 < 0x40 {
+        result.push((contents.len() + 1) as u8);
+    } else {
+        // Each additional byte encodes 8 more bits of the length, on top of 4 in the lead byte.
+        let extra = (1..=3)
+            .find(|extra| contents.len() + 1 + extra < 1 << (4 + extra * 8))
+            .unwrap_or(3);
+        let length = contents.len() + 1 + extra;
+
+        result.push(((extra as u8) << 6) | (length & 0xF) as u8);
+
+        for index in 0..extra {
+            result.push((length >> (4 + index * 8)) as u8);
+        }
+    }
+
+    result.extend_from_slice(contents);
+
+    result
+}
+
+/// Encode an AML name segment.
+fn aml_name(name: &str) -> Result<[u8; 4]> {
+    let valid = |(index, c): (usize, &u8)| {
+        c.is_ascii_uppercase() || *c == b'_' || (index != 0 && c.is_ascii_digit())
+    };
+
+    if name.is_empty() || name.len() > 4 || !name.as_bytes().iter().enumerate().all(valid) {
+        return Err(HypervisorError::BadArgument);
+    }
+
+    let mut result = [b'_'; 4];
+
+    result[..name.len()].copy_from_slice(name.as_bytes());
+
+    Ok(result)
+}
+
+/// Encode an AML integer.
+fn aml_integer(output: &mut Vec<u8>, value: u32) {
+    match value {
+        0 => output.push(AML_ZERO),
+        1 => output.push(AML_ONE),
+        2..=0xFF => output.extend_from_slice(&[AML_BYTE_PREFIX, value as u8]),
+        0x100..=0xFFFF => {
+            output.push(AML_WORD_PREFIX);
+            output.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        _ => {
+            output.push(AML_DWORD_PREFIX);
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Encode an AML device.
+fn aml_device(device: &AcpiDevice) -> Result<Vec<u8>> {
+    if device.hid.is_empty() || !device.hid.is_ascii() || device.hid.contains('\0') {
+        return Err(HypervisorError::BadArgument);
+    }
+
+    let base = u32::try_from(device.region.base).map_err(|_| HypervisorError::BadArgument)?;
+    let size = u32::try_from(device.region.size).map_err(|_| HypervisorError::BadArgument)?;
+
+    // Resource template: fixed 32-bit memory range, interrupt, end tag.
+    let mut resources = Vec::new();
+
+    resources.extend_from_slice(&[0x86, 0x09, 0x00, 0x01]);
+    resources.extend_from_slice(&base.to_le_bytes());
+    resources.extend_from_slice(&size.to_le_bytes());
+
+    if let Some(interrupt) = device.interrupt {
+        // Consumer, level-triggered, active high, exclusive.
+        resources.extend_from_slice(&[0x89, 0x06, 0x00, 0x01, 0x01]);
+        resources.extend_from_slice(&interrupt.to_le_bytes());
+    }
+
+    resources.extend_from_slice(&[0x79, 0x00]);
+
+    let mut buffer = Vec::new();
+
+    aml_integer(&mut buffer, resources.len() as u32);
+    buffer.extend_from_slice(&resources);
+
+    let mut contents = Vec::new();
+
+    contents.extend_from_slice(&aml_name(device.name)?);
+
+    contents.push(AML_NAME);
+    contents.extend_from_slice(b"_HID");
+    contents.push(AML_STRING_PREFIX);
+    contents.extend_from_slice(device.hid.as_bytes());
+    contents.push(0);
+
+    contents.push(AML_NAME);
+    contents.extend_from_slice(b"_UID");
+    aml_integer(&mut contents, device.uid);
+
+    contents.push(AML_NAME);
+    contents.extend_from_slice(b"_CRS");
+    contents.push(AML_BUFFER);
+    contents.extend_from_slice(&aml_package(&buffer));
+
+    let mut result = Vec::from([AML_EXT_PREFIX, AML_DEVICE]);
+
+    result.extend_from_slice(&aml_package(&contents));
+
+    Ok(result)
+}
+
+/// Build the DSDT describing devices in the `\_SB` scope.
+pub fn dsdt(devices: &[AcpiDevice]) -> Result<Vec<u8>> {
+    let mut contents = Vec::from(*b"\\_SB_");
+
+    for device in devices {
+        contents.extend_from_slice(&aml_device(device)?);
+    }
+
+    let mut body = Vec::from([AML_SCOPE]);
+
+    body.extend_from_slice(&aml_package(&contents));
+
+    Ok(table(b"DSDT", 2, &body))
+}
+
+/// Lay out the ACPI tables in a blob to load at a given guest address.
+///
+/// The blob starts with the RSDP, followed by the XSDT, a FADT pointing to the DSDT, the DSDT and
+/// the other tables (MADT, GTDT, ...), each 8 bytes aligned.
+pub fn build(address: hv_ipa_t, dsdt: &[u8], tables: &[&[u8]]) -> Vec<u8> {
+    let align = |offset: usize| offset.next_multiple_of(8);
+
+    let xsdt_offset = align(RSDP_SIZE);
+    let fadt_offset = align(xsdt_offset + HEADER_SIZE + (tables.len() + 1) * 8);
+    let dsdt_offset = align(fadt_offset + FADT_SIZE);
+
+    let mut table_offsets = Vec::with_capacity(tables.len());
+    let mut end = align(dsdt_offset + dsdt.len());
+
+    for table in tables {
+        table_offsets.push(end);
+        end = align(end + table.len());
+    }
+
+    let mut xsdt_body = Vec::new();
+
+    for offset in core::iter::once(fadt_offset).chain(table_offsets.iter().copied()) {
+        xsdt_body.extend_from_slice(&(address + offset as u64).to_le_bytes());
+    }
+
+    let mut rsdp = [0u8; RSDP_SIZE];
+
+    rsdp[0..8].copy_from_slice(b"RSD PTR ");
+    rsdp[9..15].copy_from_slice(OEM_ID);
+    rsdp[15] = 2;
+    rsdp[20..24].copy_from_slice(&(RSDP_SIZE as u32).to_le_bytes());
+    rsdp[24..32].copy_from_slice(&(address + xsdt_offset as u64).to_le_bytes());
+    rsdp[8] = checksum(&rsdp[..20]);
+    rsdp[32] = checksum(&rsdp);
+
+    let mut result = vec![0; end];
+
+    result[..RSDP_SIZE].copy_from_slice(&rsdp);
+
+    for (offset, data) in [
+        (xsdt_offset, table(b"XSDT", 1, &xsdt_body).as_slice()),
+        (fadt_offset, fadt(address + dsdt_offset as u64).as_slice()),
+        (dsdt_offset, dsdt),
+    ] {
+        result[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    for (offset, data) in table_offsets.into_iter().zip(tables) {
+        result[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    result
+}
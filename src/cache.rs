@@ -0,0 +1,277 @@
+//! Cache topology decoding (CLIDR_EL1, CTR_EL0, CCSIDR_EL1 and DCZID_EL0).
+
+use crate::bindings::hv_ipa_t;
+use crate::err::Result;
+use crate::fdt::FdtWriter;
+use crate::reg::{FeatureRegister, Register};
+use crate::vcpu::{CacheType, ExceptionExit, VirtualCpu, VirtualCpuConfiguration};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Maximum number of cache levels described by CLIDR_EL1.
+const MAX_CACHE_LEVELS: usize = 7;
+
+/// Kind of cache implemented at a given level (CLIDR_EL1.Ctype<n>).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CacheKind {
+    /// Instruction cache only.
+    Instruction,
+
+    /// Data cache only.
+    Data,
+
+    /// Separate instruction and data caches.
+    Separate,
+
+    /// Unified cache.
+    Unified,
+}
+
+/// Geometry of a cache, decoded from CCSIDR_EL1.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CacheGeometry {
+    /// Size of a cache line in bytes.
+    pub line_size: u32,
+
+    /// Number of ways.
+    pub associativity: u32,
+
+    /// Number of sets.
+    pub sets: u32,
+}
+
+impl CacheGeometry {
+    /// Decode a CCSIDR_EL1 value.
+    ///
+    /// `ccidx` should be set if FEAT_CCIDX is implemented (ID_AA64MMFR2_EL1.CCIDX).
+    pub fn from_ccsidr(value: u64, ccidx: bool) -> Self {
+        let line_size = 1 << ((value & 0x7) + 4);
+
+        let (associativity, sets) = if ccidx {
+            ((value >> 3) & 0x1F_FFFF, (value >> 32) & 0xFF_FFFF)
+        } else {
+            ((value >> 3) & 0x3FF, (value >> 13) & 0x7FFF)
+        };
+
+        CacheGeometry {
+            line_size,
+            associativity: associativity as u32 + 1,
+            sets: sets as u32 + 1,
+        }
+    }
+
+    /// Gets the total size of the cache in bytes.
+    pub fn size(&self) -> u64 {
+        u64::from(self.line_size) * u64::from(self.associativity) * u64::from(self.sets)
+    }
+}
+
+/// A level of the cache hierarchy.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CacheLevel {
+    /// The cache level (starting at 1).
+    pub level: u8,
+
+    /// The kind of cache implemented at this level.
+    pub kind: CacheKind,
+
+    /// Geometry of the data or unified cache, if any.
+    pub data: Option<CacheGeometry>,
+
+    /// Geometry of the instruction cache, if any.
+    pub instruction: Option<CacheGeometry>,
+}
+
+/// Cache topology of a vCPU.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheInfo {
+    /// The implemented cache levels, starting at level 1.
+    pub levels: Vec<CacheLevel>,
+
+    /// Level of Coherence (CLIDR_EL1.LoC).
+    pub level_of_coherence: u8,
+
+    /// Level of Unification Uniprocessor (CLIDR_EL1.LoUU).
+    pub level_of_unification: u8,
+
+    /// Level of Unification Inner Shareable (CLIDR_EL1.LoUIS).
+    pub level_of_unification_inner_shareable: u8,
+
+    /// Smallest instruction cache line size in bytes (CTR_EL0.IminLine).
+    pub instruction_min_line_size: u32,
+
+    /// Smallest data cache line size in bytes (CTR_EL0.DminLine).
+    pub data_min_line_size: u32,
+
+    /// Data cache clean to the Point of Unification isn't required for instruction to data
+    /// coherence (CTR_EL0.IDC).
+    pub idc: bool,
+
+    /// Instruction cache invalidation to the Point of Unification isn't required for data to
+    /// instruction coherence (CTR_EL0.DIC).
+    pub dic: bool,
+}
+
+impl CacheInfo {
+    /// Decode the cache topology from the raw register values.
+    ///
+    /// `data_ccsidr` and `instruction_ccsidr` hold the CCSIDR_EL1 values of each level (starting
+    /// at level 1) for data/unified and instruction caches respectively.
+    pub fn decode(
+        clidr: u64,
+        ctr: u64,
+        data_ccsidr: &[u64; 8],
+        instruction_ccsidr: &[u64; 8],
+        ccidx: bool,
+    ) -> Self {
+        let mut levels = Vec::new();
+
+        for index in 0..MAX_CACHE_LEVELS {
+            let kind = match (clidr >> (index * 3)) & 0x7 {
+                0b001 => CacheKind::Instruction,
+                0b010 => CacheKind::Data,
+                0b011 => CacheKind::Separate,
+                0b100 => CacheKind::Unified,
+                _ => break,
+            };
+
+            let has_data = kind != CacheKind::Instruction;
+            let has_instruction = matches!(kind, CacheKind::Instruction | CacheKind::Separate);
+
+            levels.push(CacheLevel {
+                level: index as u8 + 1,
+                kind,
+                data: has_data.then(|| CacheGeometry::from_ccsidr(data_ccsidr[index], ccidx)),
+                instruction: has_instruction
+                    .then(|| CacheGeometry::from_ccsidr(instruction_ccsidr[index], ccidx)),
+            });
+        }
+
+        CacheInfo {
+            levels,
+            level_of_coherence: ((clidr >> 24) & 0x7) as u8,
+            level_of_unification: ((clidr >> 27) & 0x7) as u8,
+            level_of_unification_inner_shareable: ((clidr >> 21) & 0x7) as u8,
+            instruction_min_line_size: 4 << (ctr & 0xF),
+            data_min_line_size: 4 << ((ctr >> 16) & 0xF),
+            idc: ctr & (1 << 28) != 0,
+            dic: ctr & (1 << 29) != 0,
+        }
+    }
+
+    /// Gets a cache level (starting at 1).
+    pub fn level(&self, level: u8) -> Option<&CacheLevel> {
+        self.levels.iter().find(|entry| entry.level == level)
+    }
+
+    /// Write the level 1 cache properties of a cpu node to a device tree.
+    ///
+    /// This should be called while the cpu node is opened.
+    pub fn write_l1_properties(&self, fdt: &mut FdtWriter) {
+        let Some(level) = self.level(1) else {
+            return;
+        };
+
+        if let Some(data) = level.data {
+            fdt.property_u32("d-cache-size", data.size() as u32);
+            fdt.property_u32("d-cache-line-size", data.line_size);
+            fdt.property_u32("d-cache-sets", data.sets);
+        }
+
+        if let Some(instruction) = level.instruction {
+            fdt.property_u32("i-cache-size", instruction.size() as u32);
+            fdt.property_u32("i-cache-line-size", instruction.line_size);
+            fdt.property_u32("i-cache-sets", instruction.sets);
+        }
+    }
+}
+
+impl VirtualCpuConfiguration {
+    /// Gets the cache topology reported to vCPUs created with this configuration.
+    pub fn get_cache_info(&self) -> Result<CacheInfo> {
+        let clidr = self.get_feature_register(FeatureRegister::CLIDR_EL1)?;
+        let ctr = self.get_feature_register(FeatureRegister::CTR_EL0)?;
+        let mmfr2 = self.get_feature_register(FeatureRegister::ID_AA64MMFR2_EL1)?;
+
+        let data_ccsidr = self.get_ccsidr_el1_sys_register_values(CacheType::Data)?;
+        let instruction_ccsidr = self.get_ccsidr_el1_sys_register_values(CacheType::Instruction)?;
+
+        Ok(CacheInfo::decode(
+            clidr,
+            ctr,
+            &data_ccsidr,
+            &instruction_ccsidr,
+            (mmfr2 >> 20) & 0xF != 0,
+        ))
+    }
+}
+
+/// Decoded value of the Data Cache Zero ID register (DCZID_EL0).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DczId {
+    /// Size in bytes of the block zeroed by DC ZVA.
+    pub block_size: usize,
+
+    /// DC ZVA is prohibited (DCZID_EL0.DZP).
+    pub prohibited: bool,
+}
+
+impl From<u64> for DczId {
+    fn from(value: u64) -> DczId {
+        DczId {
+            block_size: 4 << (value & 0xF),
+            prohibited: value & (1 << 4) != 0,
+        }
+    }
+}
+
+impl VirtualCpuConfiguration {
+    /// Gets the DC ZVA configuration reported to vCPUs created with this configuration.
+    pub fn get_dczid(&self) -> Result<DczId> {
+        self.get_feature_register(FeatureRegister::DCZID_EL0)
+            .map(DczId::from)
+    }
+}
+
+/// Check if an instruction is DC ZVA, returning its register operand.
+pub fn decode_dc_zva(instruction: u32) -> Option<u8> {
+    // SYS #3, C7, C4, #1, <Xt>
+    if instruction & !0x1F == 0xD50B_7420 {
+        Some((instruction & 0x1F) as u8)
+    } else {
+        None
+    }
+}
+
+/// Emulate a DC ZVA that trapped as a data abort, typically on a MMIO region.
+///
+/// `instruction` is the instruction at the faulting PC, fetched by the caller. If it's a DC ZVA,
+/// `zero` is called with the guest address and size of the block to zero, and the vCPU resumes
+/// after the instruction. Returns `false` if the instruction isn't a DC ZVA.
+///
+/// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+pub fn emulate_dc_zva<F>(
+    vcpu: &mut VirtualCpu,
+    exception: &ExceptionExit,
+    instruction: u32,
+    dczid: DczId,
+    mut zero: F,
+) -> Result<bool>
+where
+    F: FnMut(hv_ipa_t, usize) -> Result<()>,
+{
+    if decode_dc_zva(instruction).is_none() {
+        return Ok(false);
+    }
+
+    let block_address = exception.physical_address & !(dczid.block_size as u64 - 1);
+
+    zero(block_address, dczid.block_size)?;
+
+    let pc = vcpu.get_register(Register::PC)?;
+
+    vcpu.set_register(Register::PC, pc + 4)?;
+
+    Ok(true)
+}
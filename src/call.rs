@@ -0,0 +1,125 @@
+//! Calling guest functions from the host.
+//!
+//! A [GuestCall] sets up the AAPCS64 argument registers, points the link register at a return
+//! trampoline (an `HVC` instruction placed in guest memory) and runs the vCPU until the function
+//! returns to it.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::reg::{GENERAL_PURPOSE_REGISTERS, Register};
+use crate::vcpu::{ExceptionClass, VirtualCpu, VirtualCpuExitReason};
+use crate::virtual_machine::VirtualMachine;
+
+/// Immediate of the HVC instruction used as return trampoline.
+pub const RETURN_TRAMPOLINE_IMMEDIATE: u16 = 0xCA11;
+
+/// Number of argument and result registers (X0 to X7).
+pub const ARGUMENT_REGISTERS: usize = 8;
+
+/// Encode an HVC instruction.
+const fn encode_hvc(immediate: u16) -> u32 {
+    0xD400_0002 | (immediate as u32) << 5
+}
+
+/// Helper calling guest functions.
+#[derive(Copy, Clone, Debug)]
+pub struct GuestCall {
+    /// The guest address of the return trampoline.
+    trampoline: u64,
+}
+
+impl GuestCall {
+    /// Write the return trampoline at a guest address and create a new helper.
+    ///
+    /// The address must be executable by the guest at the EL the functions run at, and identity
+    /// mapped if the guest MMU is enabled.
+    pub fn new(vm: &mut VirtualMachine, trampoline: hv_ipa_t) -> Result<Self> {
+        if !trampoline.is_multiple_of(4) {
+            return Err(HypervisorError::MisalignedAddress);
+        }
+
+        vm.write_memory(
+            trampoline,
+            &encode_hvc(RETURN_TRAMPOLINE_IMMEDIATE).to_le_bytes(),
+        )?;
+        vm.clean_instruction_cache_range(trampoline, 4)?;
+
+        Ok(GuestCall { trampoline })
+    }
+
+    /// Check if an exit is the return of a called function.
+    fn is_return(&self, reason: &VirtualCpuExitReason) -> bool {
+        let VirtualCpuExitReason::Exception { exception } = reason else {
+            return false;
+        };
+
+        exception.class() == ExceptionClass::HvcAArch64
+            && exception.iss() & 0xFFFF == u32::from(RETURN_TRAMPOLINE_IMMEDIATE)
+    }
+
+    /// Call a guest function with up to 8 arguments and return X0 to X7.
+    ///
+    /// The stack pointer and the rest of the guest state must already be set up by the caller.
+    /// The general purpose registers, PC and CPSR are restored once the function returns. Exits
+    /// not handled by [VirtualCpu::after_exit] hooks abort the call with
+    /// [HypervisorError::IllegalGuestState], leaving the vCPU as it exited for inspection.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn call(
+        &self,
+        vcpu: &mut VirtualCpu,
+        function: u64,
+        arguments: &[u64],
+    ) -> Result<[u64; ARGUMENT_REGISTERS]> {
+        if arguments.len() > ARGUMENT_REGISTERS {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let mut saved = [0; 31];
+
+        for (index, register) in GENERAL_PURPOSE_REGISTERS.iter().enumerate() {
+            saved[index] = vcpu.get_register(*register)?;
+        }
+
+        let saved_pc = vcpu.get_register(Register::PC)?;
+        let saved_cpsr = vcpu.get_register(Register::CPSR)?;
+
+        for (register, value) in GENERAL_PURPOSE_REGISTERS.iter().zip(arguments) {
+            vcpu.set_register(*register, *value)?;
+        }
+
+        vcpu.set_register(Register::LR, self.trampoline)?;
+        vcpu.set_register(Register::PC, function)?;
+
+        loop {
+            let reason = vcpu.run()?;
+
+            if self.is_return(&reason) {
+                break;
+            }
+
+            // Virtual timer activations are ignored during the call.
+            if reason != VirtualCpuExitReason::VTimerActivated {
+                return Err(HypervisorError::IllegalGuestState);
+            }
+        }
+
+        let mut result = [0; ARGUMENT_REGISTERS];
+
+        for (index, register) in GENERAL_PURPOSE_REGISTERS[..ARGUMENT_REGISTERS]
+            .iter()
+            .enumerate()
+        {
+            result[index] = vcpu.get_register(*register)?;
+        }
+
+        for (register, value) in GENERAL_PURPOSE_REGISTERS.iter().zip(saved) {
+            vcpu.set_register(*register, value)?;
+        }
+
+        vcpu.set_register(Register::PC, saved_pc)?;
+        vcpu.set_register(Register::CPSR, saved_cpsr)?;
+
+        Ok(result)
+    }
+}
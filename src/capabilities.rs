@@ -0,0 +1,91 @@
+//! Host capability queries.
+//!
+//! These don't need a Virtual Machine, so applications can adapt their configuration (vCPU
+//! count, guest memory layout, nested virtualization) before creating one.
+
+use crate::bindings::hv_vm_get_max_vcpu_count;
+use crate::err::{HypervisorError, Result, convert_hv_return};
+use crate::virtual_machine::VirtualMachineConfiguration;
+
+use core::ffi::{c_char, c_int, c_void};
+
+unsafe extern "C" {
+    fn sysctlbyname(
+        name: *const c_char,
+        oldp: *mut c_void,
+        oldlenp: *mut usize,
+        newp: *mut c_void,
+        newlen: usize,
+    ) -> c_int;
+}
+
+/// Gets the version of the host macOS, as its major and minor numbers.
+pub fn macos_version() -> Result<(u32, u32)> {
+    let mut buffer = [0u8; 32];
+    let mut length = buffer.len();
+
+    let ret = unsafe {
+        sysctlbyname(
+            c"kern.osproductversion".as_ptr(),
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut length,
+            core::ptr::null_mut(),
+            0,
+        )
+    };
+
+    if ret != 0 {
+        return Err(HypervisorError::Error);
+    }
+
+    // The length includes the NUL terminator.
+    let version = core::str::from_utf8(&buffer[..length.saturating_sub(1)])
+        .map_err(|_| HypervisorError::Error)?;
+
+    let mut parts = version.split('.').map(str::parse::<u32>);
+
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        (Some(Ok(major)), None) => Ok((major, 0)),
+        _ => Err(HypervisorError::Error),
+    }
+}
+
+/// Fails with [HypervisorError::Unsupported] if the host macOS is older than `major`.
+///
+/// Hypervisor.framework functions introduced in later releases are missing from older ones, so
+/// this must be checked before calling them.
+pub(crate) fn require_macos(major: u32) -> Result<()> {
+    if macos_version()?.0 < major {
+        return Err(HypervisorError::Unsupported);
+    }
+
+    Ok(())
+}
+
+/// Gets the maximum number of vCPUs of a Virtual Machine.
+pub fn max_vcpu_count() -> Result<u32> {
+    let mut result = 0;
+
+    let ret = unsafe { hv_vm_get_max_vcpu_count(&mut result) };
+
+    // Ensure no error got reported
+    convert_hv_return(ret)?;
+
+    Ok(result)
+}
+
+/// Gets the default guest physical address size in bits.
+pub fn default_ipa_size() -> Result<u32> {
+    VirtualMachineConfiguration::default_ipa_size()
+}
+
+/// Gets the maximum guest physical address size in bits.
+pub fn max_ipa_size() -> Result<u32> {
+    VirtualMachineConfiguration::max_ipa_size()
+}
+
+/// Check if guests can run at EL2 (nested virtualization).
+pub fn el2_supported() -> Result<bool> {
+    VirtualMachineConfiguration::is_el2_supported()
+}
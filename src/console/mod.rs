@@ -0,0 +1,8 @@
+//! Host-side frontends of guest consoles.
+//!
+//! Like device models, frontends don't know about the serial device they are attached to: the
+//! caller feeds them the bytes written by the guest and forwards the input they return to it.
+
+pub mod mux;
+pub mod pty;
+pub mod terminal;
@@ -0,0 +1,235 @@
+//! Console output multiplexing.
+//!
+//! Mirrors the output of a guest console to several sinks at once (host stdout, a log file,
+//! clients of a Unix socket), each optionally prefixing lines with the time elapsed since the
+//! multiplexer was created. Attaching a log file before the guest starts keeps early boot output
+//! that would otherwise be lost.
+
+use crate::err::{HypervisorError, Result};
+
+extern crate alloc;
+use alloc::format;
+use alloc::vec::Vec;
+
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Time given to a socket client to accept a write before being disconnected.
+pub const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Destination of a sink.
+#[derive(Debug)]
+enum SinkTarget {
+    /// The host stdout.
+    Stdout,
+
+    /// A log file.
+    File(File),
+
+    /// A Unix socket, with its connected clients.
+    Socket {
+        /// The listening socket.
+        listener: UnixListener,
+
+        /// The path of the socket, removed on drop.
+        path: PathBuf,
+
+        /// The connected clients.
+        clients: Vec<UnixStream>,
+    },
+}
+
+/// A console output sink.
+#[derive(Debug)]
+struct Sink {
+    /// The destination.
+    target: SinkTarget,
+
+    /// Lines are prefixed with a timestamp.
+    timestamps: bool,
+}
+
+/// Mirrors guest console output to several sinks.
+#[derive(Debug)]
+pub struct ConsoleMux {
+    /// The sinks.
+    sinks: Vec<Sink>,
+
+    /// The reference of timestamps.
+    start: Instant,
+
+    /// The next byte starts a new line.
+    at_line_start: bool,
+}
+
+impl Default for ConsoleMux {
+    fn default() -> Self {
+        ConsoleMux::new()
+    }
+}
+
+impl ConsoleMux {
+    /// Create a new multiplexer without any sink.
+    pub fn new() -> Self {
+        ConsoleMux {
+            sinks: Vec::new(),
+            start: Instant::now(),
+            at_line_start: true,
+        }
+    }
+
+    /// Mirror the output to the host stdout.
+    pub fn add_stdout(&mut self, timestamps: bool) {
+        self.sinks.push(Sink {
+            target: SinkTarget::Stdout,
+            timestamps,
+        });
+    }
+
+    /// Mirror the output to a log file, appending to it if it exists.
+    pub fn add_log_file<P: AsRef<Path>>(&mut self, path: P, timestamps: bool) -> Result<()> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|_| HypervisorError::Denied)?;
+
+        self.sinks.push(Sink {
+            target: SinkTarget::File(file),
+            timestamps,
+        });
+
+        Ok(())
+    }
+
+    /// Mirror the output to the clients of a Unix socket created at a given path.
+    ///
+    /// Clients connecting later only receive the output written after they connected.
+    pub fn add_socket<P: AsRef<Path>>(&mut self, path: P, timestamps: bool) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path).map_err(|_| HypervisorError::Denied)?;
+
+        listener
+            .set_nonblocking(true)
+            .map_err(|_| HypervisorError::Error)?;
+
+        self.sinks.push(Sink {
+            target: SinkTarget::Socket {
+                listener,
+                path,
+                clients: Vec::new(),
+            },
+            timestamps,
+        });
+
+        Ok(())
+    }
+
+    /// Prefix every line of some output with a timestamp.
+    fn timestamped(&self, data: &[u8]) -> Vec<u8> {
+        let elapsed = self.start.elapsed();
+        let stamp = format!("[{:5}.{:06}] ", elapsed.as_secs(), elapsed.subsec_micros());
+
+        let mut result = Vec::with_capacity(data.len() + stamp.len());
+        let mut at_line_start = self.at_line_start;
+
+        for byte in data {
+            if at_line_start {
+                result.extend_from_slice(stamp.as_bytes());
+            }
+
+            result.push(*byte);
+            at_line_start = *byte == b'\n';
+        }
+
+        result
+    }
+
+    /// Write guest console output to every sink.
+    ///
+    /// Every sink is written in full, the first error being returned once all sinks have been
+    /// attempted. Socket clients that don't take the output within [CLIENT_WRITE_TIMEOUT], and
+    /// disconnected ones, are dropped, so the guest is never stalled for long by a sink.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let stamped = self
+            .sinks
+            .iter()
+            .any(|sink| sink.timestamps)
+            .then(|| self.timestamped(data));
+
+        self.at_line_start = data.last() == Some(&b'\n');
+
+        // A failing sink must not deprive the other ones of the output.
+        let mut result = Ok(());
+
+        for sink in self.sinks.iter_mut() {
+            let output = match (&stamped, sink.timestamps) {
+                (Some(stamped), true) => stamped.as_slice(),
+                _ => data,
+            };
+
+            match &mut sink.target {
+                SinkTarget::Stdout => {
+                    let mut stdout = std::io::stdout().lock();
+
+                    if stdout
+                        .write_all(output)
+                        .and_then(|_| stdout.flush())
+                        .is_err()
+                    {
+                        result = result.and(Err(HypervisorError::Error));
+                    }
+                }
+                SinkTarget::File(file) => {
+                    if file.write_all(output).is_err() {
+                        result = result.and(Err(HypervisorError::Error));
+                    }
+                }
+                SinkTarget::Socket {
+                    listener, clients, ..
+                } => {
+                    while let Ok((client, _)) = listener.accept() {
+                        if client.set_nonblocking(false).is_ok()
+                            && client.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)).is_ok()
+                        {
+                            clients.push(client);
+                        }
+                    }
+
+                    // Clients too slow to take the whole output are dropped rather than torn.
+                    clients.retain_mut(|client| client.write_all(output).is_ok());
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Gets the number of connected socket clients.
+    pub fn client_count(&self) -> usize {
+        self.sinks
+            .iter()
+            .map(|sink| match &sink.target {
+                SinkTarget::Socket { clients, .. } => clients.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl Drop for ConsoleMux {
+    fn drop(&mut self) {
+        for sink in self.sinks.iter() {
+            if let SinkTarget::Socket { path, .. } = &sink.target {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
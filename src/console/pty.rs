@@ -0,0 +1,152 @@
+//! Pseudo-terminal console frontend.
+//!
+//! Allocates a host pty whose slave path can be opened by a terminal program (`screen`,
+//! `minicom`, ...) to interact with the guest console.
+
+use crate::err::{HypervisorError, Result};
+
+extern crate alloc;
+use alloc::string::String;
+
+/// Size of the buffer receiving the slave path.
+const PATH_BUFFER_SIZE: usize = 128;
+
+/// A host pseudo-terminal attached to a guest console.
+#[derive(Debug)]
+pub struct Pty {
+    /// The master side, used by the console.
+    master: libc::c_int,
+
+    /// The slave side, kept open so the master doesn't report errors while no client is
+    /// attached.
+    slave: libc::c_int,
+
+    /// The path of the slave side.
+    path: String,
+}
+
+impl Pty {
+    /// Allocate a new pty in raw mode.
+    pub fn open() -> Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                core::ptr::null_mut(),
+                core::ptr::null_mut(),
+                core::ptr::null_mut(),
+            )
+        };
+
+        if ret != 0 {
+            return Err(HypervisorError::NoResources);
+        }
+
+        // Closes both sides on error.
+        let mut pty = Pty {
+            master,
+            slave,
+            path: String::new(),
+        };
+
+        let mut path = [0u8; PATH_BUFFER_SIZE];
+
+        if unsafe { libc::ttyname_r(slave, path.as_mut_ptr() as *mut libc::c_char, path.len()) }
+            != 0
+        {
+            return Err(HypervisorError::Error);
+        }
+
+        let length = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+
+        pty.path = String::from_utf8_lossy(&path[..length]).into_owned();
+
+        // Guest output must reach the client unmodified (no newline translation, no echo).
+        unsafe {
+            let mut attributes: libc::termios = core::mem::zeroed();
+
+            if libc::tcgetattr(slave, &mut attributes) != 0 {
+                return Err(HypervisorError::Error);
+            }
+
+            libc::cfmakeraw(&mut attributes);
+
+            if libc::tcsetattr(slave, libc::TCSANOW, &attributes) != 0 {
+                return Err(HypervisorError::Error);
+            }
+
+            let flags = libc::fcntl(master, libc::F_GETFL);
+
+            if flags < 0 || libc::fcntl(master, libc::F_SETFL, flags | libc::O_NONBLOCK) != 0 {
+                return Err(HypervisorError::Error);
+            }
+        }
+
+        Ok(pty)
+    }
+
+    /// Gets the path of the slave side, to attach a terminal program to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Gets the file descriptor of the master side, to poll it for input.
+    pub fn as_raw_fd(&self) -> libc::c_int {
+        self.master
+    }
+
+    /// Write guest console output to the pty, returning the number of bytes written.
+    ///
+    /// Output that doesn't fit in the pty buffer (while no client reads it) is dropped.
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        let ret = unsafe {
+            libc::write(
+                self.master,
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+            )
+        };
+
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+
+        match std::io::Error::last_os_error().kind() {
+            std::io::ErrorKind::WouldBlock => Ok(0),
+            _ => Err(HypervisorError::Error),
+        }
+    }
+
+    /// Read console input typed in the pty, returning the number of bytes read (0 if none is
+    /// pending).
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        let ret = unsafe {
+            libc::read(
+                self.master,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+
+        match std::io::Error::last_os_error().kind() {
+            std::io::ErrorKind::WouldBlock => Ok(0),
+            _ => Err(HypervisorError::Error),
+        }
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.master);
+            libc::close(self.slave);
+        }
+    }
+}
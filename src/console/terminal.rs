@@ -0,0 +1,215 @@
+//! Raw-terminal interactive console frontend.
+//!
+//! Puts the host terminal in raw mode so every keystroke (including Ctrl-C) is forwarded to the
+//! guest, like QEMU's `-serial stdio`. An escape character (Ctrl-A by default) followed by a
+//! command key is interpreted by the frontend instead:
+//!
+//! - `c`: switch to the monitor console ([ConsoleInput::Monitor]),
+//! - `x`: quit ([ConsoleInput::Quit]),
+//! - the escape character again: send it to the guest.
+//!
+//! Any other key after the escape character is ignored.
+
+use crate::err::{HypervisorError, Result};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Default escape character (Ctrl-A).
+pub const DEFAULT_ESCAPE_CHARACTER: u8 = 0x01;
+
+/// Size of the buffer receiving keystrokes.
+const READ_BUFFER_SIZE: usize = 256;
+
+/// Input received from the host terminal.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConsoleInput {
+    /// Bytes to forward to the guest console.
+    Data(Vec<u8>),
+
+    /// The user asked for the monitor console.
+    Monitor,
+
+    /// The user asked to quit.
+    Quit,
+}
+
+/// Decoder of escape sequences in keystrokes.
+///
+/// Can also be used on input coming from another frontend (a pty for example).
+#[derive(Copy, Clone, Debug)]
+pub struct EscapeDecoder {
+    /// The escape character.
+    escape_character: u8,
+
+    /// The escape character was the last key received.
+    escape_pending: bool,
+}
+
+impl Default for EscapeDecoder {
+    fn default() -> Self {
+        EscapeDecoder::new(DEFAULT_ESCAPE_CHARACTER)
+    }
+}
+
+impl EscapeDecoder {
+    /// Create a new decoder with a given escape character.
+    pub fn new(escape_character: u8) -> Self {
+        EscapeDecoder {
+            escape_character,
+            escape_pending: false,
+        }
+    }
+
+    /// Decode keystrokes, interpreting escape sequences.
+    ///
+    /// An escape character at the end of the keystrokes applies to the next call.
+    pub fn decode(&mut self, keys: &[u8]) -> Vec<ConsoleInput> {
+        let mut result = Vec::new();
+        let mut data = Vec::new();
+
+        for key in keys.iter().copied() {
+            if !self.escape_pending {
+                if key == self.escape_character {
+                    self.escape_pending = true;
+                } else {
+                    data.push(key);
+                }
+
+                continue;
+            }
+
+            self.escape_pending = false;
+
+            let command = match key {
+                b'c' => ConsoleInput::Monitor,
+                b'x' => ConsoleInput::Quit,
+                _ => {
+                    if key == self.escape_character {
+                        data.push(key);
+                    }
+
+                    continue;
+                }
+            };
+
+            if !data.is_empty() {
+                result.push(ConsoleInput::Data(core::mem::take(&mut data)));
+            }
+
+            result.push(command);
+        }
+
+        if !data.is_empty() {
+            result.push(ConsoleInput::Data(data));
+        }
+
+        result
+    }
+}
+
+/// The host terminal in raw mode, restored when dropped.
+pub struct RawTerminal {
+    /// The terminal attributes to restore.
+    original: libc::termios,
+
+    /// The escape sequence decoder.
+    decoder: EscapeDecoder,
+}
+
+impl RawTerminal {
+    /// Put the host terminal (stdin) in raw mode.
+    ///
+    /// Fails with [HypervisorError::NoDevice] if stdin isn't a terminal.
+    pub fn enter() -> Result<Self> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return Err(HypervisorError::NoDevice);
+        }
+
+        let mut original: libc::termios = unsafe { core::mem::zeroed() };
+
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(HypervisorError::Error);
+        }
+
+        let terminal = RawTerminal {
+            original,
+            decoder: EscapeDecoder::default(),
+        };
+
+        terminal.resume()?;
+
+        Ok(terminal)
+    }
+
+    /// Set the escape character.
+    pub fn set_escape_character(&mut self, value: u8) {
+        self.decoder = EscapeDecoder::new(value);
+    }
+
+    /// Restore the original terminal mode, while the monitor console is in use for example.
+    pub fn suspend(&self) -> Result<()> {
+        match unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original) } {
+            0 => Ok(()),
+            _ => Err(HypervisorError::Error),
+        }
+    }
+
+    /// Put the terminal back in raw mode after [RawTerminal::suspend].
+    pub fn resume(&self) -> Result<()> {
+        let mut attributes = self.original;
+
+        unsafe { libc::cfmakeraw(&mut attributes) };
+
+        // Keep output processing so guest line endings render as usual.
+        attributes.c_oflag |= libc::OPOST;
+
+        match unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &attributes) } {
+            0 => Ok(()),
+            _ => Err(HypervisorError::Error),
+        }
+    }
+
+    /// Wait up to `timeout_ms` milliseconds (-1 to wait forever) for keystrokes, returning the
+    /// decoded input.
+    pub fn poll(&mut self, timeout_ms: i32) -> Result<Vec<ConsoleInput>> {
+        let mut descriptor = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        match unsafe { libc::poll(&mut descriptor, 1, timeout_ms) } {
+            0 => return Ok(Vec::new()),
+            ret if ret < 0 => {
+                return match std::io::Error::last_os_error().kind() {
+                    std::io::ErrorKind::Interrupted => Ok(Vec::new()),
+                    _ => Err(HypervisorError::Error),
+                };
+            }
+            _ => {}
+        }
+
+        let mut buffer = [0u8; READ_BUFFER_SIZE];
+        let ret = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+
+        match ret {
+            // The terminal was closed.
+            0 => Ok(Vec::from([ConsoleInput::Quit])),
+            ret if ret < 0 => Err(HypervisorError::Error),
+            ret => Ok(self.decoder.decode(&buffer[..ret as usize])),
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
@@ -0,0 +1,182 @@
+//! Full vCPU context save and restore.
+//!
+//! A [VcpuContext] holds everything needed to put a vCPU back in a given state: general purpose,
+//! SIMD and floating-point, and system registers, along with the virtual timer state, the GIC CPU
+//! interface registers and the pending interrupts. This is the building block for snapshots,
+//! fuzzing resets and migration.
+
+use crate::err::Result;
+use crate::gic::GicIccRegister;
+use crate::reg::{
+    EL2_SYSTEM_REGISTERS, GeneralRegisters, Register, SIMD_FP_REGISTERS, SYSTEM_REGISTERS,
+    SimdFpValue, SystemRegister,
+};
+use crate::vcpu::{InterruptType, VirtualCpu};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// GIC CPU interface registers saved in a context, in restore order: the interface is configured
+/// before its priorities, and the interrupt groups are enabled last. ICC_RPR_EL1 is read-only and
+/// derived from the active priorities.
+const GIC_ICC_REGISTERS: [GicIccRegister; 9] = [
+    GicIccRegister::SRE_EL1,
+    GicIccRegister::CTLR_EL1,
+    GicIccRegister::PMR_EL1,
+    GicIccRegister::BPR0_EL1,
+    GicIccRegister::BPR1_EL1,
+    GicIccRegister::AP0R0_EL1,
+    GicIccRegister::AP1R0_EL1,
+    GicIccRegister::IGRPEN0_EL1,
+    GicIccRegister::IGRPEN1_EL1,
+];
+
+/// Saved state of a vCPU.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VcpuContext {
+    /// The general purpose registers, PC and CPSR.
+    pub general: GeneralRegisters,
+
+    /// The FPCR register.
+    pub fpcr: u64,
+
+    /// The FPSR register.
+    pub fpsr: u64,
+
+    /// The SIMD and floating-point registers Q0 to Q31.
+    pub simd_fp: [SimdFpValue; 32],
+
+    /// The system registers and their values, including the EL2 ones when EL2 is enabled.
+    pub system: Vec<(SystemRegister, u64)>,
+
+    /// Whether the virtual timer is masked.
+    pub vtimer_mask: bool,
+
+    /// The virtual timer offset, absent with EL2 enabled (part of the system registers as
+    /// CNTVOFF_EL2 then).
+    pub vtimer_offset: Option<u64>,
+
+    /// The GIC CPU interface registers and their values, empty without a GIC. ICC_SRE_EL2 is
+    /// included when EL2 is enabled.
+    pub gic_icc: Vec<(GicIccRegister, u64)>,
+
+    /// Whether an IRQ is pending.
+    pub pending_irq: bool,
+
+    /// Whether a FIQ is pending.
+    pub pending_fiq: bool,
+}
+
+impl VcpuContext {
+    /// Gets the saved value of a system register, if part of the context.
+    pub fn system_register(&self, register: SystemRegister) -> Option<u64> {
+        self.system
+            .iter()
+            .find(|(saved, _)| *saved == register)
+            .map(|(_, value)| *value)
+    }
+}
+
+impl VirtualCpu {
+    /// Save the full state of the vCPU.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn save_context(&mut self) -> Result<VcpuContext> {
+        let general = self.get_general_registers()?;
+        let fpcr = self.get_register(Register::FPCR)?;
+        let fpsr = self.get_register(Register::FPSR)?;
+
+        let mut simd_fp = [SimdFpValue::default(); 32];
+
+        for (value, register) in simd_fp.iter_mut().zip(SIMD_FP_REGISTERS) {
+            *value = self.get_simd_fp_register(register)?;
+        }
+
+        // EL2 registers can only be accessed with EL2 enabled.
+        let is_el2_enabled = self.is_el2_enabled();
+
+        let mut system = Vec::with_capacity(SYSTEM_REGISTERS.len() + EL2_SYSTEM_REGISTERS.len());
+
+        for register in SYSTEM_REGISTERS {
+            system.push((register, self.get_system_register(register)?));
+        }
+
+        if is_el2_enabled {
+            for register in EL2_SYSTEM_REGISTERS {
+                system.push((register, self.get_system_register(register)?));
+            }
+        }
+
+        let vtimer_mask = self.get_vtimer_mask()?;
+        let vtimer_offset = if is_el2_enabled {
+            None
+        } else {
+            Some(self.get_vtimer_offset()?)
+        };
+
+        let mut gic_icc = Vec::new();
+
+        if self.has_gic() {
+            if is_el2_enabled {
+                let value = self.get_gic_icc_register(GicIccRegister::SRE_EL2)?;
+
+                gic_icc.push((GicIccRegister::SRE_EL2, value));
+            }
+
+            for register in GIC_ICC_REGISTERS {
+                gic_icc.push((register, self.get_gic_icc_register(register)?));
+            }
+        }
+
+        let pending_irq = self.get_pending_interrupt(InterruptType::IRQ)?;
+        let pending_fiq = self.get_pending_interrupt(InterruptType::FIQ)?;
+
+        Ok(VcpuContext {
+            general,
+            fpcr,
+            fpsr,
+            simd_fp,
+            system,
+            vtimer_mask,
+            vtimer_offset,
+            gic_icc,
+            pending_irq,
+            pending_fiq,
+        })
+    }
+
+    /// Restore a state saved by [VirtualCpu::save_context].
+    ///
+    /// System registers are restored first, so that the general purpose registers and PSTATE
+    /// are set in the final translation regime. The GIC CPU interface registers follow, and the
+    /// pending interrupts are set again for the next run.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn restore_context(&mut self, context: &VcpuContext) -> Result<()> {
+        for (register, value) in context.system.iter() {
+            self.set_system_register(*register, *value)?;
+        }
+
+        if let Some(offset) = context.vtimer_offset {
+            self.set_vtimer_offset(offset)?;
+        }
+
+        self.set_vtimer_mask(context.vtimer_mask)?;
+
+        for (register, value) in context.gic_icc.iter() {
+            self.set_gic_icc_register(*register, *value)?;
+        }
+
+        self.set_pending_interrupt(InterruptType::IRQ, context.pending_irq)?;
+        self.set_pending_interrupt(InterruptType::FIQ, context.pending_fiq)?;
+
+        for (value, register) in context.simd_fp.iter().zip(SIMD_FP_REGISTERS) {
+            self.set_simd_fp_register(register, *value)?;
+        }
+
+        self.set_register(Register::FPCR, context.fpcr)?;
+        self.set_register(Register::FPSR, context.fpsr)?;
+
+        self.set_general_registers(&context.general)
+    }
+}
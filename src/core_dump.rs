@@ -0,0 +1,213 @@
+//! ELF core dumps of guest state.
+//!
+//! A core file holds one PT_LOAD segment per guest memory mapping and one NT_PRSTATUS note per
+//! vCPU, so that a crashed guest can be inspected post-mortem with gdb or lldb. Guest memory is
+//! dumped by physical address: segments have both their virtual and physical addresses set to
+//! the guest physical address, which matches the guest view only for identity-mapped or MMU-off
+//! guests.
+
+use crate::bindings::{HV_MEMORY_EXEC, HV_MEMORY_READ, HV_MEMORY_WRITE, hv_memory_flags_t};
+use crate::diff::RegisterState;
+use crate::err::{HypervisorError, Result};
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// ET_CORE file type.
+const ET_CORE: u16 = 4;
+
+/// EM_AARCH64 machine.
+const EM_AARCH64: u16 = 183;
+
+/// PT_LOAD segment type.
+const PT_LOAD: u32 = 1;
+
+/// PT_NOTE segment type.
+const PT_NOTE: u32 = 4;
+
+/// Executable segment flag.
+const PF_X: u32 = 1;
+
+/// Writable segment flag.
+const PF_W: u32 = 2;
+
+/// Readable segment flag.
+const PF_R: u32 = 4;
+
+/// NT_PRSTATUS note type.
+const NT_PRSTATUS: u32 = 1;
+
+/// Size of the ELF header.
+const EHDR_SIZE: usize = 64;
+
+/// Size of a program header.
+const PHDR_SIZE: usize = 56;
+
+/// Size of the AArch64 `elf_prstatus` structure.
+const PRSTATUS_SIZE: usize = 392;
+
+/// Offset of `pr_pid` in `elf_prstatus`.
+const PRSTATUS_PID_OFFSET: usize = 32;
+
+/// Offset of `pr_reg` in `elf_prstatus`.
+const PRSTATUS_REG_OFFSET: usize = 112;
+
+/// Name of the notes.
+const NOTE_NAME: &[u8] = b"CORE\0\0\0\0";
+
+/// Append a program header.
+fn push_phdr(
+    output: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    address: u64,
+    size: u64,
+    align: u64,
+) {
+    output.extend_from_slice(&kind.to_le_bytes());
+    output.extend_from_slice(&flags.to_le_bytes());
+    output.extend_from_slice(&offset.to_le_bytes());
+    output.extend_from_slice(&address.to_le_bytes());
+    output.extend_from_slice(&address.to_le_bytes());
+    output.extend_from_slice(&size.to_le_bytes());
+    output.extend_from_slice(&size.to_le_bytes());
+    output.extend_from_slice(&align.to_le_bytes());
+}
+
+/// Build the NT_PRSTATUS note of a vCPU.
+fn prstatus_note(index: usize, state: &RegisterState) -> Vec<u8> {
+    let mut descriptor = [0u8; PRSTATUS_SIZE];
+
+    // gdb identifies threads by pid, which must be non-zero.
+    descriptor[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4]
+        .copy_from_slice(&(index as u32 + 1).to_le_bytes());
+
+    // The current stack pointer is SP_EL1 when running at EL1 with SPSel set (EL1h).
+    let general = &state.general;
+    let sp = if general.cpsr & 0b1101 == 0b0101 {
+        state.sp_el1
+    } else {
+        state.sp_el0
+    };
+
+    let registers = general.x.into_iter().chain([sp, general.pc, general.cpsr]);
+
+    for (slot, value) in descriptor[PRSTATUS_REG_OFFSET..]
+        .chunks_exact_mut(8)
+        .zip(registers)
+    {
+        slot.copy_from_slice(&value.to_le_bytes());
+    }
+
+    let mut note = Vec::with_capacity(12 + NOTE_NAME.len() + PRSTATUS_SIZE);
+
+    note.extend_from_slice(&5u32.to_le_bytes());
+    note.extend_from_slice(&(PRSTATUS_SIZE as u32).to_le_bytes());
+    note.extend_from_slice(&NT_PRSTATUS.to_le_bytes());
+    note.extend_from_slice(NOTE_NAME);
+    note.extend_from_slice(&descriptor);
+
+    note
+}
+
+impl VirtualMachine {
+    /// Build an AArch64 ELF core file from the guest memory and the register states of the
+    /// vCPUs (see [RegisterState::capture]).
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn core_dump(&self, vcpu_states: &[RegisterState]) -> Result<Vec<u8>> {
+        let mappings = self.get_all_mapping_infos();
+
+        let notes: Vec<u8> = vcpu_states
+            .iter()
+            .enumerate()
+            .flat_map(|(index, state)| prstatus_note(index, state))
+            .collect();
+
+        let phnum = mappings.len() + 1;
+        let notes_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+        let mut data_offset = notes_offset + notes.len();
+
+        let mut output = Vec::with_capacity(data_offset);
+
+        // ELF header.
+        output.extend_from_slice(b"\x7fELF");
+        output.extend_from_slice(&[2, 1, 1, 0]);
+        output.extend_from_slice(&[0; 8]);
+        output.extend_from_slice(&ET_CORE.to_le_bytes());
+        output.extend_from_slice(&EM_AARCH64.to_le_bytes());
+        output.extend_from_slice(&1u32.to_le_bytes());
+        output.extend_from_slice(&0u64.to_le_bytes());
+        output.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        output.extend_from_slice(&0u64.to_le_bytes());
+        output.extend_from_slice(&0u32.to_le_bytes());
+        output.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        output.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        output.extend_from_slice(&(phnum as u16).to_le_bytes());
+        output.extend_from_slice(&[0; 6]);
+
+        push_phdr(
+            &mut output,
+            PT_NOTE,
+            0,
+            notes_offset as u64,
+            0,
+            notes.len() as u64,
+            4,
+        );
+
+        for mapping in mappings.iter() {
+            let permission = hv_memory_flags_t::from(mapping.permission);
+            let mut flags = 0;
+
+            if permission & HV_MEMORY_READ as u64 != 0 {
+                flags |= PF_R;
+            }
+
+            if permission & HV_MEMORY_WRITE as u64 != 0 {
+                flags |= PF_W;
+            }
+
+            if permission & HV_MEMORY_EXEC as u64 != 0 {
+                flags |= PF_X;
+            }
+
+            push_phdr(
+                &mut output,
+                PT_LOAD,
+                flags,
+                data_offset as u64,
+                mapping.address,
+                mapping.size as u64,
+                1,
+            );
+
+            data_offset += mapping.size;
+        }
+
+        output.extend_from_slice(&notes);
+
+        for mapping in mappings.iter() {
+            let contents = self.get_allocation_slice(mapping.allocation_handle)?;
+
+            output.extend_from_slice(&contents[..mapping.size]);
+        }
+
+        Ok(output)
+    }
+
+    /// Write an AArch64 ELF core file to a path (see [VirtualMachine::core_dump]).
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn write_core<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        vcpu_states: &[RegisterState],
+    ) -> Result<()> {
+        let core = self.core_dump(vcpu_states)?;
+
+        std::fs::write(path, core).map_err(|_| HypervisorError::Denied)
+    }
+}
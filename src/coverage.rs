@@ -0,0 +1,198 @@
+//! AFL-compatible coverage bitmap.
+//!
+//! Guest PCs recorded by the harness (breakpoints, single-stepping, exits) are hashed into an
+//! edge bitmap following the AFL scheme: every edge between two consecutive blocks increments a
+//! byte of the map. The map can live in the SysV shared memory segment provided by `afl-fuzz`
+//! through the `__AFL_SHM_ID` environment variable.
+
+use crate::err::{HypervisorError, Result};
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Default size of an AFL coverage map.
+pub const DEFAULT_MAP_SIZE: usize = 1 << 16;
+
+/// Environment variable holding the shared memory identifier.
+const SHM_ENV_VAR: &core::ffi::CStr = c"__AFL_SHM_ID";
+
+/// Environment variable holding the map size, when different from the default.
+const MAP_SIZE_ENV_VAR: &core::ffi::CStr = c"AFL_MAP_SIZE";
+
+/// Backing storage of a coverage map.
+#[derive(Debug)]
+enum CoverageStorage {
+    /// Memory owned by the map.
+    Owned(Vec<u8>),
+
+    /// An attached SysV shared memory segment.
+    Shared {
+        /// Base address of the segment.
+        address: *mut u8,
+
+        /// Size of the map in the segment.
+        size: usize,
+    },
+}
+
+/// An AFL-compatible edge coverage map.
+#[derive(Debug)]
+pub struct CoverageMap {
+    /// Backing storage of the map.
+    storage: CoverageStorage,
+
+    /// Hash of the previous block.
+    previous: usize,
+}
+
+/// Read an environment variable as an integer.
+fn env_integer(name: &core::ffi::CStr) -> Option<usize> {
+    let value = unsafe { libc::getenv(name.as_ptr()) };
+
+    if value.is_null() {
+        return None;
+    }
+
+    unsafe { core::ffi::CStr::from_ptr(value) }
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+impl CoverageMap {
+    /// Create a new map in private memory.
+    pub fn new(size: usize) -> Self {
+        CoverageMap {
+            storage: CoverageStorage::Owned(vec![0; size]),
+            previous: 0,
+        }
+    }
+
+    /// Attach to a SysV shared memory segment.
+    ///
+    /// Fails with [HypervisorError::BadArgument] if `size` is zero or exceeds the segment size.
+    pub fn attach(shm_id: i32, size: usize) -> Result<Self> {
+        let mut info: libc::shmid_ds = unsafe { core::mem::zeroed() };
+
+        if unsafe { libc::shmctl(shm_id, libc::IPC_STAT, &mut info) } != 0 {
+            return Err(HypervisorError::Denied);
+        }
+
+        if size == 0 || size > info.shm_segsz as usize {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let address = unsafe { libc::shmat(shm_id, core::ptr::null(), 0) };
+
+        if address as isize == -1 {
+            return Err(HypervisorError::Denied);
+        }
+
+        Ok(CoverageMap {
+            storage: CoverageStorage::Shared {
+                address: address as *mut u8,
+                size,
+            },
+            previous: 0,
+        })
+    }
+
+    /// Attach to the shared memory segment set up by `afl-fuzz`, if any.
+    pub fn attach_from_env() -> Result<Option<Self>> {
+        let Some(shm_id) = env_integer(SHM_ENV_VAR) else {
+            return Ok(None);
+        };
+
+        let size = env_integer(MAP_SIZE_ENV_VAR).unwrap_or(DEFAULT_MAP_SIZE);
+
+        CoverageMap::attach(shm_id as i32, size).map(Some)
+    }
+
+    /// Gets the map.
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.storage {
+            CoverageStorage::Owned(map) => map,
+            CoverageStorage::Shared { address, size } => unsafe {
+                core::slice::from_raw_parts(*address, *size)
+            },
+        }
+    }
+
+    /// Gets the map mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        match &mut self.storage {
+            CoverageStorage::Owned(map) => map,
+            CoverageStorage::Shared { address, size } => unsafe {
+                core::slice::from_raw_parts_mut(*address, *size)
+            },
+        }
+    }
+
+    /// Hash a PC into a map index.
+    fn hash(&self, pc: u64) -> usize {
+        ((pc >> 4) ^ (pc << 8)) as usize % self.as_slice().len()
+    }
+
+    /// Increment a map byte, never wrapping to zero.
+    fn hit(&mut self, index: usize) {
+        let entry = &mut self.as_mut_slice()[index];
+
+        *entry = entry.checked_add(1).unwrap_or(1);
+    }
+
+    /// Record the execution of a block starting at `pc`, as an edge from the previous block.
+    pub fn record_edge(&mut self, pc: u64) {
+        if self.as_slice().is_empty() {
+            return;
+        }
+
+        let current = self.hash(pc);
+        // With a map size that isn't a power of two (AFL_MAP_SIZE), the XOR can exceed it.
+        let index = (current ^ self.previous) % self.as_slice().len();
+
+        self.hit(index);
+        self.previous = current >> 1;
+    }
+
+    /// Record the execution of a block starting at `pc`, without edge information.
+    pub fn record_block(&mut self, pc: u64) {
+        if self.as_slice().is_empty() {
+            return;
+        }
+
+        let index = self.hash(pc);
+
+        self.hit(index);
+    }
+
+    /// Record all PCs of a run as edges, in execution order.
+    pub fn record_edges(&mut self, pcs: &[u64]) {
+        for pc in pcs {
+            self.record_edge(*pc);
+        }
+    }
+
+    /// Clear the map and the edge state before a new run.
+    pub fn reset(&mut self) {
+        self.as_mut_slice().fill(0);
+        self.previous = 0;
+    }
+
+    /// Gets the number of map entries hit.
+    pub fn count_hits(&self) -> usize {
+        self.as_slice().iter().filter(|value| **value != 0).count()
+    }
+}
+
+impl Drop for CoverageMap {
+    fn drop(&mut self) {
+        if let CoverageStorage::Shared { address, .. } = self.storage {
+            unsafe {
+                libc::shmdt(address as *const libc::c_void);
+            }
+        }
+    }
+}
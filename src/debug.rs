@@ -0,0 +1,866 @@
+//! Guest debug exceptions.
+//!
+//! With [VirtualCpu::set_trap_debug_exceptions] enabled, guest breakpoints, watchpoints, software
+//! steps and BRK instructions exit to the host. This module decodes them from the exception
+//! syndrome, emulates the guest view of the debug registers and manages software breakpoints,
+//! which can be made conditional on register values with [RegisterCondition].
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::reg::{GENERAL_PURPOSE_REGISTERS, Register, SystemRegister};
+use crate::vcpu::{ExceptionClass, ExceptionExit, VirtualCpu};
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// A decoded debug exception.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DebugException {
+    /// A hardware breakpoint matched.
+    Breakpoint {
+        /// The guest virtual address of the instruction.
+        address: u64,
+    },
+
+    /// A hardware watchpoint matched.
+    Watchpoint {
+        /// The guest virtual address accessed.
+        address: u64,
+
+        /// The watchpoint slot, when reported by the hardware (FEAT_Debugv8p9).
+        slot: Option<u8>,
+
+        /// The access was a write.
+        is_write: bool,
+    },
+
+    /// A software step completed.
+    SoftwareStep {
+        /// The guest virtual address of the next instruction.
+        address: u64,
+
+        /// The stepped instruction was a load-exclusive, when reported.
+        is_load_exclusive: Option<bool>,
+    },
+
+    /// A BRK instruction was executed.
+    Brk {
+        /// The guest virtual address of the instruction.
+        address: u64,
+
+        /// The BRK immediate.
+        immediate: u16,
+    },
+}
+
+impl DebugException {
+    /// Decode a debug exception, `pc` being the PC of the vCPU at the exit.
+    ///
+    /// Returns `None` if the exception isn't a debug exception.
+    pub fn decode(exception: &ExceptionExit, pc: u64) -> Option<Self> {
+        let iss = exception.iss();
+
+        let result = match exception.class() {
+            ExceptionClass::Breakpoint | ExceptionClass::Other(0x31) => {
+                DebugException::Breakpoint { address: pc }
+            }
+            ExceptionClass::SoftwareStep | ExceptionClass::Other(0x33) => {
+                DebugException::SoftwareStep {
+                    address: pc,
+                    is_load_exclusive: (iss & (1 << 24) != 0).then_some(iss & (1 << 6) != 0),
+                }
+            }
+            ExceptionClass::Watchpoint | ExceptionClass::Other(0x35) => {
+                DebugException::Watchpoint {
+                    address: exception.virtual_address,
+                    slot: (iss & (1 << 17) != 0).then_some(((iss >> 18) & 0x3F) as u8),
+                    is_write: iss & (1 << 6) != 0,
+                }
+            }
+            ExceptionClass::Brk => DebugException::Brk {
+                address: pc,
+                immediate: (iss & 0xFFFF) as u16,
+            },
+            _ => return None,
+        };
+
+        Some(result)
+    }
+}
+
+impl VirtualCpu {
+    /// Decode the debug exception that caused an exit, if any.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn get_debug_exception(
+        &mut self,
+        exception: &ExceptionExit,
+    ) -> Result<Option<DebugException>> {
+        let pc = self.get_register(Register::PC)?;
+
+        Ok(DebugException::decode(exception, pc))
+    }
+}
+
+/// Number of breakpoint and watchpoint register pairs architecturally available.
+pub const DEBUG_REGISTER_PAIRS: usize = 16;
+
+/// Hardware debug registers of each pair: DBGBVR, DBGBCR, DBGWVR and DBGWCR.
+const DEBUG_REGISTERS: [[SystemRegister; 4]; DEBUG_REGISTER_PAIRS] = [
+    [
+        SystemRegister::DBGBVR0_EL1,
+        SystemRegister::DBGBCR0_EL1,
+        SystemRegister::DBGWVR0_EL1,
+        SystemRegister::DBGWCR0_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR1_EL1,
+        SystemRegister::DBGBCR1_EL1,
+        SystemRegister::DBGWVR1_EL1,
+        SystemRegister::DBGWCR1_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR2_EL1,
+        SystemRegister::DBGBCR2_EL1,
+        SystemRegister::DBGWVR2_EL1,
+        SystemRegister::DBGWCR2_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR3_EL1,
+        SystemRegister::DBGBCR3_EL1,
+        SystemRegister::DBGWVR3_EL1,
+        SystemRegister::DBGWCR3_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR4_EL1,
+        SystemRegister::DBGBCR4_EL1,
+        SystemRegister::DBGWVR4_EL1,
+        SystemRegister::DBGWCR4_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR5_EL1,
+        SystemRegister::DBGBCR5_EL1,
+        SystemRegister::DBGWVR5_EL1,
+        SystemRegister::DBGWCR5_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR6_EL1,
+        SystemRegister::DBGBCR6_EL1,
+        SystemRegister::DBGWVR6_EL1,
+        SystemRegister::DBGWCR6_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR7_EL1,
+        SystemRegister::DBGBCR7_EL1,
+        SystemRegister::DBGWVR7_EL1,
+        SystemRegister::DBGWCR7_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR8_EL1,
+        SystemRegister::DBGBCR8_EL1,
+        SystemRegister::DBGWVR8_EL1,
+        SystemRegister::DBGWCR8_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR9_EL1,
+        SystemRegister::DBGBCR9_EL1,
+        SystemRegister::DBGWVR9_EL1,
+        SystemRegister::DBGWCR9_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR10_EL1,
+        SystemRegister::DBGBCR10_EL1,
+        SystemRegister::DBGWVR10_EL1,
+        SystemRegister::DBGWCR10_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR11_EL1,
+        SystemRegister::DBGBCR11_EL1,
+        SystemRegister::DBGWVR11_EL1,
+        SystemRegister::DBGWCR11_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR12_EL1,
+        SystemRegister::DBGBCR12_EL1,
+        SystemRegister::DBGWVR12_EL1,
+        SystemRegister::DBGWCR12_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR13_EL1,
+        SystemRegister::DBGBCR13_EL1,
+        SystemRegister::DBGWVR13_EL1,
+        SystemRegister::DBGWCR13_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR14_EL1,
+        SystemRegister::DBGBCR14_EL1,
+        SystemRegister::DBGWVR14_EL1,
+        SystemRegister::DBGWCR14_EL1,
+    ],
+    [
+        SystemRegister::DBGBVR15_EL1,
+        SystemRegister::DBGBCR15_EL1,
+        SystemRegister::DBGWVR15_EL1,
+        SystemRegister::DBGWCR15_EL1,
+    ],
+];
+
+/// Breakpoint and watchpoint control enable bit.
+const CONTROL_ENABLE: u64 = 1 << 0;
+
+/// MDSCR_EL1 bits enabling breakpoints and watchpoints (MDE and KDE), merged from the guest.
+const MDSCR_DEBUG_ENABLES: u64 = (1 << 15) | (1 << 13);
+
+/// CPSR of a debug exception injected at EL1: EL1h with all exceptions masked.
+const INJECTED_CPSR: u64 = 0x3C5;
+
+/// Check if a watchpoint, given its value and control registers, matches an address.
+fn watchpoint_matches(value: u64, control: u64, address: u64) -> bool {
+    // MASK watches a power of two range, otherwise the byte address select covers a double word.
+    let mask = (control >> 24) & 0x1F;
+    let size = if mask != 0 { 1 << mask } else { 8 };
+
+    address & !(size - 1) == value & !(size - 1)
+}
+
+/// A trapped guest access to a debug system register.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct DebugRegisterAccess {
+    /// The register encoding (CRn, CRm, op2).
+    encoding: (u8, u8, u8),
+
+    /// The general purpose register index (31 being XZR).
+    rt: usize,
+
+    /// The access is a read (MRS).
+    is_read: bool,
+}
+
+impl DebugRegisterAccess {
+    /// Decode a trapped MSR/MRS to a debug register (op0 = 2, op1 = 0).
+    fn decode(exception: &ExceptionExit) -> Option<Self> {
+        let iss = exception.iss();
+
+        let op0 = (iss >> 20) & 0x3;
+        let op1 = (iss >> 14) & 0x7;
+
+        if exception.class() != ExceptionClass::MsrTrap || op0 != 2 || op1 != 0 {
+            return None;
+        }
+
+        Some(DebugRegisterAccess {
+            encoding: (
+                ((iss >> 10) & 0xF) as u8,
+                ((iss >> 1) & 0xF) as u8,
+                ((iss >> 17) & 0x7) as u8,
+            ),
+            rt: ((iss >> 5) & 0x1F) as usize,
+            is_read: iss & 1 != 0,
+        })
+    }
+}
+
+/// Guest view of the debug registers, stored by the host.
+///
+/// With [VirtualCpu::set_trap_debug_reg_accesses] enabled, guest accesses to the debug registers
+/// are emulated against this shadow state, leaving the real registers to host-side debugging.
+/// Accesses to debug registers without a dedicated field (OS lock, DBGCLAIM...) read as zero and
+/// ignore writes.
+///
+/// The slots used by the host are marked in [DebugRegisterShadow::host_breakpoints] and
+/// [DebugRegisterShadow::host_watchpoints]. Before each run, [DebugRegisterShadow::apply] loads
+/// the guest breakpoints and watchpoints in the other slots, and with
+/// [VirtualCpu::set_trap_debug_exceptions] enabled, [DebugRegisterShadow::handle_debug_exception]
+/// injects the exceptions they cause back into the guest.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugRegisterShadow {
+    /// DBGBVR<n>_EL1.
+    pub breakpoint_values: [u64; DEBUG_REGISTER_PAIRS],
+
+    /// DBGBCR<n>_EL1.
+    pub breakpoint_controls: [u64; DEBUG_REGISTER_PAIRS],
+
+    /// DBGWVR<n>_EL1.
+    pub watchpoint_values: [u64; DEBUG_REGISTER_PAIRS],
+
+    /// DBGWCR<n>_EL1.
+    pub watchpoint_controls: [u64; DEBUG_REGISTER_PAIRS],
+
+    /// MDSCR_EL1.
+    pub mdscr: u64,
+
+    /// Bitmap of the breakpoint slots used by the host.
+    pub host_breakpoints: u16,
+
+    /// Bitmap of the watchpoint slots used by the host.
+    pub host_watchpoints: u16,
+
+    /// Bitmap of the breakpoint slots loaded with the guest registers.
+    applied_breakpoints: u16,
+
+    /// Bitmap of the watchpoint slots loaded with the guest registers.
+    applied_watchpoints: u16,
+
+    /// MDSCR_EL1 bits set from the guest.
+    applied_mdscr: u64,
+}
+
+impl DebugRegisterShadow {
+    /// Create a new shadow with all registers cleared.
+    pub fn new() -> Self {
+        DebugRegisterShadow::default()
+    }
+
+    /// Gets the shadow storage of a register encoding, if any.
+    fn register_mut(&mut self, encoding: (u8, u8, u8)) -> Option<&mut u64> {
+        let (crn, crm, op2) = encoding;
+        let index = crm as usize;
+
+        match (crn, op2) {
+            (0, 4) => Some(&mut self.breakpoint_values[index]),
+            (0, 5) => Some(&mut self.breakpoint_controls[index]),
+            (0, 6) => Some(&mut self.watchpoint_values[index]),
+            (0, 7) => Some(&mut self.watchpoint_controls[index]),
+            (0, 2) if crm == 2 => Some(&mut self.mdscr),
+            _ => None,
+        }
+    }
+
+    /// Emulate a trapped guest access to a debug register.
+    ///
+    /// Returns `false` if the exception isn't a debug register access, otherwise the access is
+    /// emulated and the vCPU resumes after the instruction.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn handle_exit(
+        &mut self,
+        vcpu: &mut VirtualCpu,
+        exception: &ExceptionExit,
+    ) -> Result<bool> {
+        let Some(access) = DebugRegisterAccess::decode(exception) else {
+            return Ok(false);
+        };
+
+        let target = GENERAL_PURPOSE_REGISTERS.get(access.rt).copied();
+        let register = self.register_mut(access.encoding);
+
+        if access.is_read {
+            let value = register.map_or(0, |value| *value);
+
+            if let Some(target) = target {
+                vcpu.set_register(target, value)?;
+            }
+        } else if let Some(register) = register {
+            *register = match target {
+                Some(source) => vcpu.get_register(source)?,
+                None => 0,
+            };
+        }
+
+        let pc = vcpu.get_register(Register::PC)?;
+
+        vcpu.set_register(Register::PC, pc + 4)?;
+
+        Ok(true)
+    }
+
+    /// Load the guest breakpoints and watchpoints in the hardware slots not used by the host.
+    ///
+    /// Slots the guest disabled since the last call are cleared, and the guest MDSCR_EL1 debug
+    /// enables are merged in the hardware register. Should be called before every run.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn apply(&mut self, vcpu: &mut VirtualCpu) -> Result<()> {
+        for (index, registers) in DEBUG_REGISTERS.iter().enumerate() {
+            let [bvr, bcr, wvr, wcr] = *registers;
+            let bit = 1 << index;
+
+            self.applied_breakpoints = Self::apply_slot(
+                vcpu,
+                (bvr, bcr),
+                (
+                    self.breakpoint_values[index],
+                    self.breakpoint_controls[index],
+                ),
+                self.host_breakpoints & bit != 0,
+                self.applied_breakpoints,
+                bit,
+            )?;
+            self.applied_watchpoints = Self::apply_slot(
+                vcpu,
+                (wvr, wcr),
+                (
+                    self.watchpoint_values[index],
+                    self.watchpoint_controls[index],
+                ),
+                self.host_watchpoints & bit != 0,
+                self.applied_watchpoints,
+                bit,
+            )?;
+        }
+
+        let mdscr = vcpu.get_system_register(SystemRegister::MDSCR_EL1)? & !self.applied_mdscr;
+
+        self.applied_mdscr = self.mdscr & MDSCR_DEBUG_ENABLES & !mdscr;
+
+        vcpu.set_system_register(SystemRegister::MDSCR_EL1, mdscr | self.applied_mdscr)
+    }
+
+    /// Load a guest slot in hardware if it's free, returning the updated bitmap of applied slots.
+    fn apply_slot(
+        vcpu: &mut VirtualCpu,
+        registers: (SystemRegister, SystemRegister),
+        (value, control): (u64, u64),
+        is_host: bool,
+        applied: u16,
+        bit: u16,
+    ) -> Result<u16> {
+        // The host took the slot over, its registers are not ours to clear anymore.
+        if is_host {
+            return Ok(applied & !bit);
+        }
+
+        if control & CONTROL_ENABLE != 0 {
+            vcpu.set_system_register(registers.0, value)?;
+            vcpu.set_system_register(registers.1, control)?;
+
+            Ok(applied | bit)
+        } else if applied & bit != 0 {
+            vcpu.set_system_register(registers.1, 0)?;
+
+            Ok(applied & !bit)
+        } else {
+            Ok(applied)
+        }
+    }
+
+    /// Check if a hardware slot used by the host matches an address.
+    fn is_host_hit(&self, vcpu: &mut VirtualCpu, exception: &DebugException) -> Result<bool> {
+        for (index, registers) in DEBUG_REGISTERS.iter().enumerate() {
+            let [bvr, bcr, wvr, wcr] = *registers;
+            let bit = 1 << index;
+
+            let is_hit = match *exception {
+                DebugException::Breakpoint { address } if self.host_breakpoints & bit != 0 => {
+                    vcpu.get_system_register(bcr)? & CONTROL_ENABLE != 0
+                        && vcpu.get_system_register(bvr)? & !0x3 == address & !0x3
+                }
+                DebugException::Watchpoint { address, .. } if self.host_watchpoints & bit != 0 => {
+                    let control = vcpu.get_system_register(wcr)?;
+
+                    control & CONTROL_ENABLE != 0
+                        && watchpoint_matches(vcpu.get_system_register(wvr)?, control, address)
+                }
+                _ => false,
+            };
+
+            if is_hit {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Check if a guest slot loaded by [DebugRegisterShadow::apply] matches an address.
+    fn is_guest_hit(&self, exception: &DebugException) -> bool {
+        (0..DEBUG_REGISTER_PAIRS).any(|index| {
+            let bit = 1 << index;
+
+            match *exception {
+                DebugException::Breakpoint { address } => {
+                    self.applied_breakpoints & bit != 0
+                        && self.breakpoint_values[index] & !0x3 == address & !0x3
+                }
+                DebugException::Watchpoint { address, slot, .. } => {
+                    self.applied_watchpoints & bit != 0
+                        && slot.map_or(
+                            watchpoint_matches(
+                                self.watchpoint_values[index],
+                                self.watchpoint_controls[index],
+                                address,
+                            ),
+                            |slot| slot as usize == index,
+                        )
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Inject a breakpoint or watchpoint exception caused by a guest slot back into the guest.
+    ///
+    /// Returns `false` if the exception isn't a breakpoint or watchpoint of a guest slot (the
+    /// slots of the host having priority), or if the guest isn't running at EL0 or EL1, in which
+    /// case the exception belongs to the host. Otherwise the vCPU resumes in the guest debug
+    /// exception vector.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn handle_debug_exception(
+        &mut self,
+        vcpu: &mut VirtualCpu,
+        exception: &ExceptionExit,
+    ) -> Result<bool> {
+        let Some(debug_exception) = vcpu.get_debug_exception(exception)? else {
+            return Ok(false);
+        };
+
+        if !self.is_guest_hit(&debug_exception) || self.is_host_hit(vcpu, &debug_exception)? {
+            return Ok(false);
+        }
+
+        let pc = vcpu.get_register(Register::PC)?;
+        let cpsr = vcpu.get_register(Register::CPSR)?;
+
+        // The vector offset and class depend on the exception level and state of the guest.
+        let (offset, is_same_level) = if cpsr & 0x10 != 0 {
+            (0x600, false)
+        } else {
+            match cpsr & 0xF {
+                0b0000 => (0x400, false),
+                0b0100 => (0x000, true),
+                0b0101 => (0x200, true),
+                _ => return Ok(false),
+            }
+        };
+
+        let class = u8::from(exception.class()) as u64 | is_same_level as u64;
+        let length = if exception.instruction_length() == 4 {
+            1 << 25
+        } else {
+            0
+        };
+        let vbar = vcpu.get_system_register(SystemRegister::VBAR_EL1)?;
+
+        vcpu.set_system_register(
+            SystemRegister::ESR_EL1,
+            class << 26 | length | exception.iss() as u64,
+        )?;
+
+        if let DebugException::Watchpoint { address, .. } = debug_exception {
+            vcpu.set_system_register(SystemRegister::FAR_EL1, address)?;
+        }
+
+        vcpu.set_system_register(SystemRegister::ELR_EL1, pc)?;
+        vcpu.set_system_register(SystemRegister::SPSR_EL1, cpsr)?;
+        vcpu.set_register(Register::CPSR, INJECTED_CPSR)?;
+        vcpu.set_register(Register::PC, vbar + offset)?;
+
+        Ok(true)
+    }
+}
+
+/// Immediate of the BRK instructions patched in by [SoftwareBreakpoints].
+pub const SOFTWARE_BREAKPOINT_IMMEDIATE: u16 = 0;
+
+/// MDSCR_EL1 software step enable bit.
+const MDSCR_SS: u64 = 1 << 0;
+
+/// PSTATE software step bit.
+const CPSR_SS: u64 = 1 << 21;
+
+/// Enable or disable software stepping of the next instruction of a vCPU (MDSCR_EL1.SS and
+/// PSTATE.SS).
+///
+/// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+pub(crate) fn set_software_step(vcpu: &mut VirtualCpu, enabled: bool) -> Result<()> {
+    let mdscr = vcpu.get_system_register(SystemRegister::MDSCR_EL1)?;
+    let cpsr = vcpu.get_register(Register::CPSR)?;
+
+    if enabled {
+        vcpu.set_system_register(SystemRegister::MDSCR_EL1, mdscr | MDSCR_SS)?;
+        vcpu.set_register(Register::CPSR, cpsr | CPSR_SS)
+    } else {
+        vcpu.set_system_register(SystemRegister::MDSCR_EL1, mdscr & !MDSCR_SS)?;
+        vcpu.set_register(Register::CPSR, cpsr & !CPSR_SS)
+    }
+}
+
+/// Encode a BRK instruction.
+pub const fn encode_brk(immediate: u16) -> u32 {
+    0xD420_0000 | (immediate as u32) << 5
+}
+
+/// Comparison of a register value against the operand of a [RegisterCondition].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    /// The value equals the operand.
+    Equal,
+
+    /// The value differs from the operand.
+    NotEqual,
+
+    /// The value is lower than the operand (unsigned).
+    Below,
+
+    /// The value is higher than the operand (unsigned).
+    Above,
+
+    /// The value has all the bits of the operand set.
+    AllSet,
+}
+
+/// A predicate over a vCPU register, e.g. `X0 == 0xdead`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterCondition {
+    /// The register compared.
+    pub register: Register,
+
+    /// The comparison.
+    pub comparison: Comparison,
+
+    /// The operand compared against.
+    pub value: u64,
+}
+
+impl RegisterCondition {
+    /// Create a new condition.
+    pub fn new(register: Register, comparison: Comparison, value: u64) -> Self {
+        RegisterCondition {
+            register,
+            comparison,
+            value,
+        }
+    }
+
+    /// Check if a register value satisfies the condition.
+    pub fn matches(&self, value: u64) -> bool {
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Below => value < self.value,
+            Comparison::Above => value > self.value,
+            Comparison::AllSet => value & self.value == self.value,
+        }
+    }
+
+    /// Check if all conditions hold on the current state of a vCPU (always true without any).
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn all_match(conditions: &[RegisterCondition], vcpu: &mut VirtualCpu) -> Result<bool> {
+        for condition in conditions {
+            if !condition.matches(vcpu.get_register(condition.register)?) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// A software breakpoint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SoftwareBreakpoint {
+    /// The guest physical address of the instruction.
+    address: hv_ipa_t,
+
+    /// The original instruction.
+    original: u32,
+
+    /// The conditions that must all hold for a hit to stop.
+    conditions: Vec<RegisterCondition>,
+}
+
+/// Manager of software breakpoints, implemented by patching BRK instructions in guest memory.
+///
+/// Breakpoints are placed at guest physical addresses: the caller is in charge of translating
+/// guest virtual addresses. The vCPU must trap debug exceptions
+/// ([VirtualCpu::set_trap_debug_exceptions]) so that hits exit to the host.
+///
+/// On a hit, [SoftwareBreakpoints::step_over] restores the original instruction and single steps
+/// it, then [SoftwareBreakpoints::handle_software_step] patches the breakpoint back. Breakpoints
+/// with conditions ([SoftwareBreakpoints::set_conditions]) should only stop when
+/// [SoftwareBreakpoints::should_stop] says so, being stepped over otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct SoftwareBreakpoints {
+    /// List of all breakpoints.
+    breakpoints: Vec<SoftwareBreakpoint>,
+
+    /// The breakpoint being stepped over, if any.
+    stepping_over: Option<hv_ipa_t>,
+}
+
+impl SoftwareBreakpoints {
+    /// Create a new manager without any breakpoint.
+    pub fn new() -> Self {
+        SoftwareBreakpoints::default()
+    }
+
+    /// Check if a breakpoint is placed at an address.
+    pub fn contains(&self, address: hv_ipa_t) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.address == address)
+    }
+
+    /// Place a breakpoint at a guest physical address.
+    pub fn insert(&mut self, vm: &mut VirtualMachine, address: hv_ipa_t) -> Result<()> {
+        if !address.is_multiple_of(4) {
+            return Err(HypervisorError::MisalignedAddress);
+        }
+
+        if self.contains(address) {
+            return Ok(());
+        }
+
+        let mut original = [0; 4];
+
+        vm.read_memory(address, &mut original)?;
+        vm.write_memory(
+            address,
+            &encode_brk(SOFTWARE_BREAKPOINT_IMMEDIATE).to_le_bytes(),
+        )?;
+        vm.clean_instruction_cache_range(address, 4)?;
+
+        self.breakpoints.push(SoftwareBreakpoint {
+            address,
+            original: u32::from_le_bytes(original),
+            conditions: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    /// Remove a breakpoint, restoring the original instruction.
+    pub fn remove(&mut self, vm: &mut VirtualMachine, address: hv_ipa_t) -> Result<()> {
+        let index = self
+            .breakpoints
+            .iter()
+            .position(|breakpoint| breakpoint.address == address)
+            .ok_or(HypervisorError::BadArgument)?;
+
+        let breakpoint = self.breakpoints.remove(index);
+
+        // While stepping over, the original instruction is already in place.
+        if self.stepping_over == Some(address) {
+            self.stepping_over = None;
+        } else {
+            vm.write_memory(address, &breakpoint.original.to_le_bytes())?;
+            vm.clean_instruction_cache_range(address, 4)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove all breakpoints.
+    pub fn clear(&mut self, vm: &mut VirtualMachine) -> Result<()> {
+        while let Some(breakpoint) = self.breakpoints.first() {
+            self.remove(vm, breakpoint.address)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check if a BRK exit was caused by one of the breakpoints, `address` being the guest
+    /// physical address of the PC.
+    pub fn is_hit(&self, exception: &DebugException, address: hv_ipa_t) -> bool {
+        matches!(
+            exception,
+            DebugException::Brk {
+                immediate: SOFTWARE_BREAKPOINT_IMMEDIATE,
+                ..
+            }
+        ) && self.contains(address)
+    }
+
+    /// Set the conditions of a breakpoint, replacing the previous ones. A breakpoint without
+    /// conditions always stops.
+    pub fn set_conditions(
+        &mut self,
+        address: hv_ipa_t,
+        conditions: Vec<RegisterCondition>,
+    ) -> Result<()> {
+        self.breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.address == address)
+            .ok_or(HypervisorError::BadArgument)?
+            .conditions = conditions;
+
+        Ok(())
+    }
+
+    /// Check if a hit breakpoint should stop the vCPU, all its conditions holding.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn should_stop(&self, vcpu: &mut VirtualCpu, address: hv_ipa_t) -> Result<bool> {
+        let breakpoint = self
+            .breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.address == address)
+            .ok_or(HypervisorError::BadArgument)?;
+
+        RegisterCondition::all_match(&breakpoint.conditions, vcpu)
+    }
+
+    /// Resume from a hit breakpoint: restore the original instruction and single step it.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn step_over(
+        &mut self,
+        vm: &mut VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        address: hv_ipa_t,
+    ) -> Result<()> {
+        let original = self
+            .breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.address == address)
+            .ok_or(HypervisorError::BadArgument)?
+            .original;
+
+        vm.write_memory(address, &original.to_le_bytes())?;
+        vm.clean_instruction_cache_range(address, 4)?;
+
+        set_software_step(vcpu, true)?;
+
+        self.stepping_over = Some(address);
+
+        Ok(())
+    }
+
+    /// Handle the software step exit that follows [SoftwareBreakpoints::step_over], patching the
+    /// breakpoint back.
+    ///
+    /// Returns `false` if no breakpoint was being stepped over, in which case the step belongs to
+    /// the caller.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn handle_software_step(
+        &mut self,
+        vm: &mut VirtualMachine,
+        vcpu: &mut VirtualCpu,
+    ) -> Result<bool> {
+        let Some(address) = self.stepping_over.take() else {
+            return Ok(false);
+        };
+
+        set_software_step(vcpu, false)?;
+        vm.write_memory(
+            address,
+            &encode_brk(SOFTWARE_BREAKPOINT_IMMEDIATE).to_le_bytes(),
+        )?;
+        vm.clean_instruction_cache_range(address, 4)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_condition_comparisons() {
+        let condition = |comparison| RegisterCondition::new(Register::X0, comparison, 0xDEAD);
+
+        assert!(condition(Comparison::Equal).matches(0xDEAD));
+        assert!(!condition(Comparison::Equal).matches(0xBEEF));
+        assert!(condition(Comparison::NotEqual).matches(0xBEEF));
+        assert!(condition(Comparison::Below).matches(0xDEAC));
+        assert!(!condition(Comparison::Below).matches(0xDEAD));
+        assert!(condition(Comparison::Above).matches(u64::MAX));
+        assert!(condition(Comparison::AllSet).matches(0xFFFF));
+        assert!(!condition(Comparison::AllSet).matches(0xDEA0));
+    }
+}
@@ -0,0 +1,111 @@
+//! Shared host/guest atomic communication page.
+//!
+//! A page mapped in the guest and exposed on the host as an array of [AtomicU64], for polling
+//! protocols (progress counters, flags, sequence numbers) that need no doorbell nor exit.
+//!
+//! ## Memory ordering
+//!
+//! The host and the guest vCPUs are in the same Inner Shareable domain, so host atomics and guest
+//! accesses are coherent and ordered like between host threads, provided that:
+//!
+//! - the guest maps the page as Normal Inner Write-Back cacheable memory (device or
+//!   non-cacheable mappings bypass coherency with the host),
+//! - entries are naturally aligned 64-bit words, accessed by single-copy atomic instructions,
+//! - a host `Release` store pairs with a guest load-acquire (LDAR, or LDR followed by DMB ISHLD),
+//!   and a guest store-release (STLR, or DMB ISH followed by STR) pairs with a host `Acquire`
+//!   load,
+//! - read-modify-write operations on both sides use LSE atomics or exclusives (LDXR/STXR).
+//!
+//! Nothing orders the host accesses with respect to a vCPU that isn't running: a value written
+//! by the host is observed by the guest whenever it next reads it.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::virtual_machine::{MappingHandle, MemoryPermission, PAGE_SIZE, VirtualMachine};
+
+extern crate alloc;
+use alloc::alloc::{Layout, alloc_zeroed, dealloc};
+use alloc::sync::Arc;
+
+use core::sync::atomic::AtomicU64;
+
+/// Number of 64-bit words in the page.
+pub const WORD_COUNT: usize = PAGE_SIZE / 8;
+
+/// Host page backing the shared words.
+#[derive(Debug)]
+struct AtomicPageInner {
+    /// The host address of the page.
+    page: *mut AtomicU64,
+}
+
+// The page is only accessed through atomics.
+unsafe impl Send for AtomicPageInner {}
+unsafe impl Sync for AtomicPageInner {}
+
+impl Drop for AtomicPageInner {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.page as *mut u8, AtomicPage::layout()) };
+    }
+}
+
+/// A page of atomic words shared between host threads and the guest.
+///
+/// Clones share the same page and can be sent to other host threads.
+#[derive(Clone, Debug)]
+pub struct AtomicPage {
+    /// The shared page.
+    inner: Arc<AtomicPageInner>,
+}
+
+impl AtomicPage {
+    /// Layout of the page allocation.
+    fn layout() -> Layout {
+        Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()
+    }
+
+    /// Create a new zeroed page.
+    pub fn new() -> Result<Self> {
+        let page = unsafe { alloc_zeroed(AtomicPage::layout()) } as *mut AtomicU64;
+
+        if page.is_null() {
+            return Err(HypervisorError::NoResources);
+        }
+
+        Ok(AtomicPage {
+            inner: Arc::new(AtomicPageInner { page }),
+        })
+    }
+
+    /// Map the page in the guest.
+    ///
+    /// # Safety
+    ///
+    /// The page must outlive the Virtual Machine, or be unmapped and deallocated first.
+    pub unsafe fn attach(
+        &self,
+        vm: &mut VirtualMachine,
+        guest_address: hv_ipa_t,
+    ) -> Result<MappingHandle> {
+        let allocation_handle =
+            unsafe { vm.allocate_external(self.inner.page as *mut u8, PAGE_SIZE)? };
+
+        vm.map(
+            allocation_handle,
+            guest_address,
+            MemoryPermission::READ_WRITE,
+        )
+    }
+
+    /// Gets the words of the page.
+    ///
+    /// The word at index `i` is at guest offset `i * 8`.
+    pub fn words(&self) -> &[AtomicU64] {
+        unsafe { core::slice::from_raw_parts(self.inner.page, WORD_COUNT) }
+    }
+
+    /// Gets a word of the page.
+    pub fn word(&self, index: usize) -> Option<&AtomicU64> {
+        self.words().get(index)
+    }
+}
@@ -0,0 +1,180 @@
+//! Bounds-checked guest memory access for device models.
+//!
+//! A [DmaMemory] wraps the guest memory given to a device backend and only lets it access the
+//! ranges allowed for the device (its queues and buffers area, a shared window, ...). Any other
+//! access fails with [HypervisorError::Denied] and is logged, so a buggy or malicious guest driver
+//! can't make a device model read or write arbitrary guest memory.
+//!
+//! The device models access guest memory through the [GuestMemory] trait, so a [DmaMemory] can be
+//! given to them: [crate::virtqueue::Virtqueue::regions] and
+//! [crate::device::mailbox::RingHandle::region] give the regions to allow for their rings.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::machine::MemoryRegion;
+use crate::virtqueue::GuestMemory;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use core::cell::RefCell;
+
+/// Maximum number of violations kept in the log.
+pub const MAX_LOGGED_VIOLATIONS: usize = 1024;
+
+/// A device access outside of its allowed ranges.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DmaViolation {
+    /// The guest address accessed.
+    pub address: hv_ipa_t,
+
+    /// The size of the access.
+    pub size: usize,
+
+    /// The access is a write.
+    pub is_write: bool,
+}
+
+/// A range a device is allowed to access.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct DmaRange {
+    /// The guest memory region.
+    region: MemoryRegion,
+
+    /// The device may write the region.
+    is_writable: bool,
+}
+
+/// Record of the denied accesses.
+#[derive(Clone, Debug, Default)]
+struct ViolationLog {
+    /// The first violations, up to [MAX_LOGGED_VIOLATIONS].
+    violations: Vec<DmaViolation>,
+
+    /// Total number of violations.
+    count: u64,
+}
+
+/// A view of guest memory restricted to the ranges allowed for a device.
+#[derive(Debug)]
+pub struct DmaMemory<M: GuestMemory> {
+    /// The underlying guest memory.
+    memory: M,
+
+    /// The allowed ranges.
+    ranges: Vec<DmaRange>,
+
+    /// The denied accesses.
+    log: RefCell<ViolationLog>,
+}
+
+impl<M: GuestMemory> DmaMemory<M> {
+    /// Create a new view without any allowed range.
+    pub fn new(memory: M) -> Self {
+        DmaMemory {
+            memory,
+            ranges: Vec::new(),
+            log: RefCell::new(ViolationLog::default()),
+        }
+    }
+
+    /// Allow the device to read a region, and to write it if `is_writable` is set.
+    pub fn allow(&mut self, region: MemoryRegion, is_writable: bool) -> Result<()> {
+        if region.size == 0 || region.base.checked_add(region.size).is_none() {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        self.ranges.push(DmaRange {
+            region,
+            is_writable,
+        });
+
+        Ok(())
+    }
+
+    /// Revoke a previously allowed region.
+    pub fn revoke(&mut self, region: MemoryRegion) -> Result<()> {
+        let index = self
+            .ranges
+            .iter()
+            .position(|range| range.region == region)
+            .ok_or(HypervisorError::BadArgument)?;
+
+        self.ranges.remove(index);
+
+        Ok(())
+    }
+
+    /// Revoke all allowed regions (on device reset for example).
+    pub fn revoke_all(&mut self) {
+        self.ranges.clear();
+    }
+
+    /// Gets the logged violations.
+    pub fn violations(&self) -> Vec<DmaViolation> {
+        self.log.borrow().violations.clone()
+    }
+
+    /// Gets the total number of violations, including the ones not kept in the log.
+    pub fn violation_count(&self) -> u64 {
+        self.log.borrow().count
+    }
+
+    /// Take the logged violations, clearing the log.
+    pub fn take_violations(&mut self) -> Vec<DmaViolation> {
+        core::mem::take(&mut self.log.get_mut().violations)
+    }
+
+    /// Gets the underlying guest memory.
+    pub fn inner(&self) -> &M {
+        &self.memory
+    }
+
+    /// Consume the view, returning the underlying guest memory.
+    pub fn into_inner(self) -> M {
+        self.memory
+    }
+
+    /// Check that an access is inside a single allowed range, logging it otherwise.
+    fn check(&self, address: hv_ipa_t, size: usize, is_write: bool) -> Result<()> {
+        let allowed = address.checked_add(size as u64).is_some_and(|end| {
+            self.ranges.iter().any(|range| {
+                (range.is_writable || !is_write)
+                    && address >= range.region.base
+                    && end <= range.region.end()
+            })
+        });
+
+        if allowed {
+            return Ok(());
+        }
+
+        let mut log = self.log.borrow_mut();
+
+        log.count += 1;
+
+        if log.violations.len() < MAX_LOGGED_VIOLATIONS {
+            log.violations.push(DmaViolation {
+                address,
+                size,
+                is_write,
+            });
+        }
+
+        Err(HypervisorError::Denied)
+    }
+}
+
+impl<M: GuestMemory> GuestMemory for DmaMemory<M> {
+    fn read(&self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()> {
+        self.check(address, buffer.len(), false)?;
+
+        self.memory.read(address, buffer)
+    }
+
+    fn write(&mut self, address: hv_ipa_t, data: &[u8]) -> Result<()> {
+        self.check(address, data.len(), true)?;
+
+        self.memory.write(address, data)
+    }
+}
@@ -0,0 +1,139 @@
+//! Simple linear framebuffer.
+//!
+//! A dumb framebuffer placed in guest memory and described to the guest by a
+//! "simple-framebuffer" device tree node, usable by firmware and early boot code before any
+//! graphics driver is loaded. The host reads the pixels back from guest memory.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::fdt::FdtWriter;
+use crate::virtual_machine::{MappingHandle, MemoryPermission, PAGE_SIZE, VirtualMachine};
+
+extern crate alloc;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Pixel format of the framebuffer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PixelFormat {
+    /// 32 bits per pixel, x8r8g8b8.
+    X8R8G8B8,
+
+    /// 32 bits per pixel, a8r8g8b8.
+    A8R8G8B8,
+
+    /// 16 bits per pixel, r5g6b5.
+    R5G6B5,
+}
+
+impl PixelFormat {
+    /// Gets the size of a pixel in bytes.
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            PixelFormat::X8R8G8B8 | PixelFormat::A8R8G8B8 => 4,
+            PixelFormat::R5G6B5 => 2,
+        }
+    }
+
+    /// Gets the format name used by the "simple-framebuffer" binding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelFormat::X8R8G8B8 => "x8r8g8b8",
+            PixelFormat::A8R8G8B8 => "a8r8g8b8",
+            PixelFormat::R5G6B5 => "r5g6b5",
+        }
+    }
+}
+
+/// A simple framebuffer mapped in a Virtual Machine.
+#[derive(Copy, Clone, Debug)]
+pub struct Framebuffer {
+    /// The mapping backing the framebuffer.
+    pub mapping: MappingHandle,
+
+    /// The guest address of the framebuffer.
+    pub address: hv_ipa_t,
+
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+
+    /// Pixel format.
+    pub format: PixelFormat,
+}
+
+impl Framebuffer {
+    /// Allocate and map a new framebuffer at a guest address.
+    ///
+    /// The stride must fit in 32 bits and the whole framebuffer in the host address space.
+    pub fn new(
+        vm: &mut VirtualMachine,
+        address: hv_ipa_t,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<Self> {
+        let size = width
+            .checked_mul(format.bytes_per_pixel())
+            .and_then(|stride| (stride as usize).checked_mul(height as usize))
+            .and_then(|size| size.checked_next_multiple_of(PAGE_SIZE))
+            .ok_or(HypervisorError::BadArgument)?;
+        let allocation_handle = vm.allocate(size)?;
+        let mapping = vm.map(allocation_handle, address, MemoryPermission::READ_WRITE)?;
+
+        Ok(Framebuffer {
+            mapping,
+            address,
+            width,
+            height,
+            format,
+        })
+    }
+
+    /// Gets the size of a line in bytes.
+    pub fn stride(&self) -> u32 {
+        self.width * self.format.bytes_per_pixel()
+    }
+
+    /// Gets the size of the framebuffer in bytes.
+    pub fn size(&self) -> usize {
+        self.stride() as usize * self.height as usize
+    }
+
+    /// Copy the current pixel data out of guest memory.
+    pub fn snapshot(&self, vm: &VirtualMachine) -> Result<Vec<u8>> {
+        let mut result = vec![0; self.size()];
+
+        vm.read_memory(self.address, &mut result)?;
+
+        Ok(result)
+    }
+
+    /// Write the "simple-framebuffer" device tree node.
+    ///
+    /// By convention, the node is placed under the `chosen` node so that it's only used until a
+    /// real driver takes over.
+    pub fn write_fdt_node(&self, fdt: &mut FdtWriter) {
+        let size = self.size() as u64;
+
+        fdt.begin_node(&format!("framebuffer@{:x}", self.address));
+        fdt.property_string("compatible", "simple-framebuffer");
+        fdt.property_cells(
+            "reg",
+            &[
+                (self.address >> 32) as u32,
+                self.address as u32,
+                (size >> 32) as u32,
+                size as u32,
+            ],
+        );
+        fdt.property_u32("width", self.width);
+        fdt.property_u32("height", self.height);
+        fdt.property_u32("stride", self.stride());
+        fdt.property_string("format", self.format.name());
+        fdt.end_node();
+    }
+}
@@ -0,0 +1,236 @@
+//! Doorbell-signaled shared-memory mailbox.
+//!
+//! A minimal device for custom host/guest protocols. The guest allocates a ring in its memory,
+//! programs its location and geometry in the device registers, pushes fixed-size entries in it
+//! and rings the doorbell. The host consumes the entries through a [RingHandle] and raises the
+//! completion interrupt, which the guest acknowledges.
+//!
+//! The ring is accessed through any [GuestMemory], typically a [crate::device::dma::DmaMemory]
+//! only allowing the [RingHandle::region] of the ring.
+//!
+//! The ring starts with a header of two little-endian u32 free-running indices, the producer
+//! (written by the guest) followed by the consumer (written by the host). Entries follow at
+//! [RING_HEADER_SIZE], the entry for index `i` being at slot `i % entries`.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::machine::MemoryRegion;
+use crate::virtqueue::GuestMemory;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Offset of the magic register ("AHMB").
+pub const MAGIC: u64 = 0x00;
+
+/// Offset of the version register.
+pub const VERSION: u64 = 0x04;
+
+/// Offset of the low half of the ring guest address.
+pub const RING_BASE_LOW: u64 = 0x08;
+
+/// Offset of the high half of the ring guest address.
+pub const RING_BASE_HIGH: u64 = 0x0C;
+
+/// Offset of the number of ring entries.
+pub const RING_ENTRIES: u64 = 0x10;
+
+/// Offset of the size of a ring entry.
+pub const ENTRY_SIZE: u64 = 0x14;
+
+/// Offset of the doorbell register.
+pub const DOORBELL: u64 = 0x18;
+
+/// Offset of the interrupt status register.
+pub const INTERRUPT_STATUS: u64 = 0x1C;
+
+/// Offset of the interrupt acknowledge register.
+pub const INTERRUPT_ACK: u64 = 0x20;
+
+/// Size of the register frame.
+pub const FRAME_SIZE: u64 = 0x1000;
+
+/// Size of the ring header.
+pub const RING_HEADER_SIZE: u64 = 16;
+
+/// Maximum size of a ring entry.
+pub const MAX_ENTRY_SIZE: u32 = 0x1000;
+
+/// Value of the magic register.
+const MAILBOX_MAGIC: u32 = 0x424D_4841;
+
+/// Value of the version register.
+const MAILBOX_VERSION: u32 = 1;
+
+/// Interrupt status bit of a completion.
+pub const INTERRUPT_COMPLETION: u32 = 1 << 0;
+
+/// Event raised by a guest access to the mailbox.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MailboxEvent {
+    /// The guest rang the doorbell with a value.
+    Doorbell(u32),
+}
+
+/// Host-side access to the ring configured by the guest.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RingHandle {
+    /// The guest address of the ring.
+    pub base: hv_ipa_t,
+
+    /// The number of entries.
+    pub entries: u32,
+
+    /// The size of an entry.
+    pub entry_size: u32,
+}
+
+impl RingHandle {
+    /// Gets the guest address of the entry in a slot.
+    fn slot_address(&self, slot: u32) -> Result<hv_ipa_t> {
+        u64::from(slot)
+            .checked_mul(u64::from(self.entry_size))
+            .and_then(|offset| offset.checked_add(RING_HEADER_SIZE))
+            .and_then(|offset| self.base.checked_add(offset))
+            .ok_or(HypervisorError::IllegalGuestState)
+    }
+
+    /// Gets the guest memory region of the whole ring.
+    pub fn region(&self) -> Result<MemoryRegion> {
+        let end = self.slot_address(self.entries)?;
+
+        Ok(MemoryRegion::new(self.base, end - self.base))
+    }
+
+    /// Read the (producer, consumer) indices.
+    fn indices<M: GuestMemory>(&self, memory: &M) -> Result<(u32, u32)> {
+        let mut header = [0; 8];
+
+        memory.read(self.base, &mut header)?;
+
+        Ok((
+            u32::from_le_bytes(header[0..4].try_into().unwrap()),
+            u32::from_le_bytes(header[4..8].try_into().unwrap()),
+        ))
+    }
+
+    /// Gets the number of entries waiting to be consumed.
+    pub fn pending<M: GuestMemory>(&self, memory: &M) -> Result<u32> {
+        let (producer, consumer) = self.indices(memory)?;
+
+        Ok(producer.wrapping_sub(consumer).min(self.entries))
+    }
+
+    /// Consume the next entry, if any.
+    pub fn pop<M: GuestMemory>(&self, memory: &mut M) -> Result<Option<Vec<u8>>> {
+        let (producer, consumer) = self.indices(memory)?;
+
+        if producer == consumer {
+            return Ok(None);
+        }
+
+        if self.entries == 0 || self.entry_size > MAX_ENTRY_SIZE {
+            return Err(HypervisorError::IllegalGuestState);
+        }
+
+        let address = self.slot_address(consumer % self.entries)?;
+        let mut entry = vec![0; self.entry_size as usize];
+
+        memory.read(address, &mut entry)?;
+        memory.write_u32(self.base + 4, consumer.wrapping_add(1))?;
+
+        Ok(Some(entry))
+    }
+}
+
+/// A doorbell-signaled shared-memory mailbox.
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    /// The ring guest address.
+    ring_base: u64,
+
+    /// The number of ring entries.
+    ring_entries: u32,
+
+    /// The size of a ring entry.
+    entry_size: u32,
+
+    /// The interrupt status.
+    interrupt_status: u32,
+}
+
+impl Mailbox {
+    /// Create a new mailbox without any ring configured.
+    pub fn new() -> Self {
+        Mailbox::default()
+    }
+
+    /// Gets the ring configured by the guest, if any.
+    ///
+    /// The entries must be at most [MAX_ENTRY_SIZE] bytes and the whole ring must fit in the guest
+    /// address space.
+    pub fn ring(&self) -> Result<RingHandle> {
+        if self.ring_base == 0
+            || self.ring_entries == 0
+            || self.entry_size == 0
+            || self.entry_size > MAX_ENTRY_SIZE
+        {
+            return Err(HypervisorError::IllegalGuestState);
+        }
+
+        let ring = RingHandle {
+            base: self.ring_base,
+            entries: self.ring_entries,
+            entry_size: self.entry_size,
+        };
+
+        // The last entry must not wrap around the address space.
+        ring.slot_address(self.ring_entries)?;
+
+        Ok(ring)
+    }
+
+    /// Raise the completion interrupt.
+    pub fn complete(&mut self) {
+        self.interrupt_status |= INTERRUPT_COMPLETION;
+    }
+
+    /// Check if the interrupt is asserted.
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    /// Read a register.
+    pub fn read(&self, offset: u64) -> u64 {
+        match offset {
+            MAGIC => u64::from(MAILBOX_MAGIC),
+            VERSION => u64::from(MAILBOX_VERSION),
+            RING_BASE_LOW => self.ring_base & 0xFFFF_FFFF,
+            RING_BASE_HIGH => self.ring_base >> 32,
+            RING_ENTRIES => u64::from(self.ring_entries),
+            ENTRY_SIZE => u64::from(self.entry_size),
+            INTERRUPT_STATUS => u64::from(self.interrupt_status),
+            _ => 0,
+        }
+    }
+
+    /// Write a register.
+    ///
+    /// Returns the event raised by the write, if any.
+    pub fn write(&mut self, offset: u64, value: u64) -> Option<MailboxEvent> {
+        match offset {
+            RING_BASE_LOW => {
+                self.ring_base = (self.ring_base & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF)
+            }
+            RING_BASE_HIGH => self.ring_base = (self.ring_base & 0xFFFF_FFFF) | (value << 32),
+            RING_ENTRIES => self.ring_entries = value as u32,
+            ENTRY_SIZE => self.entry_size = value as u32,
+            DOORBELL => return Some(MailboxEvent::Doorbell(value as u32)),
+            INTERRUPT_ACK => self.interrupt_status &= !(value as u32),
+            _ => {}
+        }
+
+        None
+    }
+}
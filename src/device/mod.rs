@@ -0,0 +1,12 @@
+//! Emulated device models.
+//!
+//! Device models only implement the register interface of the hardware. Dispatching guest MMIO
+//! accesses to them and delivering their interrupts is left to the caller.
+
+pub mod atomic_page;
+pub mod dma;
+pub mod framebuffer;
+pub mod mailbox;
+pub mod pvclock;
+pub mod sbsa_watchdog;
+pub mod shared_memory;
@@ -0,0 +1,262 @@
+//! Paravirtual clock shared page.
+//!
+//! The host publishes in a read-only guest page the relation between the guest virtual counter
+//! (CNTVCT_EL0) and both the time elapsed since boot and the wall-clock time. The guest computes
+//! the current time from the counter alone, without trapping or needing an RTC interrupt.
+//!
+//! The page holds a [PvClockPage] and is protected by a sequence counter. The guest reads it as
+//! follows:
+//!
+//! ```text
+//! loop {
+//!     version = page.version;            // retry while odd (update in progress)
+//!     dmb ishld;
+//!     copy = page.*;
+//!     dmb ishld;
+//!     if page.version == version { break; }
+//! }
+//! delta_ns = (CNTVCT_EL0 - copy.counter) * 1_000_000_000 / copy.frequency;
+//! boot_time_ns = copy.boot_time_ns + delta_ns;
+//! wall_clock = (copy.wall_clock_sec, copy.wall_clock_nsec) + delta_ns;
+//! ```
+//!
+//! The host refreshes the page with [PvClock::update], periodically or on demand. The guest can
+//! also force a refresh with the [PVCLOCK_REFRESH] hypervisor call, served by registering the
+//! clock as a [crate::smccc::Service].
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::smccc::{Call, Service};
+use crate::virtual_machine::{MappingHandle, MemoryPermission, PAGE_SIZE, VirtualMachine};
+
+extern crate alloc;
+use alloc::alloc::{Layout, alloc_zeroed, dealloc};
+use alloc::rc::Rc;
+
+use core::cell::Cell;
+use core::ops::RangeInclusive;
+use core::sync::atomic::{Ordering, fence};
+
+unsafe extern "C" {
+    pub(crate) fn mach_absolute_time() -> u64;
+
+    fn mach_timebase_info(info: *mut MachTimebaseInfo) -> libc::c_int;
+}
+
+/// Conversion factor from mach_absolute_time() ticks to nanoseconds.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct MachTimebaseInfo {
+    /// Numerator.
+    pub(crate) numer: u32,
+
+    /// Denominator.
+    pub(crate) denom: u32,
+}
+
+impl MachTimebaseInfo {
+    /// Gets the timebase of the host counter, which is also the guest virtual counter.
+    pub(crate) fn get() -> Result<Self> {
+        let mut timebase = MachTimebaseInfo::default();
+
+        if unsafe { mach_timebase_info(&mut timebase) } != 0 || timebase.numer == 0 {
+            return Err(HypervisorError::Error);
+        }
+
+        Ok(timebase)
+    }
+}
+
+/// Range of function numbers of the PV clock service (owned by
+/// [crate::smccc::ServiceOwner::VendorHypervisor]).
+pub const FUNCTIONS: RangeInclusive<u16> = 0x10..=0x10;
+
+/// PVCLOCK_REFRESH function identifier.
+///
+/// Refreshes the page and returns its guest address in X1.
+pub const PVCLOCK_REFRESH: u32 = 0x8600_0010;
+
+/// Layout of the shared page, as seen by the guest (little-endian).
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PvClockPage {
+    /// Sequence counter, odd while the host updates the page.
+    pub version: u32,
+
+    /// Reserved, zero.
+    pub flags: u32,
+
+    /// Value of the guest virtual counter at the time of the update.
+    pub counter: u64,
+
+    /// Frequency of the guest virtual counter in Hz.
+    pub frequency: u64,
+
+    /// Nanoseconds elapsed since boot at the time of the update.
+    pub boot_time_ns: u64,
+
+    /// Wall-clock seconds since the Unix epoch at the time of the update.
+    pub wall_clock_sec: u64,
+
+    /// Wall-clock nanoseconds at the time of the update.
+    pub wall_clock_nsec: u64,
+}
+
+/// Host page backing the clock.
+#[derive(Debug)]
+struct PvClockInner {
+    /// The host address of the page.
+    page: *mut PvClockPage,
+
+    /// The guest address of the page once attached.
+    guest_address: Cell<Option<hv_ipa_t>>,
+
+    /// The vtimer offset of the vCPUs.
+    vtimer_offset: Cell<u64>,
+
+    /// The host counter value at boot.
+    boot_counter: u64,
+
+    /// The timebase of the host counter.
+    timebase: MachTimebaseInfo,
+}
+
+impl Drop for PvClockInner {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.page as *mut u8, PvClock::layout()) };
+    }
+}
+
+/// A paravirtual clock.
+///
+/// Clones share the same page, so one can be registered as a SMCCC service while another is
+/// kept to update the page.
+#[derive(Clone, Debug)]
+pub struct PvClock {
+    /// The shared state.
+    inner: Rc<PvClockInner>,
+}
+
+impl PvClock {
+    /// Layout of the page allocation.
+    fn layout() -> Layout {
+        Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()
+    }
+
+    /// Create a new clock, taking the current time as the boot time.
+    pub fn new() -> Result<Self> {
+        let timebase = MachTimebaseInfo::get()?;
+        let page = unsafe { alloc_zeroed(PvClock::layout()) } as *mut PvClockPage;
+
+        if page.is_null() {
+            return Err(HypervisorError::NoResources);
+        }
+
+        let clock = PvClock {
+            inner: Rc::new(PvClockInner {
+                page,
+                guest_address: Cell::new(None),
+                vtimer_offset: Cell::new(0),
+                boot_counter: unsafe { mach_absolute_time() },
+                timebase,
+            }),
+        };
+
+        clock.update();
+
+        Ok(clock)
+    }
+
+    /// Map the page read-only in the guest.
+    ///
+    /// # Safety
+    ///
+    /// The clock must outlive the Virtual Machine, or be unmapped and deallocated first.
+    pub unsafe fn attach(
+        &self,
+        vm: &mut VirtualMachine,
+        guest_address: hv_ipa_t,
+    ) -> Result<MappingHandle> {
+        let allocation_handle =
+            unsafe { vm.allocate_external(self.inner.page as *mut u8, PAGE_SIZE)? };
+        let mapping = vm.map(allocation_handle, guest_address, MemoryPermission::READ)?;
+
+        self.inner.guest_address.set(Some(guest_address));
+
+        Ok(mapping)
+    }
+
+    /// Gets the guest address of the page, if attached.
+    pub fn guest_address(&self) -> Option<hv_ipa_t> {
+        self.inner.guest_address.get()
+    }
+
+    /// Set the vtimer offset programmed in the vCPUs, so that the published counter matches
+    /// CNTVCT_EL0 as seen by the guest.
+    pub fn set_vtimer_offset(&self, offset: u64) {
+        self.inner.vtimer_offset.set(offset);
+        self.update();
+    }
+
+    /// Convert host counter ticks to nanoseconds.
+    fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        let timebase = self.inner.timebase;
+
+        (u128::from(ticks) * u128::from(timebase.numer) / u128::from(timebase.denom)) as u64
+    }
+
+    /// Publish the current time in the page.
+    pub fn update(&self) {
+        let inner = &self.inner;
+        let mut wall_clock = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        let counter = unsafe { mach_absolute_time() };
+
+        unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut wall_clock) };
+
+        let timebase = inner.timebase;
+        let frequency =
+            (1_000_000_000u128 * u128::from(timebase.denom) / u128::from(timebase.numer)) as u64;
+
+        let page = inner.page;
+
+        unsafe {
+            let version = (&raw const (*page).version).read_volatile();
+
+            (&raw mut (*page).version).write_volatile(version.wrapping_add(1));
+            fence(Ordering::Release);
+
+            (&raw mut (*page).counter)
+                .write_volatile(counter.wrapping_sub(inner.vtimer_offset.get()));
+            (&raw mut (*page).frequency).write_volatile(frequency);
+            (&raw mut (*page).boot_time_ns)
+                .write_volatile(self.ticks_to_ns(counter - inner.boot_counter));
+            (&raw mut (*page).wall_clock_sec).write_volatile(wall_clock.tv_sec as u64);
+            (&raw mut (*page).wall_clock_nsec).write_volatile(wall_clock.tv_nsec as u64);
+
+            fence(Ordering::Release);
+            (&raw mut (*page).version).write_volatile(version.wrapping_add(2));
+        }
+    }
+
+    /// Gets a copy of the page contents.
+    pub fn read(&self) -> PvClockPage {
+        unsafe { self.inner.page.read_volatile() }
+    }
+}
+
+impl Service for PvClock {
+    fn call(&mut self, call: &Call) -> Option<[u64; 4]> {
+        match call.function.0 {
+            PVCLOCK_REFRESH => {
+                self.update();
+
+                Some([0, self.guest_address().unwrap_or(0), 0, 0])
+            }
+            _ => None,
+        }
+    }
+}
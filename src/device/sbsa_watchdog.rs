@@ -0,0 +1,179 @@
+//! SBSA generic watchdog.
+//!
+//! The watchdog exposes two 4KiB frames: a refresh frame and a control frame. Time is expressed in
+//! ticks of the system counter and provided by the caller, which is expected to call
+//! [SbsaWatchdog::poll] before dispatching guest accesses and whenever [SbsaWatchdog::deadline]
+//! expires.
+
+/// Offset of the Watchdog Refresh Register in the refresh frame.
+pub const WRR: u64 = 0x000;
+
+/// Offset of the Watchdog Control and Status register in the control frame.
+pub const WCS: u64 = 0x000;
+
+/// Offset of the Watchdog Offset Register in the control frame.
+pub const WOR: u64 = 0x008;
+
+/// Offset of the Watchdog Compare Value register in the control frame.
+pub const WCV: u64 = 0x010;
+
+/// Offset of the upper half of WCV when accessed with 32-bit accesses.
+const WCV_HIGH: u64 = WCV + 4;
+
+/// Offset of the Watchdog Interface Identification Register in both frames.
+pub const W_IIDR: u64 = 0xFCC;
+
+/// Size of a watchdog frame.
+pub const FRAME_SIZE: u64 = 0x1000;
+
+/// WCS enable bit.
+const WCS_EN: u32 = 1 << 0;
+
+/// WCS first stage (WS0) bit.
+const WCS_WS0: u32 = 1 << 1;
+
+/// WCS second stage (WS1) bit.
+const WCS_WS1: u32 = 1 << 2;
+
+/// Interface identification (Arm implementer, architecture version 0).
+const IIDR: u32 = 0x43B;
+
+/// Action to take when the watchdog second stage is reached.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WatchdogAction {
+    /// The Virtual Machine should be reset.
+    Reset,
+
+    /// The timeout should only be reported.
+    Report,
+}
+
+/// Event raised by the watchdog.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WatchdogEvent {
+    /// The first stage was reached, the watchdog interrupt (WS0) should be raised.
+    FirstStage,
+
+    /// The second stage was reached, the configured action should be taken.
+    SecondStage(WatchdogAction),
+}
+
+/// An SBSA generic watchdog.
+#[derive(Debug)]
+pub struct SbsaWatchdog {
+    /// The Watchdog Control and Status register.
+    control: u32,
+
+    /// The Watchdog Offset Register.
+    offset: u32,
+
+    /// The Watchdog Compare Value register.
+    compare: u64,
+
+    /// Action to take when the second stage is reached.
+    action: WatchdogAction,
+}
+
+impl SbsaWatchdog {
+    /// Create a new disabled watchdog.
+    pub fn new(action: WatchdogAction) -> Self {
+        SbsaWatchdog {
+            control: 0,
+            offset: 0,
+            compare: 0,
+            action,
+        }
+    }
+
+    /// Perform an explicit watchdog refresh.
+    fn refresh(&mut self, now: u64) {
+        self.control &= !(WCS_WS0 | WCS_WS1);
+        self.compare = now.wrapping_add(u64::from(self.offset));
+    }
+
+    /// Check if the watchdog is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.control & WCS_EN != 0
+    }
+
+    /// Check if the watchdog interrupt (WS0) is asserted.
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.control & WCS_WS0 != 0
+    }
+
+    /// Gets the system counter value at which the next stage is reached, if enabled.
+    pub fn deadline(&self) -> Option<u64> {
+        if self.is_enabled() && self.control & WCS_WS1 == 0 {
+            Some(self.compare)
+        } else {
+            None
+        }
+    }
+
+    /// Read a register of the refresh frame.
+    pub fn read_refresh(&self, offset: u64) -> u64 {
+        match offset {
+            W_IIDR => u64::from(IIDR),
+            _ => 0,
+        }
+    }
+
+    /// Write a register of the refresh frame.
+    pub fn write_refresh(&mut self, offset: u64, _value: u64, now: u64) {
+        if offset == WRR {
+            self.refresh(now);
+        }
+    }
+
+    /// Read a register of the control frame.
+    pub fn read_control(&self, offset: u64) -> u64 {
+        match offset {
+            WCS => u64::from(self.control),
+            WOR => u64::from(self.offset),
+            WCV => self.compare,
+            WCV_HIGH => self.compare >> 32,
+            W_IIDR => u64::from(IIDR),
+            _ => 0,
+        }
+    }
+
+    /// Write a register of the control frame with an access of `size` bytes.
+    pub fn write_control(&mut self, offset: u64, size: usize, value: u64, now: u64) {
+        match offset {
+            WCS => {
+                self.control = (self.control & !WCS_EN) | (value as u32 & WCS_EN);
+                self.refresh(now);
+            }
+            WOR => {
+                self.offset = value as u32;
+                self.refresh(now);
+            }
+            WCV if size == 4 => {
+                self.compare = (self.compare & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF)
+            }
+            WCV => self.compare = value,
+            WCV_HIGH => self.compare = (self.compare & 0xFFFF_FFFF) | (value << 32),
+            _ => {}
+        }
+    }
+
+    /// Advance the watchdog to the given system counter value.
+    ///
+    /// Returns the event raised if a new stage was reached.
+    pub fn poll(&mut self, now: u64) -> Option<WatchdogEvent> {
+        if !self.is_enabled() || self.control & WCS_WS1 != 0 || now < self.compare {
+            return None;
+        }
+
+        if self.control & WCS_WS0 == 0 {
+            self.control |= WCS_WS0;
+            self.compare = now.wrapping_add(u64::from(self.offset));
+
+            Some(WatchdogEvent::FirstStage)
+        } else {
+            self.control |= WCS_WS1;
+
+            Some(WatchdogEvent::SecondStage(self.action))
+        }
+    }
+}
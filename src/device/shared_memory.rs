@@ -0,0 +1,197 @@
+//! ivshmem-style shared memory device.
+//!
+//! Exposes a host-provided memory region, optionally a POSIX shared memory object shared with
+//! another process, directly in the guest physical address space. The guest discovers it through
+//! a device tree node, and host tools exchange data with guest workloads without any copy.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::fdt::FdtWriter;
+use crate::virtual_machine::{MappingHandle, MemoryPermission, PAGE_SIZE, VirtualMachine};
+
+extern crate alloc;
+use alloc::format;
+
+use core::ffi::CStr;
+
+/// Compatible string of the device tree node.
+pub const COMPATIBLE: &str = "ahvf,shared-memory";
+
+/// A host memory region that can be shared with the guest.
+#[derive(Debug)]
+pub struct SharedMemory {
+    /// The host address reserved to align the region.
+    reservation: *mut u8,
+
+    /// The host address of the region.
+    address: *mut u8,
+
+    /// The size of the region.
+    size: usize,
+
+    /// File descriptor of the POSIX shared memory object.
+    fd: libc::c_int,
+}
+
+impl SharedMemory {
+    /// Open (or create) a POSIX shared memory object and map `size` bytes of it.
+    ///
+    /// The size is rounded up to the Virtual Machine page size. Only a newly created object is
+    /// sized, an existing one (even with `create`) is opened as is and rejected if smaller.
+    pub fn open_posix(name: &CStr, size: usize, create: bool) -> Result<Self> {
+        let size = size.next_multiple_of(PAGE_SIZE);
+
+        let mut is_created = false;
+        let mut fd = -1;
+
+        if create {
+            fd = unsafe {
+                libc::shm_open(
+                    name.as_ptr(),
+                    libc::O_RDWR | libc::O_CREAT | libc::O_EXCL,
+                    0o600,
+                )
+            };
+
+            is_created = fd >= 0;
+
+            if !is_created && std::io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST) {
+                return Err(HypervisorError::Denied);
+            }
+        }
+
+        if !is_created {
+            fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0o600) };
+        }
+
+        if fd < 0 {
+            return Err(HypervisorError::Denied);
+        }
+
+        if is_created && unsafe { libc::ftruncate(fd, size as libc::off_t) } != 0 {
+            unsafe {
+                libc::close(fd);
+                libc::shm_unlink(name.as_ptr());
+            }
+
+            return Err(HypervisorError::NoResources);
+        }
+
+        // Accessing an existing object past its end would raise SIGBUS.
+        if !is_created {
+            let mut stat: libc::stat = unsafe { core::mem::zeroed() };
+
+            if unsafe { libc::fstat(fd, &mut stat) } != 0 || (stat.st_size as u64) < size as u64 {
+                unsafe { libc::close(fd) };
+
+                return Err(HypervisorError::BadArgument);
+            }
+        }
+
+        // Reserve one more page to align the mapping on the Virtual Machine page size.
+        let reservation = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                size + PAGE_SIZE,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            )
+        };
+
+        if reservation == libc::MAP_FAILED {
+            unsafe { libc::close(fd) };
+
+            return Err(HypervisorError::NoResources);
+        }
+
+        let aligned = (reservation as usize).next_multiple_of(PAGE_SIZE);
+
+        let address = unsafe {
+            libc::mmap(
+                aligned as *mut libc::c_void,
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                fd,
+                0,
+            )
+        };
+
+        if address == libc::MAP_FAILED {
+            unsafe {
+                libc::munmap(reservation, size + PAGE_SIZE);
+                libc::close(fd);
+            }
+
+            return Err(HypervisorError::NoResources);
+        }
+
+        Ok(SharedMemory {
+            reservation: reservation as *mut u8,
+            address: address as *mut u8,
+            size,
+            fd,
+        })
+    }
+
+    /// Gets the size of the region.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Gets the region.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.address, self.size) }
+    }
+
+    /// Gets the region mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.address, self.size) }
+    }
+
+    /// Map the region in the guest.
+    ///
+    /// # Safety
+    ///
+    /// The region must outlive the Virtual Machine, or be unmapped and deallocated first.
+    pub unsafe fn attach(
+        &self,
+        vm: &mut VirtualMachine,
+        guest_address: hv_ipa_t,
+        permission: MemoryPermission,
+    ) -> Result<MappingHandle> {
+        let allocation_handle = unsafe { vm.allocate_external(self.address, self.size)? };
+
+        vm.map(allocation_handle, guest_address, permission)
+            .inspect_err(|_| {
+                let _ = vm.deallocate(allocation_handle);
+            })
+    }
+
+    /// Write the device tree node describing the region mapped at a guest address.
+    pub fn write_fdt_node(&self, fdt: &mut FdtWriter, guest_address: hv_ipa_t) {
+        fdt.begin_node(&format!("shared-memory@{guest_address:x}"));
+        fdt.property_string("compatible", COMPATIBLE);
+        fdt.property_cells(
+            "reg",
+            &[
+                (guest_address >> 32) as u32,
+                guest_address as u32,
+                (self.size as u64 >> 32) as u32,
+                self.size as u32,
+            ],
+        );
+        fdt.end_node();
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.reservation as *mut libc::c_void, self.size + PAGE_SIZE);
+            libc::close(self.fd);
+        }
+    }
+}
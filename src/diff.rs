@@ -0,0 +1,180 @@
+//! Lockstep differential execution.
+//!
+//! A [DiffHarness] compares the state of a vCPU against a reference implementation (e.g. QEMU
+//! driven through its gdb stub, or another Virtual Machine) at every point chosen by the caller
+//! (exits, breakpoints), and reports the first divergence.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::Result;
+use crate::reg::{GeneralRegisters, SystemRegister};
+use crate::vcpu::VirtualCpu;
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Architectural register state compared between executors.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RegisterState {
+    /// X0 to X30, PC and CPSR.
+    pub general: GeneralRegisters,
+
+    /// SP_EL0.
+    pub sp_el0: u64,
+
+    /// SP_EL1.
+    pub sp_el1: u64,
+}
+
+impl RegisterState {
+    /// Capture the register state of a vCPU.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn capture(vcpu: &mut VirtualCpu) -> Result<Self> {
+        Ok(RegisterState {
+            general: vcpu.get_general_registers()?,
+            sp_el0: vcpu.get_system_register(SystemRegister::SP_EL0)?,
+            sp_el1: vcpu.get_system_register(SystemRegister::SP_EL1)?,
+        })
+    }
+
+    /// Gets all register values, in the order of [REGISTER_NAMES].
+    pub fn values(&self) -> [u64; REGISTER_COUNT] {
+        let mut result = [0; REGISTER_COUNT];
+
+        result[..31].copy_from_slice(&self.general.x);
+        result[31] = self.sp_el0;
+        result[32] = self.sp_el1;
+        result[33] = self.general.pc;
+        result[34] = self.general.cpsr;
+
+        result
+    }
+
+    /// Find the first register differing from another state.
+    pub fn compare(&self, reference: &RegisterState) -> Option<Divergence> {
+        self.values()
+            .into_iter()
+            .zip(reference.values())
+            .enumerate()
+            .find(|(_, (ours, theirs))| ours != theirs)
+            .map(|(index, (ours, theirs))| Divergence::Register {
+                name: REGISTER_NAMES[index],
+                ours,
+                reference: theirs,
+            })
+    }
+}
+
+/// Number of registers in a [RegisterState].
+pub const REGISTER_COUNT: usize = 35;
+
+/// Names of the registers of [RegisterState], in comparison order.
+pub const REGISTER_NAMES: [&str; REGISTER_COUNT] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30", "sp_el0", "sp_el1", "pc", "cpsr",
+];
+
+/// The first difference found between the vCPU and the reference.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Divergence {
+    /// A register differs.
+    Register {
+        /// The register name.
+        name: &'static str,
+
+        /// The value in the vCPU.
+        ours: u64,
+
+        /// The value in the reference.
+        reference: u64,
+    },
+
+    /// A byte of guest memory differs.
+    Memory {
+        /// The guest address of the byte.
+        address: hv_ipa_t,
+
+        /// The value in the Virtual Machine.
+        ours: u8,
+
+        /// The value in the reference.
+        reference: u8,
+    },
+}
+
+/// A reference implementation executing the same guest.
+pub trait ReferenceExecutor {
+    /// Run the reference until it reaches the same point as the vCPU, whose state is given, and
+    /// return its register state.
+    fn advance_to(&mut self, state: &RegisterState) -> Result<RegisterState>;
+
+    /// Read the guest memory of the reference.
+    fn read_memory(&mut self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()>;
+}
+
+/// Compare a vCPU against a reference implementation.
+#[derive(Debug)]
+pub struct DiffHarness<R: ReferenceExecutor> {
+    /// The reference implementation.
+    reference: R,
+
+    /// Guest memory ranges compared at every check.
+    memory_ranges: Vec<(hv_ipa_t, usize)>,
+}
+
+impl<R: ReferenceExecutor> DiffHarness<R> {
+    /// Create a new harness, both sides should start from the same snapshot.
+    pub fn new(reference: R) -> Self {
+        DiffHarness {
+            reference,
+            memory_ranges: Vec::new(),
+        }
+    }
+
+    /// Compare a guest memory range at every check.
+    pub fn watch_memory(&mut self, address: hv_ipa_t, size: usize) {
+        self.memory_ranges.push((address, size));
+    }
+
+    /// Gets the reference implementation.
+    pub fn reference(&mut self) -> &mut R {
+        &mut self.reference
+    }
+
+    /// Bring the reference to the point reached by the vCPU and compare both states.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn check(
+        &mut self,
+        vm: &VirtualMachine,
+        vcpu: &mut VirtualCpu,
+    ) -> Result<Option<Divergence>> {
+        let ours = RegisterState::capture(vcpu)?;
+        let theirs = self.reference.advance_to(&ours)?;
+
+        if let Some(divergence) = ours.compare(&theirs) {
+            return Ok(Some(divergence));
+        }
+
+        for (address, size) in self.memory_ranges.iter() {
+            let mut ours = vec![0; *size];
+            let mut theirs = vec![0; *size];
+
+            vm.read_memory(*address, &mut ours)?;
+            self.reference.read_memory(*address, &mut theirs)?;
+
+            if let Some(offset) = ours.iter().zip(theirs.iter()).position(|(a, b)| a != b) {
+                return Ok(Some(Divergence::Memory {
+                    address: address + offset as u64,
+                    ours: ours[offset],
+                    reference: theirs[offset],
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -39,6 +39,12 @@ pub enum HypervisorError {
     /// A memory address was misaligned
     MisalignedAddress,
 
+    /// A guest address isn't backed by any mapping.
+    UnmappedAddress,
+
+    /// The operation must be done in the thread owning the vCPU.
+    WrongThread,
+
     /// An unknown error was returned.
     Unknown(i32),
 }
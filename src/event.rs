@@ -0,0 +1,75 @@
+//! Virtual Machine lifecycle events.
+
+use crate::bindings::hv_vcpu_t;
+use crate::device::sbsa_watchdog::WatchdogAction;
+use crate::guest_panic::PanicContext;
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// An event concerning the whole Virtual Machine, to be handled by the embedder.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VmEvent {
+    /// A vCPU was created.
+    VcpuCreated(hv_vcpu_t),
+
+    /// A vCPU stopped running for good, as reported by the embedder.
+    VcpuStopped(hv_vcpu_t),
+
+    /// The guest requested to be powered off.
+    PowerOff,
+
+    /// The guest requested to be reset, or a watchdog expired with a reset action.
+    Reset,
+
+    /// A watchdog expired with a report action.
+    WatchdogTimeout,
+
+    /// A vCPU exit couldn't be handled, as reported by the embedder.
+    FatalExit(hv_vcpu_t),
+
+    /// The guest panicked, as detected by a [crate::guest_panic::PanicDetector].
+    GuestPanicked(PanicContext),
+}
+
+impl From<WatchdogAction> for VmEvent {
+    fn from(value: WatchdogAction) -> VmEvent {
+        match value {
+            WatchdogAction::Reset => VmEvent::Reset,
+            WatchdogAction::Report => VmEvent::WatchdogTimeout,
+        }
+    }
+}
+
+/// A callback receiving [VmEvent].
+pub type EventCallback = Box<dyn FnMut(&VmEvent)>;
+
+/// A registry of callbacks interested in [VmEvent].
+#[derive(Default)]
+pub(crate) struct EventRegistry {
+    /// List of all callbacks.
+    callbacks: Vec<EventCallback>,
+}
+
+impl core::fmt::Debug for EventRegistry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EventRegistry")
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl EventRegistry {
+    /// Register a new callback.
+    pub fn register(&mut self, callback: EventCallback) {
+        self.callbacks.push(callback);
+    }
+
+    /// Deliver an event to all callbacks, in registration order.
+    pub fn notify(&mut self, event: &VmEvent) {
+        for callback in self.callbacks.iter_mut() {
+            callback(event);
+        }
+    }
+}
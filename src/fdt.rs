@@ -0,0 +1,844 @@
+//! Flattened Device Tree (DTB) writer.
+//!
+//! Blobs can also be parsed back into a [FdtNode] tree, to apply device tree overlays on top of a
+//! generated tree before it's packed into guest memory.
+
+use crate::err::{HypervisorError, Result};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// FDT header magic.
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+/// FDT version produced by the writer.
+const FDT_VERSION: u32 = 17;
+
+/// Oldest FDT version compatible with the produced blob.
+const FDT_LAST_COMPATIBLE_VERSION: u32 = 16;
+
+/// Size of the FDT header.
+const FDT_HEADER_SIZE: usize = 40;
+
+/// Size of an entry of the memory reservation block.
+const FDT_RESERVE_ENTRY_SIZE: usize = 16;
+
+/// Start of a node.
+const FDT_BEGIN_NODE: u32 = 0x1;
+
+/// End of a node.
+const FDT_END_NODE: u32 = 0x2;
+
+/// A property.
+const FDT_PROP: u32 = 0x3;
+
+/// No operation.
+const FDT_NOP: u32 = 0x4;
+
+/// End of the structure block.
+const FDT_END: u32 = 0x9;
+
+/// Incremental writer of a Flattened Device Tree blob.
+#[derive(Debug, Default)]
+pub struct FdtWriter {
+    /// The structure block.
+    structure: Vec<u8>,
+
+    /// The strings block.
+    strings: Vec<u8>,
+
+    /// Current depth of opened nodes.
+    depth: usize,
+
+    /// The blob fields outside of the structure block.
+    header: FdtHeader,
+}
+
+/// Fields of a DTB blob outside of the node tree.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FdtHeader {
+    /// The memory reservation block, as (address, size).
+    pub reservations: Vec<(u64, u64)>,
+
+    /// The physical ID of the boot CPU.
+    pub boot_cpuid_phys: u32,
+}
+
+impl FdtWriter {
+    /// Create a new empty writer.
+    ///
+    /// The root node must be opened with an empty name.
+    pub fn new() -> Self {
+        FdtWriter::default()
+    }
+
+    /// Set the fields of the blob outside of the node tree.
+    pub fn set_header(&mut self, header: FdtHeader) {
+        self.header = header;
+    }
+
+    /// Add an entry to the memory reservation block.
+    pub fn add_reservation(&mut self, address: u64, size: u64) {
+        self.header.reservations.push((address, size));
+    }
+
+    /// Append a token to the structure block.
+    fn write_token(&mut self, token: u32) {
+        self.structure.extend_from_slice(&token.to_be_bytes());
+    }
+
+    /// Pad the structure block to a 4 bytes boundary.
+    fn align_structure(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+
+    /// Gets the offset of a property name in the strings block, inserting it if needed.
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+
+        while offset < self.strings.len() {
+            let end = offset
+                + self.strings[offset..]
+                    .iter()
+                    .position(|value| *value == 0)
+                    .unwrap();
+
+            if &self.strings[offset..end] == name.as_bytes() {
+                return offset as u32;
+            }
+
+            offset = end + 1;
+        }
+
+        let offset = self.strings.len();
+
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+
+        offset as u32
+    }
+
+    /// Open a new node.
+    pub fn begin_node(&mut self, name: &str) {
+        self.write_token(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align_structure();
+
+        self.depth += 1;
+    }
+
+    /// Close the last opened node.
+    pub fn end_node(&mut self) {
+        assert!(self.depth > 0, "No node to close!");
+
+        self.write_token(FDT_END_NODE);
+
+        self.depth -= 1;
+    }
+
+    /// Add a property with raw data to the current node.
+    pub fn property(&mut self, name: &str, data: &[u8]) {
+        let name_offset = self.string_offset(name);
+
+        self.write_token(FDT_PROP);
+        self.structure
+            .extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.structure.extend_from_slice(&name_offset.to_be_bytes());
+        self.structure.extend_from_slice(data);
+        self.align_structure();
+    }
+
+    /// Add an empty property to the current node.
+    pub fn property_null(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    /// Add a 32-bit cell property to the current node.
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    /// Add a 64-bit (two cells) property to the current node.
+    pub fn property_u64(&mut self, name: &str, value: u64) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    /// Add a list of 32-bit cells property to the current node.
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let data: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+
+        self.property(name, &data);
+    }
+
+    /// Add a string property to the current node.
+    pub fn property_string(&mut self, name: &str, value: &str) {
+        let mut data = Vec::with_capacity(value.len() + 1);
+
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+
+        self.property(name, &data);
+    }
+
+    /// Finish the tree and return the DTB blob.
+    pub fn finish(mut self) -> Vec<u8> {
+        assert!(self.depth == 0, "Some nodes are still opened!");
+
+        self.write_token(FDT_END);
+
+        // The reservation block is terminated by an empty entry.
+        let reserve_map_size = (self.header.reservations.len() + 1) * FDT_RESERVE_ENTRY_SIZE;
+        let structure_offset = FDT_HEADER_SIZE + reserve_map_size;
+        let strings_offset = structure_offset + self.structure.len();
+        let total_size = strings_offset + self.strings.len();
+
+        let mut result = Vec::with_capacity(total_size);
+
+        for value in [
+            FDT_MAGIC,
+            total_size as u32,
+            structure_offset as u32,
+            strings_offset as u32,
+            FDT_HEADER_SIZE as u32,
+            FDT_VERSION,
+            FDT_LAST_COMPATIBLE_VERSION,
+            self.header.boot_cpuid_phys,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            result.extend_from_slice(&value.to_be_bytes());
+        }
+
+        for (address, size) in self.header.reservations.iter() {
+            result.extend_from_slice(&address.to_be_bytes());
+            result.extend_from_slice(&size.to_be_bytes());
+        }
+
+        result.extend_from_slice(&[0; FDT_RESERVE_ENTRY_SIZE]);
+        result.extend_from_slice(&self.structure);
+        result.extend_from_slice(&self.strings);
+
+        result
+    }
+}
+
+/// A device tree node.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FdtNode {
+    /// The node name (empty for the root node).
+    pub name: String,
+
+    /// The properties, in order.
+    pub properties: Vec<(String, Vec<u8>)>,
+
+    /// The child nodes, in order.
+    pub children: Vec<FdtNode>,
+}
+
+/// Read a big-endian u32 from a blob.
+fn read_be32(blob: &[u8], offset: usize) -> Result<u32> {
+    blob.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or(HypervisorError::BadArgument)
+}
+
+/// Read a big-endian u64 from a blob.
+fn read_be64(blob: &[u8], offset: usize) -> Result<u64> {
+    blob.get(offset..offset + 8)
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or(HypervisorError::BadArgument)
+}
+
+/// Read a NUL-terminated string from a blob.
+fn read_string(blob: &[u8], offset: usize) -> Result<&str> {
+    let bytes = blob.get(offset..).ok_or(HypervisorError::BadArgument)?;
+    let end = bytes
+        .iter()
+        .position(|value| *value == 0)
+        .ok_or(HypervisorError::BadArgument)?;
+
+    core::str::from_utf8(&bytes[..end]).map_err(|_| HypervisorError::BadArgument)
+}
+
+impl FdtNode {
+    /// Create a new empty node.
+    pub fn new(name: &str) -> Self {
+        FdtNode {
+            name: String::from(name),
+            ..FdtNode::default()
+        }
+    }
+
+    /// Parse a DTB blob, returning the root node.
+    ///
+    /// The fields outside of the node tree are dropped, see [FdtNode::parse_with_header].
+    pub fn parse(blob: &[u8]) -> Result<FdtNode> {
+        FdtNode::parse_with_header(blob).map(|(root, _)| root)
+    }
+
+    /// Parse a DTB blob, returning the root node and the fields outside of the node tree, to be
+    /// given back to [FdtWriter::set_header] when writing it again.
+    pub fn parse_with_header(blob: &[u8]) -> Result<(FdtNode, FdtHeader)> {
+        if read_be32(blob, 0)? != FDT_MAGIC {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let mut header = FdtHeader {
+            reservations: Vec::new(),
+            boot_cpuid_phys: read_be32(blob, 28)?,
+        };
+
+        let mut offset = read_be32(blob, 16)? as usize;
+
+        loop {
+            let address = read_be64(blob, offset)?;
+            let size = read_be64(blob, offset + 8)?;
+
+            if address == 0 && size == 0 {
+                break;
+            }
+
+            header.reservations.push((address, size));
+            offset += FDT_RESERVE_ENTRY_SIZE;
+        }
+
+        let structure_offset = read_be32(blob, 8)? as usize;
+        let strings_offset = read_be32(blob, 12)? as usize;
+        let strings = blob
+            .get(strings_offset..)
+            .ok_or(HypervisorError::BadArgument)?;
+
+        let mut offset = structure_offset;
+        let mut stack: Vec<FdtNode> = Vec::new();
+
+        loop {
+            let token = read_be32(blob, offset)?;
+
+            offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_string(blob, offset)?;
+
+                    offset = (offset + name.len() + 1).next_multiple_of(4);
+                    stack.push(FdtNode::new(name));
+                }
+                FDT_END_NODE => {
+                    let node = stack.pop().ok_or(HypervisorError::BadArgument)?;
+
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => return Ok((node, header)),
+                    }
+                }
+                FDT_PROP => {
+                    let size = read_be32(blob, offset)? as usize;
+                    let name = read_string(strings, read_be32(blob, offset + 4)? as usize)?;
+                    let data = blob
+                        .get(offset + 8..offset + 8 + size)
+                        .ok_or(HypervisorError::BadArgument)?;
+
+                    stack
+                        .last_mut()
+                        .ok_or(HypervisorError::BadArgument)?
+                        .set_property(name, data);
+
+                    offset = (offset + 8 + size).next_multiple_of(4);
+                }
+                FDT_NOP => {}
+                _ => return Err(HypervisorError::BadArgument),
+            }
+        }
+    }
+
+    /// Write the node and its children.
+    pub fn write(&self, fdt: &mut FdtWriter) {
+        fdt.begin_node(&self.name);
+
+        for (name, data) in self.properties.iter() {
+            fdt.property(name, data);
+        }
+
+        for child in self.children.iter() {
+            child.write(fdt);
+        }
+
+        fdt.end_node();
+    }
+
+    /// Gets a property.
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(property_name, _)| property_name == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Gets a property mutably.
+    fn property_mut(&mut self, name: &str) -> Option<&mut Vec<u8>> {
+        self.properties
+            .iter_mut()
+            .find(|(property_name, _)| property_name == name)
+            .map(|(_, data)| data)
+    }
+
+    /// Add a property, replacing any property of the same name.
+    pub fn set_property(&mut self, name: &str, data: &[u8]) {
+        match self.property_mut(name) {
+            Some(value) => *value = data.to_vec(),
+            None => self.properties.push((String::from(name), data.to_vec())),
+        }
+    }
+
+    /// Gets a child node by name.
+    pub fn child(&self, name: &str) -> Option<&FdtNode> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Gets a child node by name mutably.
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut FdtNode> {
+        self.children.iter_mut().find(|child| child.name == name)
+    }
+
+    /// Find a node by absolute path.
+    pub fn find(&self, path: &str) -> Option<&FdtNode> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self, |node, component| node.child(component))
+    }
+
+    /// Find a node by absolute path mutably.
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut FdtNode> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self, |node, component| node.child_mut(component))
+    }
+
+    /// Gets the phandle of the node, if any.
+    fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|data| data.try_into().ok())
+            .map(u32::from_be_bytes)
+    }
+
+    /// Find the path of the node with a given phandle.
+    fn find_phandle(&self, phandle: u32, path: &mut String) -> bool {
+        if self.phandle() == Some(phandle) {
+            return true;
+        }
+
+        for child in self.children.iter() {
+            let length = path.len();
+
+            path.push('/');
+            path.push_str(&child.name);
+
+            if child.find_phandle(phandle, path) {
+                return true;
+            }
+
+            path.truncate(length);
+        }
+
+        false
+    }
+
+    /// Gets the highest phandle of the tree.
+    fn max_phandle(&self) -> u32 {
+        self.children
+            .iter()
+            .map(FdtNode::max_phandle)
+            .chain(self.phandle())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Add a value to every phandle of the tree.
+    fn shift_phandles(&mut self, delta: u32) -> Result<()> {
+        for name in ["phandle", "linux,phandle"] {
+            if let Some(data) = self.property_mut(name)
+                && let Ok(value) = <[u8; 4]>::try_from(data.as_slice())
+            {
+                let value = u32::from_be_bytes(value)
+                    .checked_add(delta)
+                    .ok_or(HypervisorError::BadArgument)?;
+
+                *data = value.to_be_bytes().to_vec();
+            }
+        }
+
+        for child in self.children.iter_mut() {
+            child.shift_phandles(delta)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a value to the phandle references listed by a `__local_fixups__` node.
+    fn shift_local_references(&mut self, fixups: &FdtNode, delta: u32) -> Result<()> {
+        for (name, offsets) in fixups.properties.iter() {
+            let data = self
+                .property_mut(name)
+                .ok_or(HypervisorError::BadArgument)?;
+
+            for offset in offsets.chunks_exact(4) {
+                let offset = u32::from_be_bytes(offset.try_into().unwrap()) as usize;
+                let cell = read_be32(data, offset)?
+                    .checked_add(delta)
+                    .ok_or(HypervisorError::BadArgument)?;
+
+                data[offset..offset + 4].copy_from_slice(&cell.to_be_bytes());
+            }
+        }
+
+        for child_fixups in fixups.children.iter() {
+            self.child_mut(&child_fixups.name)
+                .ok_or(HypervisorError::BadArgument)?
+                .shift_local_references(child_fixups, delta)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the references of an overlay to labels of this tree (`__fixups__`).
+    fn resolve_fixups(&self, overlay: &mut FdtNode) -> Result<()> {
+        let Some(fixups) = overlay.child("__fixups__").cloned() else {
+            return Ok(());
+        };
+
+        let symbols = self.child("__symbols__");
+
+        for (label, locations) in fixups.properties.iter() {
+            let target = symbols
+                .and_then(|symbols| symbols.property(label))
+                .and_then(|path| path.split(|value| *value == 0).next())
+                .and_then(|path| core::str::from_utf8(path).ok())
+                .and_then(|path| self.find(path))
+                .and_then(FdtNode::phandle)
+                .ok_or(HypervisorError::BadArgument)?;
+
+            // Each location is a "path:property:offset" string.
+            for location in locations.split(|value| *value == 0) {
+                if location.is_empty() {
+                    continue;
+                }
+
+                let location =
+                    core::str::from_utf8(location).map_err(|_| HypervisorError::BadArgument)?;
+                let mut parts = location.rsplitn(3, ':');
+
+                let (Some(offset), Some(property), Some(path)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(HypervisorError::BadArgument);
+                };
+
+                let offset: usize = offset.parse().map_err(|_| HypervisorError::BadArgument)?;
+                let data = overlay
+                    .find_mut(path)
+                    .and_then(|node| node.property_mut(property))
+                    .and_then(|data| data.get_mut(offset..offset + 4))
+                    .ok_or(HypervisorError::BadArgument)?;
+
+                data.copy_from_slice(&target.to_be_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merge the contents of an overlay node into this node.
+    fn merge(&mut self, overlay: &FdtNode) {
+        for (name, data) in overlay.properties.iter() {
+            self.set_property(name, data);
+        }
+
+        for child in overlay.children.iter() {
+            match self.child_mut(&child.name) {
+                Some(existing) => existing.merge(child),
+                None => self.children.push(child.clone()),
+            }
+        }
+    }
+
+    /// Add the labels of an overlay (`__symbols__`) to this tree, `targets` giving the target
+    /// path of each fragment.
+    fn merge_symbols(&mut self, overlay: &FdtNode, targets: &[(&str, String)]) -> Result<()> {
+        let Some(symbols) = overlay.child("__symbols__") else {
+            return Ok(());
+        };
+
+        for (label, path) in symbols.properties.iter() {
+            let path = read_string(path, 0)?;
+
+            // Labels of overlay nodes are "/fragment/__overlay__/..." paths.
+            let Some(path) = targets.iter().find_map(|(fragment, target)| {
+                let rest = path
+                    .strip_prefix('/')?
+                    .strip_prefix(fragment)?
+                    .strip_prefix("/__overlay__")
+                    .filter(|rest| rest.is_empty() || rest.starts_with('/'))?;
+
+                match (target.trim_end_matches('/'), rest) {
+                    ("", "") => Some(String::from("/")),
+                    (target, rest) => Some(format!("{target}{rest}")),
+                }
+            }) else {
+                continue;
+            };
+
+            let mut data = path.into_bytes();
+
+            data.push(0);
+
+            if self.child("__symbols__").is_none() {
+                self.children.push(FdtNode::new("__symbols__"));
+            }
+
+            self.child_mut("__symbols__")
+                .ok_or(HypervisorError::BadArgument)?
+                .set_property(label, &data);
+        }
+
+        Ok(())
+    }
+
+    /// Apply a device tree overlay (as compiled by `dtc -@`) on this root node.
+    ///
+    /// Fragments are located by `target-path` or `target` phandle. References to labels of the
+    /// base tree (`__fixups__`) are resolved through its `__symbols__` node, and the phandles
+    /// defined by the overlay (`__local_fixups__`) are renumbered above the ones of the base tree,
+    /// failing with [HypervisorError::BadArgument] if they don't fit in 32 bits.
+    /// The labels defined by the overlay are added to the `__symbols__` node of the base tree, so
+    /// that later overlays can reference them.
+    pub fn apply_overlay(&mut self, overlay: &FdtNode) -> Result<()> {
+        let mut overlay = overlay.clone();
+
+        let delta = self.max_phandle();
+
+        if delta != 0 {
+            overlay.shift_phandles(delta)?;
+
+            if let Some(local_fixups) = overlay.child("__local_fixups__").cloned() {
+                overlay.shift_local_references(&local_fixups, delta)?;
+            }
+        }
+
+        self.resolve_fixups(&mut overlay)?;
+
+        let mut targets = Vec::new();
+
+        for fragment in overlay.children.iter() {
+            let Some(contents) = fragment.child("__overlay__") else {
+                continue;
+            };
+
+            let target_path = if let Some(path) = fragment.property("target-path") {
+                String::from(read_string(path, 0)?)
+            } else if let Some(phandle) = fragment.property("target") {
+                let phandle = read_be32(phandle, 0)?;
+                let mut path = String::new();
+
+                if !self.find_phandle(phandle, &mut path) {
+                    return Err(HypervisorError::BadArgument);
+                }
+
+                path
+            } else {
+                return Err(HypervisorError::BadArgument);
+            };
+
+            self.find_mut(&target_path)
+                .ok_or(HypervisorError::BadArgument)?
+                .merge(contents);
+
+            targets.push((fragment.name.as_str(), target_path));
+        }
+
+        self.merge_symbols(&overlay, &targets)
+    }
+}
+
+/// Apply device tree overlay blobs on top of a base DTB blob, returning the resulting blob.
+///
+/// The memory reservations and boot CPU of the base blob are kept.
+pub fn apply_overlays(base: &[u8], overlays: &[&[u8]]) -> Result<Vec<u8>> {
+    let (mut root, header) = FdtNode::parse_with_header(base)?;
+
+    for overlay in overlays {
+        root.apply_overlay(&FdtNode::parse(overlay)?)?;
+    }
+
+    let mut fdt = FdtWriter::new();
+
+    fdt.set_header(header);
+    root.write(&mut fdt);
+
+    Ok(fdt.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a base tree with a labelled `uart` node of phandle 2.
+    fn base_tree() -> FdtNode {
+        let mut fdt = FdtWriter::new();
+
+        fdt.begin_node("");
+        fdt.property_u32("phandle", 1);
+        fdt.begin_node("uart");
+        fdt.property_u32("phandle", 2);
+        fdt.end_node();
+        fdt.begin_node("__symbols__");
+        fdt.property_string("uart", "/uart");
+        fdt.end_node();
+        fdt.end_node();
+
+        FdtNode::parse(&fdt.finish()).unwrap()
+    }
+
+    /// Build an overlay adding a `device` node of phandle `phandle` to the root, whose `self`
+    /// property refers to itself and `serial` property refers to the `uart` label of the base.
+    fn overlay_tree(phandle: u32) -> FdtNode {
+        let mut fdt = FdtWriter::new();
+
+        fdt.begin_node("");
+        fdt.begin_node("fragment@0");
+        fdt.property_string("target-path", "/");
+        fdt.begin_node("__overlay__");
+        fdt.begin_node("device");
+        fdt.property_u32("phandle", phandle);
+        fdt.property_u32("self", phandle);
+        fdt.property_u32("serial", 0xFFFF_FFFF);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+        fdt.begin_node("__symbols__");
+        fdt.property_string("device", "/fragment@0/__overlay__/device");
+        fdt.end_node();
+        fdt.begin_node("__fixups__");
+        fdt.property_string("uart", "/fragment@0/__overlay__/device:serial:0");
+        fdt.end_node();
+        fdt.begin_node("__local_fixups__");
+        fdt.begin_node("fragment@0");
+        fdt.begin_node("__overlay__");
+        fdt.begin_node("device");
+        fdt.property_u32("self", 0);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+
+        FdtNode::parse(&fdt.finish()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_header() {
+        let mut fdt = FdtWriter::new();
+
+        fdt.set_header(FdtHeader {
+            reservations: vec![(0x4000_0000, 0x2000)],
+            boot_cpuid_phys: 3,
+        });
+        fdt.add_reservation(0x8000_0000, 0x1000);
+        fdt.begin_node("");
+        fdt.property_string("compatible", "linux,dummy-virt");
+        fdt.property_cells("reg", &[0, 0x4000_0000, 0, 0x1000_0000]);
+        fdt.begin_node("chosen");
+        fdt.property_null("ranges");
+        fdt.end_node();
+        fdt.end_node();
+
+        let blob = fdt.finish();
+        let (root, header) = FdtNode::parse_with_header(&blob).unwrap();
+
+        assert_eq!(
+            header.reservations,
+            vec![(0x4000_0000, 0x2000), (0x8000_0000, 0x1000)]
+        );
+        assert_eq!(header.boot_cpuid_phys, 3);
+        assert_eq!(
+            root.property("compatible"),
+            Some(&b"linux,dummy-virt\0"[..])
+        );
+        assert_eq!(
+            root.child("chosen").unwrap().property("ranges"),
+            Some(&[][..])
+        );
+
+        let mut fdt = FdtWriter::new();
+
+        fdt.set_header(header);
+        root.write(&mut fdt);
+
+        assert_eq!(fdt.finish(), blob);
+    }
+
+    #[test]
+    fn parse_rejects_bad_magic() {
+        let mut blob = FdtWriter::new().finish();
+
+        blob[0] = 0;
+
+        assert!(matches!(
+            FdtNode::parse(&blob),
+            Err(HypervisorError::BadArgument)
+        ));
+    }
+
+    #[test]
+    fn overlay_resolves_fixups_and_renumbers_local_phandles() {
+        let mut root = base_tree();
+
+        root.apply_overlay(&overlay_tree(1)).unwrap();
+
+        let device = root.find("/device").unwrap();
+
+        // Phandles of the overlay are moved above the highest one of the base (2).
+        assert_eq!(device.property("phandle"), Some(&3u32.to_be_bytes()[..]));
+        assert_eq!(device.property("self"), Some(&3u32.to_be_bytes()[..]));
+        assert_eq!(device.property("serial"), Some(&2u32.to_be_bytes()[..]));
+    }
+
+    #[test]
+    fn overlay_symbols_are_merged() {
+        let mut root = base_tree();
+
+        root.apply_overlay(&overlay_tree(1)).unwrap();
+
+        let symbols = root.child("__symbols__").unwrap();
+
+        assert_eq!(symbols.property("uart"), Some(&b"/uart\0"[..]));
+        assert_eq!(symbols.property("device"), Some(&b"/device\0"[..]));
+    }
+
+    #[test]
+    fn overlay_fixup_to_unknown_label_fails() {
+        let mut root = base_tree();
+
+        root.child_mut("__symbols__").unwrap().properties.clear();
+
+        assert!(matches!(
+            root.apply_overlay(&overlay_tree(1)),
+            Err(HypervisorError::BadArgument)
+        ));
+    }
+
+    #[test]
+    fn overlay_phandle_overflow_fails() {
+        let mut root = base_tree();
+
+        assert!(matches!(
+            root.apply_overlay(&overlay_tree(u32::MAX - 1)),
+            Err(HypervisorError::BadArgument)
+        ));
+    }
+}
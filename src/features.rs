@@ -0,0 +1,384 @@
+//! CPU feature ID registers helpers.
+
+use crate::bindings::hv_sme_config_get_max_svl_bytes;
+use crate::err::{HypervisorError, Result, convert_hv_return};
+use crate::reg::{FeatureRegister, SystemRegister};
+use crate::vcpu::{VirtualCpu, VirtualCpuConfiguration};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Check if a system register is a feature ID register that can be overridden.
+fn is_feature_id_register(register: SystemRegister) -> bool {
+    matches!(
+        register,
+        SystemRegister::ID_AA64PFR0_EL1
+            | SystemRegister::ID_AA64PFR1_EL1
+            | SystemRegister::ID_AA64DFR0_EL1
+            | SystemRegister::ID_AA64DFR1_EL1
+            | SystemRegister::ID_AA64ISAR0_EL1
+            | SystemRegister::ID_AA64ISAR1_EL1
+            | SystemRegister::ID_AA64MMFR0_EL1
+            | SystemRegister::ID_AA64MMFR1_EL1
+            | SystemRegister::ID_AA64MMFR2_EL1
+    )
+}
+
+/// An override of some bits of a feature ID register.
+#[derive(Copy, Clone, Debug)]
+struct FeatureOverride {
+    /// The feature ID register.
+    register: SystemRegister,
+
+    /// The bits being overridden.
+    mask: u64,
+
+    /// The value of the overridden bits.
+    value: u64,
+}
+
+/// A set of values to report to the guest in the feature ID registers (ID_AA64*).
+///
+/// The Hypervisor doesn't trap guest reads of the ID registers, instead the overridden values are
+/// written to the vCPU registers and the guest reads them directly. Overrides should only hide or
+/// downgrade features: advertising a feature the host doesn't implement leads to undefined
+/// instruction exceptions in the guest.
+#[derive(Clone, Debug, Default)]
+pub struct FeatureOverrides {
+    /// List of all overrides.
+    overrides: Vec<FeatureOverride>,
+}
+
+impl FeatureOverrides {
+    /// Create an empty set of overrides.
+    pub fn new() -> Self {
+        FeatureOverrides::default()
+    }
+
+    /// Override a field of `width` bits at `shift` in a feature ID register.
+    pub fn set_field(
+        &mut self,
+        register: SystemRegister,
+        shift: u32,
+        width: u32,
+        value: u64,
+    ) -> Result<&mut Self> {
+        if !is_feature_id_register(register) || width == 0 || shift + width > 64 {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let mask = (u64::MAX >> (64 - width)) << shift;
+
+        self.overrides.push(FeatureOverride {
+            register,
+            mask,
+            value: (value << shift) & mask,
+        });
+
+        Ok(self)
+    }
+
+    /// Hide a 4 bits feature field by setting it to zero (not implemented).
+    pub fn hide_field(&mut self, register: SystemRegister, shift: u32) -> Result<&mut Self> {
+        self.set_field(register, shift, 4, 0)
+    }
+
+    /// Hide SVE (ID_AA64PFR0_EL1.SVE).
+    pub fn hide_sve(&mut self) -> Result<&mut Self> {
+        self.hide_field(SystemRegister::ID_AA64PFR0_EL1, 32)
+    }
+
+    /// Hide SME (ID_AA64PFR1_EL1.SME).
+    pub fn hide_sme(&mut self) -> Result<&mut Self> {
+        self.hide_field(SystemRegister::ID_AA64PFR1_EL1, 24)
+    }
+
+    /// Hide pointer authentication (ID_AA64ISAR1_EL1.{APA, API, GPA, GPI}).
+    pub fn hide_pointer_authentication(&mut self) -> Result<&mut Self> {
+        self.hide_field(SystemRegister::ID_AA64ISAR1_EL1, 4)?
+            .hide_field(SystemRegister::ID_AA64ISAR1_EL1, 8)?
+            .hide_field(SystemRegister::ID_AA64ISAR1_EL1, 24)?
+            .hide_field(SystemRegister::ID_AA64ISAR1_EL1, 28)
+    }
+
+    /// Hide branch target identification (ID_AA64PFR1_EL1.BT).
+    pub fn hide_branch_target_identification(&mut self) -> Result<&mut Self> {
+        self.hide_field(SystemRegister::ID_AA64PFR1_EL1, 0)
+    }
+
+    /// Compute the value to report for a feature ID register given its hardware value.
+    pub fn apply_to_value(&self, register: SystemRegister, value: u64) -> u64 {
+        self.overrides
+            .iter()
+            .filter(|entry| entry.register == register)
+            .fold(value, |result, entry| (result & !entry.mask) | entry.value)
+    }
+
+    /// Apply the overrides to a vCPU.
+    ///
+    /// Registers whose value already matches aren't written.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn apply(&self, vcpu: &mut VirtualCpu) -> Result<()> {
+        let mut registers: Vec<SystemRegister> = Vec::new();
+
+        for entry in self.overrides.iter() {
+            if !registers.contains(&entry.register) {
+                registers.push(entry.register);
+            }
+        }
+
+        for register in registers {
+            let hardware_value = vcpu.get_system_register(register)?;
+            let value = self.apply_to_value(register, hardware_value);
+
+            if value != hardware_value {
+                vcpu.set_system_register(register, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Level of Memory Tagging Extension support (ID_AA64PFR1_EL1.MTE).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MteSupport {
+    /// MTE isn't implemented.
+    None,
+
+    /// Only the EL0 instructions are implemented (FEAT_MTE).
+    InstructionsOnly,
+
+    /// Tag checking is implemented (FEAT_MTE2).
+    Full,
+
+    /// Asymmetric tag checking is implemented (FEAT_MTE3).
+    Asymmetric,
+}
+
+impl From<u64> for MteSupport {
+    fn from(value: u64) -> MteSupport {
+        match (value >> 8) & 0xF {
+            0 => MteSupport::None,
+            1 => MteSupport::InstructionsOnly,
+            2 => MteSupport::Full,
+            _ => MteSupport::Asymmetric,
+        }
+    }
+}
+
+impl VirtualCpuConfiguration {
+    /// Gets the level of MTE support reported to vCPUs created with this configuration.
+    ///
+    /// The Hypervisor doesn't expose the MTE system registers (GCR_EL1, RGSR_EL1, TFSR_EL1) nor
+    /// the allocation tags of guest memory, so guests can only use MTE if this reports support.
+    pub fn get_mte_support(&self) -> Result<MteSupport> {
+        self.get_feature_register(FeatureRegister::ID_AA64PFR1_EL1)
+            .map(MteSupport::from)
+    }
+}
+
+/// Extract a 4 bits ID register field.
+fn field(value: u64, shift: u32) -> u64 {
+    (value >> shift) & 0xF
+}
+
+/// Summary of the CPU features reported to the guest, decoded from the feature ID registers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FeatureReport {
+    /// Advanced SIMD (NEON).
+    pub advanced_simd: bool,
+
+    /// AES instructions.
+    pub aes: bool,
+
+    /// PMULL instructions.
+    pub pmull: bool,
+
+    /// SHA1 instructions.
+    pub sha1: bool,
+
+    /// SHA256 instructions.
+    pub sha256: bool,
+
+    /// SHA512 instructions.
+    pub sha512: bool,
+
+    /// SHA3 instructions.
+    pub sha3: bool,
+
+    /// CRC32 instructions.
+    pub crc32: bool,
+
+    /// Large System Extensions atomics.
+    pub atomics: bool,
+
+    /// Dot product instructions.
+    pub dot_product: bool,
+
+    /// BFloat16 instructions.
+    pub bf16: bool,
+
+    /// Int8 matrix multiplication instructions.
+    pub i8mm: bool,
+
+    /// Random number instructions (RNDR).
+    pub rng: bool,
+
+    /// Address pointer authentication (APA or API).
+    pub pointer_authentication: bool,
+
+    /// Generic pointer authentication (GPA or GPI).
+    pub generic_pointer_authentication: bool,
+
+    /// Branch Target Identification.
+    pub branch_target_identification: bool,
+
+    /// Speculative Store Bypass Safe.
+    pub ssbs: bool,
+
+    /// Speculation Barrier instruction.
+    pub speculation_barrier: bool,
+
+    /// Data Independent Timing.
+    pub dit: bool,
+
+    /// Reliability, Availability and Serviceability extension.
+    pub ras: bool,
+
+    /// Scalable Vector Extension.
+    pub sve: bool,
+
+    /// Scalable Matrix Extension.
+    pub sme: bool,
+
+    /// Maximum SME streaming vector length in bytes, if SME is supported.
+    pub sme_max_vector_length: Option<usize>,
+
+    /// Level of Memory Tagging Extension support.
+    pub mte: MteSupport,
+
+    /// EL2 is implemented.
+    pub el2: bool,
+
+    /// Number of physical address bits (ID_AA64MMFR0_EL1.PARange).
+    pub physical_address_bits: u8,
+}
+
+impl FeatureReport {
+    /// Decode the report from the raw feature ID register values.
+    pub fn decode(pfr0: u64, pfr1: u64, isar0: u64, isar1: u64, mmfr0: u64) -> Self {
+        let sme = field(pfr1, 24) != 0;
+
+        FeatureReport {
+            advanced_simd: field(pfr0, 20) != 0xF,
+            aes: field(isar0, 4) >= 1,
+            pmull: field(isar0, 4) >= 2,
+            sha1: field(isar0, 8) >= 1,
+            sha256: field(isar0, 12) >= 1,
+            sha512: field(isar0, 12) >= 2,
+            sha3: field(isar0, 32) >= 1,
+            crc32: field(isar0, 16) >= 1,
+            atomics: field(isar0, 20) >= 2,
+            dot_product: field(isar0, 44) >= 1,
+            bf16: field(isar1, 44) >= 1,
+            i8mm: field(isar1, 52) >= 1,
+            rng: field(isar0, 60) >= 1,
+            pointer_authentication: field(isar1, 4) != 0 || field(isar1, 8) != 0,
+            generic_pointer_authentication: field(isar1, 24) != 0 || field(isar1, 28) != 0,
+            branch_target_identification: field(pfr1, 0) >= 1,
+            ssbs: field(pfr1, 4) >= 1,
+            speculation_barrier: field(isar1, 36) >= 1,
+            dit: field(pfr0, 48) >= 1,
+            ras: field(pfr0, 28) >= 1,
+            sve: field(pfr0, 32) >= 1,
+            sme,
+            sme_max_vector_length: None,
+            mte: MteSupport::from(pfr1),
+            el2: field(pfr0, 8) != 0,
+            physical_address_bits: match field(mmfr0, 0) {
+                0 => 32,
+                1 => 36,
+                2 => 40,
+                3 => 42,
+                4 => 44,
+                5 => 48,
+                _ => 52,
+            },
+        }
+    }
+
+    /// Gets the names of the supported features, using the Linux `/proc/cpuinfo` naming.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.advanced_simd, "asimd"),
+            (self.aes, "aes"),
+            (self.pmull, "pmull"),
+            (self.sha1, "sha1"),
+            (self.sha256, "sha2"),
+            (self.sha512, "sha512"),
+            (self.sha3, "sha3"),
+            (self.crc32, "crc32"),
+            (self.atomics, "atomics"),
+            (self.dot_product, "asimddp"),
+            (self.bf16, "bf16"),
+            (self.i8mm, "i8mm"),
+            (self.rng, "rng"),
+            (self.pointer_authentication, "paca"),
+            (self.generic_pointer_authentication, "pacg"),
+            (self.branch_target_identification, "bti"),
+            (self.ssbs, "ssbs"),
+            (self.speculation_barrier, "sb"),
+            (self.dit, "dit"),
+            (self.ras, "ras"),
+            (self.sve, "sve"),
+            (self.sme, "sme"),
+            (self.mte != MteSupport::None, "mte"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect()
+    }
+}
+
+impl core::fmt::Display for FeatureReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (index, name) in self.names().into_iter().enumerate() {
+            if index != 0 {
+                write!(f, " ")?;
+            }
+
+            write!(f, "{name}")?;
+        }
+
+        if let Some(length) = self.sme_max_vector_length {
+            write!(f, " (SME SVL {} bits)", length * 8)?;
+        }
+
+        write!(f, " ({}-bit PA)", self.physical_address_bits)
+    }
+}
+
+impl VirtualCpuConfiguration {
+    /// Gets a summary of the CPU features reported to vCPUs created with this configuration.
+    pub fn feature_report(&self) -> Result<FeatureReport> {
+        let mut report = FeatureReport::decode(
+            self.get_feature_register(FeatureRegister::ID_AA64PFR0_EL1)?,
+            self.get_feature_register(FeatureRegister::ID_AA64PFR1_EL1)?,
+            self.get_feature_register(FeatureRegister::ID_AA64ISAR0_EL1)?,
+            self.get_feature_register(FeatureRegister::ID_AA64ISAR1_EL1)?,
+            self.get_feature_register(FeatureRegister::ID_AA64MMFR0_EL1)?,
+        );
+
+        if report.sme {
+            let mut length = 0;
+
+            let ret = unsafe { hv_sme_config_get_max_svl_bytes(&mut length) };
+
+            report.sme_max_vector_length = convert_hv_return(ret).ok().map(|_| length);
+        }
+
+        Ok(report)
+    }
+}
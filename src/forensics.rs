@@ -0,0 +1,143 @@
+//! Forensic export of guest memory.
+//!
+//! Every guest mapping is written to its own raw file in a directory, along with an
+//! `index.json` describing each region (guest address, size, permission, optional label, file
+//! name and SHA-256 digest), so memory-forensics tools can consume the dump without knowing
+//! anything about ahvf.
+
+use crate::bindings::{
+    HV_MEMORY_EXEC, HV_MEMORY_READ, HV_MEMORY_WRITE, hv_ipa_t, hv_memory_flags_t,
+};
+use crate::err::{HypervisorError, Result};
+use crate::sha256::sha256;
+use crate::timeline::write_json_string;
+use crate::virtual_machine::{MappingHandle, MemoryPermission, VirtualMachine};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Name of the index file.
+pub const INDEX_FILE_NAME: &str = "index.json";
+
+/// A guest memory region written by a forensic dump.
+#[derive(Clone, Debug)]
+pub struct ForensicRegion {
+    /// The guest address of the region.
+    pub address: hv_ipa_t,
+
+    /// The size of the region.
+    pub size: usize,
+
+    /// The memory permission of the region.
+    pub permission: MemoryPermission,
+
+    /// The label given to the region, if any.
+    pub label: Option<String>,
+
+    /// The name of the file holding the region, relative to the dump directory.
+    pub file_name: String,
+
+    /// The SHA-256 digest of the region.
+    pub sha256: [u8; 32],
+}
+
+/// Format a permission as "rwx".
+fn permission_string(permission: MemoryPermission) -> String {
+    let flags = hv_memory_flags_t::from(permission);
+    let mut result = String::new();
+
+    for (flag, c) in [
+        (HV_MEMORY_READ, 'r'),
+        (HV_MEMORY_WRITE, 'w'),
+        (HV_MEMORY_EXEC, 'x'),
+    ] {
+        result.push(if flags & flag as u64 != 0 { c } else { '-' });
+    }
+
+    result
+}
+
+/// Build the JSON index of dumped regions.
+pub fn index_json(regions: &[ForensicRegion]) -> String {
+    let mut output = String::from("{\"regions\":[");
+
+    for (index, region) in regions.iter().enumerate() {
+        if index != 0 {
+            output.push(',');
+        }
+
+        let _ = write!(
+            output,
+            "{{\"address\":\"0x{:x}\",\"size\":{},\"permission\":\"{}\",\"label\":",
+            region.address,
+            region.size,
+            permission_string(region.permission)
+        );
+
+        match &region.label {
+            Some(label) => write_json_string(&mut output, label),
+            None => output.push_str("null"),
+        }
+
+        output.push_str(",\"file\":");
+        write_json_string(&mut output, &region.file_name);
+        output.push_str(",\"sha256\":\"");
+
+        for byte in region.sha256 {
+            let _ = write!(output, "{byte:02x}");
+        }
+
+        output.push_str("\"}");
+    }
+
+    output.push_str("]}");
+
+    output
+}
+
+impl VirtualMachine {
+    /// Write every guest mapping and the JSON index to a directory, creating it if needed.
+    ///
+    /// Regions can be labeled by mapping handle. Returns the description of the dumped regions.
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn forensic_dump<P: AsRef<std::path::Path>>(
+        &self,
+        directory: P,
+        labels: &[(MappingHandle, &str)],
+    ) -> Result<Vec<ForensicRegion>> {
+        let directory = directory.as_ref();
+
+        std::fs::create_dir_all(directory).map_err(|_| HypervisorError::Denied)?;
+
+        let mut regions = Vec::new();
+
+        for mapping in self.get_all_mapping_infos() {
+            let contents = &self.get_allocation_slice(mapping.allocation_handle)?[..mapping.size];
+            let file_name = format!("region-{:016x}.bin", mapping.address);
+
+            std::fs::write(directory.join(&file_name), contents)
+                .map_err(|_| HypervisorError::Denied)?;
+
+            regions.push(ForensicRegion {
+                address: mapping.address,
+                size: mapping.size,
+                permission: mapping.permission,
+                label: labels
+                    .iter()
+                    .find(|(handle, _)| *handle == mapping.mapping_handle)
+                    .map(|(_, label)| String::from(*label)),
+                file_name,
+                sha256: sha256(contents),
+            });
+        }
+
+        std::fs::write(directory.join(INDEX_FILE_NAME), index_json(&regions))
+            .map_err(|_| HypervisorError::Denied)?;
+
+        Ok(regions)
+    }
+}
@@ -0,0 +1,527 @@
+//! GDB remote debugging support (requires the `gdb` feature).
+//!
+//! [GdbTarget] implements the [gdbstub] target traits on top of a [VirtualCpu] and the memory of
+//! its [VirtualMachine], so that GDB or LLDB can attach to the guest: register and memory
+//! inspection, continue, single step and software breakpoints. Breakpoints and steps can be made
+//! conditional on register values, evaluated on the target.
+//!
+//! Guest addresses given by the debugger are used as guest physical addresses, which matches the
+//! guest view as long as its MMU is off or its memory identity mapped.
+
+use crate::debug::{DebugException, RegisterCondition, SoftwareBreakpoints, set_software_step};
+use crate::err::{HypervisorError, Result};
+use crate::reg::{GeneralRegisters, Register, SIMD_FP_REGISTERS, SystemRegister};
+use crate::vcpu::{VirtualCpu, VirtualCpuExitReason};
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use gdbstub::arch::{Arch, Registers};
+use gdbstub::common::Signal;
+use gdbstub::stub::SingleThreadStopReason;
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep,
+    SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::breakpoints::{
+    Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps,
+};
+use gdbstub::target::{Target, TargetError, TargetResult};
+
+/// Size of the registers in the GDB `g` packet layout.
+const REGISTERS_SIZE: usize = 31 * 8 + 8 + 8 + 4 + 32 * 16 + 4 + 4;
+
+/// PSTATE exception level and stack pointer selection field.
+const CPSR_MODE_MASK: u64 = 0xF;
+
+/// PSTATE mode EL1 using SP_EL1.
+const CPSR_MODE_EL1H: u64 = 0b0101;
+
+/// PSTATE mode EL2 using SP_EL2.
+const CPSR_MODE_EL2H: u64 = 0b1001;
+
+/// Target description of the registers exposed to the debugger.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<architecture>aarch64</architecture>
+<feature name="org.gnu.gdb.aarch64.core">
+<reg name="x0" bitsize="64" type="uint64"/>
+<reg name="x1" bitsize="64" type="uint64"/>
+<reg name="x2" bitsize="64" type="uint64"/>
+<reg name="x3" bitsize="64" type="uint64"/>
+<reg name="x4" bitsize="64" type="uint64"/>
+<reg name="x5" bitsize="64" type="uint64"/>
+<reg name="x6" bitsize="64" type="uint64"/>
+<reg name="x7" bitsize="64" type="uint64"/>
+<reg name="x8" bitsize="64" type="uint64"/>
+<reg name="x9" bitsize="64" type="uint64"/>
+<reg name="x10" bitsize="64" type="uint64"/>
+<reg name="x11" bitsize="64" type="uint64"/>
+<reg name="x12" bitsize="64" type="uint64"/>
+<reg name="x13" bitsize="64" type="uint64"/>
+<reg name="x14" bitsize="64" type="uint64"/>
+<reg name="x15" bitsize="64" type="uint64"/>
+<reg name="x16" bitsize="64" type="uint64"/>
+<reg name="x17" bitsize="64" type="uint64"/>
+<reg name="x18" bitsize="64" type="uint64"/>
+<reg name="x19" bitsize="64" type="uint64"/>
+<reg name="x20" bitsize="64" type="uint64"/>
+<reg name="x21" bitsize="64" type="uint64"/>
+<reg name="x22" bitsize="64" type="uint64"/>
+<reg name="x23" bitsize="64" type="uint64"/>
+<reg name="x24" bitsize="64" type="uint64"/>
+<reg name="x25" bitsize="64" type="uint64"/>
+<reg name="x26" bitsize="64" type="uint64"/>
+<reg name="x27" bitsize="64" type="uint64"/>
+<reg name="x28" bitsize="64" type="uint64"/>
+<reg name="x29" bitsize="64" type="uint64"/>
+<reg name="x30" bitsize="64" type="uint64"/>
+<reg name="sp" bitsize="64" type="data_ptr"/>
+<reg name="pc" bitsize="64" type="code_ptr"/>
+<reg name="cpsr" bitsize="32" type="uint32"/>
+</feature>
+<feature name="org.gnu.gdb.aarch64.fpu">
+<reg name="v0" bitsize="128" type="uint128"/>
+<reg name="v1" bitsize="128" type="uint128"/>
+<reg name="v2" bitsize="128" type="uint128"/>
+<reg name="v3" bitsize="128" type="uint128"/>
+<reg name="v4" bitsize="128" type="uint128"/>
+<reg name="v5" bitsize="128" type="uint128"/>
+<reg name="v6" bitsize="128" type="uint128"/>
+<reg name="v7" bitsize="128" type="uint128"/>
+<reg name="v8" bitsize="128" type="uint128"/>
+<reg name="v9" bitsize="128" type="uint128"/>
+<reg name="v10" bitsize="128" type="uint128"/>
+<reg name="v11" bitsize="128" type="uint128"/>
+<reg name="v12" bitsize="128" type="uint128"/>
+<reg name="v13" bitsize="128" type="uint128"/>
+<reg name="v14" bitsize="128" type="uint128"/>
+<reg name="v15" bitsize="128" type="uint128"/>
+<reg name="v16" bitsize="128" type="uint128"/>
+<reg name="v17" bitsize="128" type="uint128"/>
+<reg name="v18" bitsize="128" type="uint128"/>
+<reg name="v19" bitsize="128" type="uint128"/>
+<reg name="v20" bitsize="128" type="uint128"/>
+<reg name="v21" bitsize="128" type="uint128"/>
+<reg name="v22" bitsize="128" type="uint128"/>
+<reg name="v23" bitsize="128" type="uint128"/>
+<reg name="v24" bitsize="128" type="uint128"/>
+<reg name="v25" bitsize="128" type="uint128"/>
+<reg name="v26" bitsize="128" type="uint128"/>
+<reg name="v27" bitsize="128" type="uint128"/>
+<reg name="v28" bitsize="128" type="uint128"/>
+<reg name="v29" bitsize="128" type="uint128"/>
+<reg name="v30" bitsize="128" type="uint128"/>
+<reg name="v31" bitsize="128" type="uint128"/>
+<reg name="fpsr" bitsize="32" type="uint32"/>
+<reg name="fpcr" bitsize="32" type="uint32"/>
+</feature>
+</target>
+"#;
+
+/// The AArch64 architecture, as seen by the debugger.
+#[derive(Debug)]
+pub enum AArch64 {}
+
+impl Arch for AArch64 {
+    type Usize = u64;
+
+    type Registers = AArch64Registers;
+
+    type BreakpointKind = usize;
+
+    type RegId = ();
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
+
+/// The registers of a vCPU, as seen by the debugger.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AArch64Registers {
+    /// X0 to X30, the program counter and the PSTATE (sent as 32 bits).
+    pub general: GeneralRegisters,
+
+    /// The stack pointer of the current exception level.
+    pub sp: u64,
+
+    /// V0 to V31.
+    pub v: [u128; 32],
+
+    /// The floating-point status register.
+    pub fpsr: u32,
+
+    /// The floating-point control register.
+    pub fpcr: u32,
+}
+
+impl Registers for AArch64Registers {
+    type ProgramCounter = u64;
+
+    fn pc(&self) -> u64 {
+        self.general.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        let mut write_bytes = |bytes: &[u8]| bytes.iter().for_each(|byte| write_byte(Some(*byte)));
+
+        self.general
+            .x
+            .iter()
+            .for_each(|x| write_bytes(&x.to_le_bytes()));
+        write_bytes(&self.sp.to_le_bytes());
+        write_bytes(&self.general.pc.to_le_bytes());
+        write_bytes(&(self.general.cpsr as u32).to_le_bytes());
+        self.v.iter().for_each(|v| write_bytes(&v.to_le_bytes()));
+        write_bytes(&self.fpsr.to_le_bytes());
+        write_bytes(&self.fpcr.to_le_bytes());
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> core::result::Result<(), ()> {
+        if bytes.len() != REGISTERS_SIZE {
+            return Err(());
+        }
+
+        let mut offset = 0;
+        let mut next = |size: usize| {
+            let result = &bytes[offset..offset + size];
+
+            offset += size;
+
+            result
+        };
+
+        for x in self.general.x.iter_mut() {
+            *x = u64::from_le_bytes(next(8).try_into().unwrap());
+        }
+
+        self.sp = u64::from_le_bytes(next(8).try_into().unwrap());
+        self.general.pc = u64::from_le_bytes(next(8).try_into().unwrap());
+        self.general.cpsr = u64::from(u32::from_le_bytes(next(4).try_into().unwrap()));
+
+        for v in self.v.iter_mut() {
+            *v = u128::from_le_bytes(next(16).try_into().unwrap());
+        }
+
+        self.fpsr = u32::from_le_bytes(next(4).try_into().unwrap());
+        self.fpcr = u32::from_le_bytes(next(4).try_into().unwrap());
+
+        Ok(())
+    }
+}
+
+/// How the vCPU runs on the next [GdbTarget::run].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExecutionMode {
+    /// Run until a breakpoint or an exit.
+    Continue,
+
+    /// Execute a single instruction.
+    Step,
+}
+
+/// Outcome of a [GdbTarget::run].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GdbRunEvent {
+    /// The guest stopped for the debugger, the reason is to be reported to it.
+    Stop(SingleThreadStopReason<u64>),
+
+    /// The vCPU exited for a reason unrelated to debugging, to be handled by the caller before
+    /// calling [GdbTarget::run] again.
+    Exit(VirtualCpuExitReason),
+}
+
+/// A vCPU and its virtual machine, debuggable through [gdbstub].
+///
+/// The vCPU traps debug exceptions while the target exists. Exits handled by the vCPU hooks
+/// ([VirtualCpu::after_exit]) stay transparent to the debugger.
+pub struct GdbTarget<'a> {
+    /// The virtual machine.
+    vm: &'a mut VirtualMachine,
+
+    /// The debugged vCPU.
+    vcpu: &'a mut VirtualCpu,
+
+    /// The software breakpoints placed by the debugger.
+    breakpoints: SoftwareBreakpoints,
+
+    /// The execution mode requested by the debugger.
+    mode: ExecutionMode,
+
+    /// The breakpoint the vCPU is stopped at, if any.
+    stopped_at: Option<u64>,
+
+    /// The conditions that must all hold for a single step to stop.
+    step_conditions: Vec<RegisterCondition>,
+}
+
+impl<'a> GdbTarget<'a> {
+    /// Create a new target debugging a vCPU, enabling the trap of its debug exceptions.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn new(vm: &'a mut VirtualMachine, vcpu: &'a mut VirtualCpu) -> Result<Self> {
+        vcpu.set_trap_debug_exceptions(true)?;
+
+        Ok(GdbTarget {
+            vm,
+            vcpu,
+            breakpoints: SoftwareBreakpoints::new(),
+            mode: ExecutionMode::Continue,
+            stopped_at: None,
+            step_conditions: Vec::new(),
+        })
+    }
+
+    /// Gets the virtual machine.
+    pub fn vm(&mut self) -> &mut VirtualMachine {
+        self.vm
+    }
+
+    /// Gets the debugged vCPU.
+    pub fn vcpu(&mut self) -> &mut VirtualCpu {
+        self.vcpu
+    }
+
+    /// Gets the execution mode requested by the debugger.
+    pub fn execution_mode(&self) -> ExecutionMode {
+        self.mode
+    }
+
+    /// Set the conditions of a breakpoint placed by the debugger, so that hits only stop when
+    /// they all hold. They are evaluated on the target, without waking the debugger.
+    pub fn set_breakpoint_conditions(
+        &mut self,
+        address: u64,
+        conditions: Vec<RegisterCondition>,
+    ) -> Result<()> {
+        self.breakpoints.set_conditions(address, conditions)
+    }
+
+    /// Set the conditions that must all hold for a single step to stop, steps going on
+    /// otherwise.
+    pub fn set_step_conditions(&mut self, conditions: Vec<RegisterCondition>) {
+        self.step_conditions = conditions;
+    }
+
+    /// Remove all breakpoints from guest memory and stop trapping debug exceptions, when the
+    /// debugger detaches.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn detach(&mut self) -> Result<()> {
+        self.breakpoints.clear(self.vm)?;
+        self.stopped_at = None;
+
+        self.vcpu.set_trap_debug_exceptions(false)
+    }
+
+    /// Run the vCPU according to the execution mode requested by the debugger.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn run(&mut self) -> Result<GdbRunEvent> {
+        // Resuming from a breakpoint executes the original instruction first.
+        if let Some(address) = self.stopped_at.take()
+            && self.breakpoints.contains(address)
+            && self.vcpu.get_register(Register::PC)? == address
+        {
+            self.breakpoints.step_over(self.vm, self.vcpu, address)?;
+        } else if self.mode == ExecutionMode::Step {
+            self.set_software_step(true)?;
+        }
+
+        loop {
+            let reason = self.vcpu.run()?;
+
+            let VirtualCpuExitReason::Exception { exception } = reason else {
+                if reason == VirtualCpuExitReason::Cancelled {
+                    return Ok(GdbRunEvent::Stop(SingleThreadStopReason::Signal(
+                        Signal::SIGINT,
+                    )));
+                }
+
+                return Ok(GdbRunEvent::Exit(reason));
+            };
+
+            match self.vcpu.get_debug_exception(&exception)? {
+                Some(DebugException::SoftwareStep { .. }) => {
+                    let was_stepping_over =
+                        self.breakpoints.handle_software_step(self.vm, self.vcpu)?;
+
+                    // Steps go on while their conditions don't hold.
+                    if self.mode == ExecutionMode::Step
+                        && !RegisterCondition::all_match(&self.step_conditions, self.vcpu)?
+                    {
+                        self.set_software_step(true)?;
+
+                        continue;
+                    }
+
+                    // Stepping over a breakpoint while continuing goes on transparently.
+                    if self.mode == ExecutionMode::Step || !was_stepping_over {
+                        self.set_software_step(false)?;
+
+                        return Ok(GdbRunEvent::Stop(SingleThreadStopReason::DoneStep));
+                    }
+                }
+                Some(exception @ DebugException::Brk { address, .. })
+                    if self.breakpoints.is_hit(&exception, address) =>
+                {
+                    // Breakpoints whose conditions don't hold are stepped over.
+                    if !self.breakpoints.should_stop(self.vcpu, address)? {
+                        self.breakpoints.step_over(self.vm, self.vcpu, address)?;
+
+                        continue;
+                    }
+
+                    self.stopped_at = Some(address);
+
+                    return Ok(GdbRunEvent::Stop(SingleThreadStopReason::SwBreak(())));
+                }
+                Some(_) => {
+                    return Ok(GdbRunEvent::Stop(SingleThreadStopReason::Signal(
+                        Signal::SIGTRAP,
+                    )));
+                }
+                None => return Ok(GdbRunEvent::Exit(reason)),
+            }
+        }
+    }
+
+    /// Enable or disable software stepping of the next instruction.
+    fn set_software_step(&mut self, value: bool) -> Result<()> {
+        set_software_step(self.vcpu, value)
+    }
+
+    /// Gets the stack pointer register of the current exception level.
+    fn stack_pointer(&mut self) -> Result<SystemRegister> {
+        let cpsr = self.vcpu.get_register(Register::CPSR)?;
+
+        let result = match cpsr & CPSR_MODE_MASK {
+            CPSR_MODE_EL1H => SystemRegister::SP_EL1,
+            CPSR_MODE_EL2H => SystemRegister::SP_EL2,
+            _ => SystemRegister::SP_EL0,
+        };
+
+        Ok(result)
+    }
+}
+
+impl From<HypervisorError> for TargetError<HypervisorError> {
+    fn from(value: HypervisorError) -> Self {
+        TargetError::Fatal(value)
+    }
+}
+
+impl Target for GdbTarget<'_> {
+    type Arch = AArch64;
+
+    type Error = HypervisorError;
+
+    fn base_ops(&mut self) -> BaseOps<'_, AArch64, HypervisorError> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbTarget<'_> {
+    fn read_registers(&mut self, regs: &mut AArch64Registers) -> TargetResult<(), Self> {
+        regs.general = self.vcpu.get_general_registers()?;
+
+        let stack_pointer = self.stack_pointer()?;
+
+        regs.sp = self.vcpu.get_system_register(stack_pointer)?;
+
+        for (value, register) in regs.v.iter_mut().zip(SIMD_FP_REGISTERS) {
+            *value = self.vcpu.get_simd_fp_register(register)?.0;
+        }
+
+        regs.fpsr = self.vcpu.get_register(Register::FPSR)? as u32;
+        regs.fpcr = self.vcpu.get_register(Register::FPCR)? as u32;
+
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &AArch64Registers) -> TargetResult<(), Self> {
+        // The stack pointer is selected by the current PSTATE, so it goes first.
+        self.vcpu.set_general_registers(&regs.general)?;
+
+        let stack_pointer = self.stack_pointer()?;
+
+        self.vcpu.set_system_register(stack_pointer, regs.sp)?;
+
+        for (value, register) in regs.v.iter().zip(SIMD_FP_REGISTERS) {
+            self.vcpu.set_simd_fp_register(register, (*value).into())?;
+        }
+
+        self.vcpu
+            .set_register(Register::FPSR, u64::from(regs.fpsr))?;
+        self.vcpu
+            .set_register(Register::FPCR, u64::from(regs.fpcr))?;
+
+        Ok(())
+    }
+
+    fn read_addrs(&mut self, start_addr: u64, data: &mut [u8]) -> TargetResult<usize, Self> {
+        self.vm
+            .read_memory(start_addr, data)
+            .map_err(|_| TargetError::NonFatal)?;
+
+        Ok(data.len())
+    }
+
+    fn write_addrs(&mut self, start_addr: u64, data: &[u8]) -> TargetResult<(), Self> {
+        self.vm
+            .write_memory(start_addr, data)
+            .and_then(|_| {
+                self.vm
+                    .clean_instruction_cache_range(start_addr, data.len())
+            })
+            .map_err(|_| TargetError::NonFatal)
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadResume for GdbTarget<'_> {
+    fn resume(&mut self, _signal: Option<Signal>) -> core::result::Result<(), HypervisorError> {
+        self.mode = ExecutionMode::Continue;
+
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbTarget<'_> {
+    fn step(&mut self, _signal: Option<Signal>) -> core::result::Result<(), HypervisorError> {
+        self.mode = ExecutionMode::Step;
+
+        Ok(())
+    }
+}
+
+impl Breakpoints for GdbTarget<'_> {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbTarget<'_> {
+    fn add_sw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(self.breakpoints.insert(self.vm, addr).is_ok())
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(self.breakpoints.remove(self.vm, addr).is_ok())
+    }
+}
@@ -0,0 +1,432 @@
+//! In-kernel GICv3 (macOS 15 and later).
+//!
+//! The GIC is created once per Virtual Machine with [VirtualMachine::create_gic], after the
+//! Virtual Machine and before any vCPU. It emulates the distributor, one redistributor per vCPU
+//! and optionally an MSI frame, and delivers the architected timer and PMU interrupts itself.
+
+use crate::bindings::*;
+use crate::capabilities::require_macos;
+use crate::err::{HypervisorError, Result, convert_hv_return};
+use crate::vcpu::{VirtualCpu, os_release};
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use core::ffi::c_void;
+
+/// First macOS release with the GIC functions.
+const GIC_MIN_MACOS_VERSION: u32 = 15;
+
+/// Configuration of the GIC of a Virtual Machine.
+#[derive(Debug)]
+pub struct GicConfiguration {
+    /// Handle of the GIC configuration.
+    pub handle: hv_gic_config_t,
+}
+
+impl GicConfiguration {
+    /// Create a new GIC configuration.
+    ///
+    /// Fails with [HypervisorError::Unsupported] on macOS releases older than 15.
+    pub fn new() -> Result<Self> {
+        require_macos(GIC_MIN_MACOS_VERSION)?;
+
+        let handle = unsafe { hv_gic_config_create() };
+
+        if handle.is_null() {
+            return Err(HypervisorError::NoResources);
+        }
+
+        Ok(GicConfiguration { handle })
+    }
+
+    /// Set the guest address of the distributor (aligned on [distributor_base_alignment]).
+    pub fn set_distributor_base(&mut self, address: hv_ipa_t) -> Result<()> {
+        let ret = unsafe { hv_gic_config_set_distributor_base(self.handle, address) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Set the guest address of the redistributor region (aligned on
+    /// [redistributor_base_alignment]).
+    pub fn set_redistributor_base(&mut self, address: hv_ipa_t) -> Result<()> {
+        let ret = unsafe { hv_gic_config_set_redistributor_base(self.handle, address) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Set the guest address of the MSI frame (aligned on [msi_region_base_alignment]).
+    pub fn set_msi_region_base(&mut self, address: hv_ipa_t) -> Result<()> {
+        let ret = unsafe { hv_gic_config_set_msi_region_base(self.handle, address) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Set the range of SPIs that MSIs can be delivered to.
+    pub fn set_msi_interrupt_range(&mut self, base: u32, count: u32) -> Result<()> {
+        let ret = unsafe { hv_gic_config_set_msi_interrupt_range(self.handle, base, count) };
+
+        convert_hv_return(ret)
+    }
+}
+
+impl Drop for GicConfiguration {
+    fn drop(&mut self) {
+        unsafe {
+            os_release(self.handle as *mut c_void);
+        }
+    }
+}
+
+/// Interrupt delivered by the GIC itself.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GicInterrupt {
+    /// Performance monitor interrupt.
+    PerformanceMonitor,
+
+    /// GIC maintenance interrupt.
+    Maintenance,
+
+    /// EL2 physical timer interrupt.
+    El2PhysicalTimer,
+
+    /// EL1 virtual timer interrupt.
+    El1VirtualTimer,
+
+    /// EL1 physical timer interrupt.
+    El1PhysicalTimer,
+}
+
+impl From<GicInterrupt> for hv_gic_intid_t {
+    fn from(value: GicInterrupt) -> hv_gic_intid_t {
+        match value {
+            GicInterrupt::PerformanceMonitor => hv_gic_intid_t_HV_GIC_INT_PERFORMANCE_MONITOR,
+            GicInterrupt::Maintenance => hv_gic_intid_t_HV_GIC_INT_MAINTENANCE,
+            GicInterrupt::El2PhysicalTimer => hv_gic_intid_t_HV_GIC_INT_EL2_PHYSICAL_TIMER,
+            GicInterrupt::El1VirtualTimer => hv_gic_intid_t_HV_GIC_INT_EL1_VIRTUAL_TIMER,
+            GicInterrupt::El1PhysicalTimer => hv_gic_intid_t_HV_GIC_INT_EL1_PHYSICAL_TIMER,
+        }
+    }
+}
+
+/// GIC CPU interface system register.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum GicIccRegister {
+    /// ICC_PMR_EL1 register.
+    PMR_EL1,
+
+    /// ICC_BPR0_EL1 register.
+    BPR0_EL1,
+
+    /// ICC_AP0R0_EL1 register.
+    AP0R0_EL1,
+
+    /// ICC_AP1R0_EL1 register.
+    AP1R0_EL1,
+
+    /// ICC_RPR_EL1 register.
+    RPR_EL1,
+
+    /// ICC_BPR1_EL1 register.
+    BPR1_EL1,
+
+    /// ICC_CTLR_EL1 register.
+    CTLR_EL1,
+
+    /// ICC_SRE_EL1 register.
+    SRE_EL1,
+
+    /// ICC_IGRPEN0_EL1 register.
+    IGRPEN0_EL1,
+
+    /// ICC_IGRPEN1_EL1 register.
+    IGRPEN1_EL1,
+
+    /// ICC_SRE_EL2 register.
+    SRE_EL2,
+}
+
+impl From<GicIccRegister> for hv_gic_icc_reg_t {
+    fn from(value: GicIccRegister) -> hv_gic_icc_reg_t {
+        match value {
+            GicIccRegister::PMR_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_PMR_EL1,
+            GicIccRegister::BPR0_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_BPR0_EL1,
+            GicIccRegister::AP0R0_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_AP0R0_EL1,
+            GicIccRegister::AP1R0_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_AP1R0_EL1,
+            GicIccRegister::RPR_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_RPR_EL1,
+            GicIccRegister::BPR1_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_BPR1_EL1,
+            GicIccRegister::CTLR_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_CTLR_EL1,
+            GicIccRegister::SRE_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_SRE_EL1,
+            GicIccRegister::IGRPEN0_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_IGRPEN0_EL1,
+            GicIccRegister::IGRPEN1_EL1 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_IGRPEN1_EL1,
+            GicIccRegister::SRE_EL2 => hv_gic_icc_reg_t_HV_GIC_ICC_REG_SRE_EL2,
+        }
+    }
+}
+
+/// Query a size or alignment of the GIC.
+fn query_size(function: unsafe extern "C" fn(*mut usize) -> hv_return_t) -> Result<usize> {
+    require_macos(GIC_MIN_MACOS_VERSION)?;
+
+    let mut result = 0;
+
+    let ret = unsafe { function(&mut result) };
+
+    // Ensure no error got reported
+    convert_hv_return(ret)?;
+
+    Ok(result)
+}
+
+/// Gets the size of the distributor.
+pub fn distributor_size() -> Result<usize> {
+    query_size(hv_gic_get_distributor_size)
+}
+
+/// Gets the required alignment of the distributor guest address.
+pub fn distributor_base_alignment() -> Result<usize> {
+    query_size(hv_gic_get_distributor_base_alignment)
+}
+
+/// Gets the size of the whole redistributor region (for the maximum number of vCPUs).
+pub fn redistributor_region_size() -> Result<usize> {
+    query_size(hv_gic_get_redistributor_region_size)
+}
+
+/// Gets the size of the redistributor of a single vCPU.
+pub fn redistributor_size() -> Result<usize> {
+    query_size(hv_gic_get_redistributor_size)
+}
+
+/// Gets the required alignment of the redistributor region guest address.
+pub fn redistributor_base_alignment() -> Result<usize> {
+    query_size(hv_gic_get_redistributor_base_alignment)
+}
+
+/// Gets the size of the MSI frame.
+pub fn msi_region_size() -> Result<usize> {
+    query_size(hv_gic_get_msi_region_size)
+}
+
+/// Gets the required alignment of the MSI frame guest address.
+pub fn msi_region_base_alignment() -> Result<usize> {
+    query_size(hv_gic_get_msi_region_base_alignment)
+}
+
+/// Gets the range of supported SPIs, as the first INTID and the count.
+pub fn spi_interrupt_range() -> Result<(u32, u32)> {
+    require_macos(GIC_MIN_MACOS_VERSION)?;
+
+    let mut base = 0;
+    let mut count = 0;
+
+    let ret = unsafe { hv_gic_get_spi_interrupt_range(&mut base, &mut count) };
+
+    // Ensure no error got reported
+    convert_hv_return(ret)?;
+
+    Ok((base, count))
+}
+
+/// Handle of the GIC of the Virtual Machine.
+///
+/// The GIC is global to the Virtual Machine, handles can be copied and used from any thread.
+#[derive(Copy, Clone, Debug)]
+pub struct Gic {
+    /// Prevent construction outside of [VirtualMachine::create_gic].
+    _private: (),
+}
+
+impl VirtualMachine {
+    /// Create the GIC of the Virtual Machine.
+    ///
+    /// Fails with [HypervisorError::Unsupported] on macOS releases older than 15.
+    ///
+    /// **This must be called before creating any vCPU.**
+    pub fn create_gic(&mut self, config: &GicConfiguration) -> Result<Gic> {
+        require_macos(GIC_MIN_MACOS_VERSION)?;
+
+        let ret = unsafe { hv_gic_create(config.handle) };
+
+        convert_hv_return(ret)?;
+
+        self.has_gic = true;
+
+        Ok(Gic { _private: () })
+    }
+
+    /// Check if the GIC got created with [VirtualMachine::create_gic].
+    pub fn has_gic(&self) -> bool {
+        self.has_gic
+    }
+}
+
+impl Gic {
+    /// Set the level of a level-triggered SPI.
+    pub fn set_spi(&self, intid: u32, level: bool) -> Result<()> {
+        let ret = unsafe { hv_gic_set_spi(intid, level) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Send an MSI as if written to the MSI frame at a given guest address.
+    pub fn send_msi(&self, address: hv_ipa_t, intid: u32) -> Result<()> {
+        let ret = unsafe { hv_gic_send_msi(address, intid) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Gets the INTID of an interrupt delivered by the GIC itself.
+    pub fn intid(&self, interrupt: GicInterrupt) -> Result<u32> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_gic_get_intid(hv_gic_intid_t::from(interrupt), &mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Gets a distributor register, by offset in the distributor.
+    pub fn get_distributor_register(&self, offset: hv_gic_distributor_reg_t) -> Result<u64> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_gic_get_distributor_reg(offset, &mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Set a distributor register, by offset in the distributor.
+    pub fn set_distributor_register(
+        &self,
+        offset: hv_gic_distributor_reg_t,
+        value: u64,
+    ) -> Result<()> {
+        let ret = unsafe { hv_gic_set_distributor_reg(offset, value) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Gets the guest address of the redistributor of a vCPU.
+    pub fn redistributor_base(&self, vcpu: &VirtualCpu) -> Result<hv_ipa_t> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_gic_get_redistributor_base(vcpu.handle, &mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Gets a redistributor register of a vCPU, by offset in the redistributor.
+    pub fn get_redistributor_register(
+        &self,
+        vcpu: &VirtualCpu,
+        offset: hv_gic_redistributor_reg_t,
+    ) -> Result<u64> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_gic_get_redistributor_reg(vcpu.handle, offset, &mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Set a redistributor register of a vCPU, by offset in the redistributor.
+    pub fn set_redistributor_register(
+        &self,
+        vcpu: &VirtualCpu,
+        offset: hv_gic_redistributor_reg_t,
+        value: u64,
+    ) -> Result<()> {
+        let ret = unsafe { hv_gic_set_redistributor_reg(vcpu.handle, offset, value) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Reset the GIC to its initial state.
+    pub fn reset(&self) -> Result<()> {
+        let ret = unsafe { hv_gic_reset() };
+
+        convert_hv_return(ret)
+    }
+
+    /// Capture the state of the GIC as an opaque blob.
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn save_state(&self) -> Result<Vec<u8>> {
+        let state = unsafe { hv_gic_state_create() };
+
+        if state.is_null() {
+            return Err(HypervisorError::NoResources);
+        }
+
+        let mut size = 0;
+        let mut result = Vec::new();
+
+        let mut ret = unsafe { hv_gic_state_get_size(state, &mut size) };
+
+        if ret == HV_SUCCESS {
+            result.resize(size, 0);
+            ret = unsafe { hv_gic_state_get_data(state, result.as_mut_ptr() as *mut c_void) };
+        }
+
+        unsafe { os_release(state as *mut c_void) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Restore a state of the GIC captured by [Gic::save_state].
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn restore_state(&self, state: &[u8]) -> Result<()> {
+        let ret = unsafe { hv_gic_set_state(state.as_ptr() as *const c_void, state.len()) };
+
+        convert_hv_return(ret)
+    }
+}
+
+impl VirtualCpu {
+    /// Check if the vCPU has a GIC CPU interface, the GIC being created before it.
+    pub fn has_gic(&self) -> bool {
+        self.has_gic
+    }
+
+    /// Gets a GIC CPU interface register value.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn get_gic_icc_register(&mut self, register: GicIccRegister) -> Result<u64> {
+        let mut result = 0;
+
+        let ret = unsafe {
+            hv_gic_get_icc_reg(self.handle, hv_gic_icc_reg_t::from(register), &mut result)
+        };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Set a GIC CPU interface register value.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn set_gic_icc_register(&mut self, register: GicIccRegister, value: u64) -> Result<()> {
+        let ret =
+            unsafe { hv_gic_set_icc_reg(self.handle, hv_gic_icc_reg_t::from(register), value) };
+
+        convert_hv_return(ret)
+    }
+}
@@ -0,0 +1,150 @@
+//! Guest panic detection.
+//!
+//! The detector watches the guest console output for registered byte patterns (e.g. "Kernel
+//! panic") and guest MMIO writes for registered "panic ports". Matches are reported as
+//! [VmEvent::GuestPanicked] with the context of the triggering vCPU.
+
+use crate::bindings::{hv_ipa_t, hv_vcpu_t};
+use crate::err::Result;
+use crate::event::VmEvent;
+use crate::reg::Register;
+use crate::vcpu::VirtualCpu;
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// What triggered a guest panic detection.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PanicTrigger {
+    /// A console output pattern matched, identified by its registration index.
+    Pattern(usize),
+
+    /// A panic port was written.
+    Port {
+        /// The guest address of the port.
+        address: hv_ipa_t,
+
+        /// The value written.
+        value: u64,
+    },
+}
+
+/// Context captured when a guest panic is detected.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PanicContext {
+    /// The vCPU that triggered the detection.
+    pub vcpu: hv_vcpu_t,
+
+    /// The PC of the vCPU.
+    pub pc: u64,
+
+    /// The link register of the vCPU.
+    pub lr: u64,
+
+    /// What triggered the detection.
+    pub trigger: PanicTrigger,
+}
+
+/// Watch guest output and MMIO writes for panic indicators.
+#[derive(Clone, Debug, Default)]
+pub struct PanicDetector {
+    /// Registered console output patterns.
+    patterns: Vec<Vec<u8>>,
+
+    /// Registered panic port addresses.
+    ports: Vec<hv_ipa_t>,
+
+    /// The last output bytes, as long as the longest pattern.
+    window: VecDeque<u8>,
+}
+
+impl PanicDetector {
+    /// Create a new detector without any pattern or port.
+    pub fn new() -> Self {
+        PanicDetector::default()
+    }
+
+    /// Register a console output pattern, returning its index.
+    pub fn add_pattern(&mut self, pattern: &[u8]) -> usize {
+        self.patterns.push(pattern.to_vec());
+
+        self.patterns.len() - 1
+    }
+
+    /// Register a panic port: any guest write to this address is a panic.
+    pub fn add_port(&mut self, address: hv_ipa_t) {
+        self.ports.push(address);
+    }
+
+    /// Forget all previously seen console output.
+    pub fn clear(&mut self) {
+        self.window.clear();
+    }
+
+    /// Capture the context of a vCPU for a trigger.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    fn capture(vcpu: &mut VirtualCpu, trigger: PanicTrigger) -> Result<VmEvent> {
+        Ok(VmEvent::GuestPanicked(PanicContext {
+            vcpu: vcpu.get_handle(),
+            pc: vcpu.get_register(Register::PC)?,
+            lr: vcpu.get_register(Register::LR)?,
+            trigger,
+        }))
+    }
+
+    /// Feed bytes written by a vCPU to the guest console.
+    ///
+    /// The whole chunk is always scanned, so output following a match is still watched. Only the
+    /// first match of a chunk is reported.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn watch_output(&mut self, vcpu: &mut VirtualCpu, data: &[u8]) -> Result<Option<VmEvent>> {
+        let window_size = self.patterns.iter().map(Vec::len).max().unwrap_or(0);
+        let mut result = None;
+
+        for byte in data {
+            self.window.push_back(*byte);
+
+            while self.window.len() > window_size {
+                self.window.pop_front();
+            }
+
+            let matched = self.patterns.iter().position(|pattern| {
+                !pattern.is_empty()
+                    && self.window.len() >= pattern.len()
+                    && self
+                        .window
+                        .range(self.window.len() - pattern.len()..)
+                        .eq(pattern.iter())
+            });
+
+            if let Some(index) = matched {
+                self.window.clear();
+
+                if result.is_none() {
+                    result = Some(Self::capture(vcpu, PanicTrigger::Pattern(index))?);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Check a guest MMIO write against the registered panic ports.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn watch_write(
+        &mut self,
+        vcpu: &mut VirtualCpu,
+        address: hv_ipa_t,
+        value: u64,
+    ) -> Result<Option<VmEvent>> {
+        if !self.ports.contains(&address) {
+            return Ok(None);
+        }
+
+        Self::capture(vcpu, PanicTrigger::Port { address, value }).map(Some)
+    }
+}
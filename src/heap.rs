@@ -0,0 +1,197 @@
+//! Guest heap for harnesses.
+//!
+//! A [GuestHeap] reserves a guest memory region and hands out blocks of it from the host, so
+//! harnesses can build argument buffers and structures for guest function calls.
+//!
+//! Every block is surrounded by [HEAP_REDZONE_SIZE] bytes of redzones, and free memory (never
+//! allocated or freed blocks) is poisoned as well, so [GuestHeap::verify] catches guest overflows
+//! and use-after-free writes.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::poison::{self, DEFAULT_POISON, RedzoneViolation, Redzones};
+use crate::virtual_machine::{MappingHandle, MemoryPermission, VirtualMachine};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Alignment of the blocks returned by [GuestHeap::guest_malloc].
+pub const HEAP_ALIGNMENT: u64 = 16;
+
+/// Size of the redzones placed before and after each block.
+pub const HEAP_REDZONE_SIZE: u64 = 16;
+
+/// A guest memory region managed by the host.
+#[derive(Clone, Debug)]
+pub struct GuestHeap {
+    /// The mapping backing the heap.
+    mapping: MappingHandle,
+
+    /// The guest address of the heap.
+    base: hv_ipa_t,
+
+    /// The size of the heap.
+    size: u64,
+
+    /// Free blocks as (address, size), sorted by address.
+    free_list: Vec<(hv_ipa_t, u64)>,
+
+    /// Allocated chunks as (address, size), including their redzones.
+    allocated: Vec<(hv_ipa_t, u64)>,
+
+    /// Redzones around the allocated blocks and over free memory.
+    redzones: Redzones,
+}
+
+impl GuestHeap {
+    /// Reserve a new heap region of `size` bytes at a guest address.
+    pub fn new(vm: &mut VirtualMachine, base: hv_ipa_t, size: usize) -> Result<Self> {
+        let allocation_handle = vm.allocate(size)?;
+        let mapping = vm.map(allocation_handle, base, MemoryPermission::READ_WRITE)?;
+
+        let mut heap = GuestHeap {
+            mapping,
+            base,
+            size: size as u64,
+            free_list: Vec::new(),
+            allocated: Vec::new(),
+            redzones: Redzones::new(),
+        };
+
+        heap.reset(vm)?;
+
+        Ok(heap)
+    }
+
+    /// Gets the mapping backing the heap.
+    pub fn mapping(&self) -> MappingHandle {
+        self.mapping
+    }
+
+    /// Check if a guest address is inside the heap.
+    pub fn contains(&self, address: hv_ipa_t) -> bool {
+        address >= self.base && address - self.base < self.size
+    }
+
+    /// Gets the redzones of the heap.
+    pub fn redzones(&self) -> &Redzones {
+        &self.redzones
+    }
+
+    /// Verify the redzones of the heap, returning the violations found.
+    pub fn verify(&self, vm: &VirtualMachine) -> Result<Vec<RedzoneViolation>> {
+        self.redzones.verify(vm)
+    }
+
+    /// Poison a guest memory range and mark it as a redzone.
+    fn add_redzone(&mut self, vm: &mut VirtualMachine, address: hv_ipa_t, size: u64) -> Result<()> {
+        poison::poison(vm, address, size as usize, DEFAULT_POISON)?;
+
+        self.redzones.add(address, size as usize);
+
+        Ok(())
+    }
+
+    /// Allocate a zeroed block between two redzones, returning its guest address.
+    pub fn guest_malloc(&mut self, vm: &mut VirtualMachine, size: usize) -> Result<hv_ipa_t> {
+        let size = (size.max(1) as u64)
+            .next_multiple_of(HEAP_ALIGNMENT)
+            .checked_add(2 * HEAP_REDZONE_SIZE)
+            .ok_or(HypervisorError::NoResources)?;
+
+        let index = self
+            .free_list
+            .iter()
+            .position(|(_, free_size)| *free_size >= size)
+            .ok_or(HypervisorError::NoResources)?;
+
+        let (address, free_size) = self.free_list[index];
+
+        if free_size == size {
+            self.free_list.remove(index);
+        } else {
+            self.free_list[index] = (address + size, free_size - size);
+        }
+
+        self.allocated.push((address, size));
+
+        // The free block was poisoned: keep what remains of it, guard the new block.
+        self.redzones.remove(address, free_size as usize);
+
+        if free_size != size {
+            self.redzones
+                .add(address + size, (free_size - size) as usize);
+        }
+
+        self.add_redzone(vm, address, HEAP_REDZONE_SIZE)?;
+        self.add_redzone(vm, address + size - HEAP_REDZONE_SIZE, HEAP_REDZONE_SIZE)?;
+
+        // The block itself may hold the poison of a previous free.
+        let block = address + HEAP_REDZONE_SIZE;
+
+        vm.write_memory(
+            block,
+            &alloc::vec![0; (size - 2 * HEAP_REDZONE_SIZE) as usize],
+        )?;
+
+        Ok(block)
+    }
+
+    /// Allocate a block and copy data in it, returning its guest address.
+    pub fn guest_malloc_from(&mut self, vm: &mut VirtualMachine, data: &[u8]) -> Result<hv_ipa_t> {
+        let address = self.guest_malloc(vm, data.len())?;
+
+        vm.write_memory(address, data)?;
+
+        Ok(address)
+    }
+
+    /// Free a block returned by [GuestHeap::guest_malloc], poisoning it.
+    pub fn guest_free(&mut self, vm: &mut VirtualMachine, address: hv_ipa_t) -> Result<()> {
+        let index = self
+            .allocated
+            .iter()
+            .position(|(allocated, _)| *allocated + HEAP_REDZONE_SIZE == address)
+            .ok_or(HypervisorError::BadArgument)?;
+
+        let (address, size) = self.allocated.remove(index);
+
+        // The whole chunk becomes a single redzone, replacing its guards.
+        self.redzones.remove(address, size as usize);
+        self.add_redzone(vm, address, size)?;
+
+        let position = self
+            .free_list
+            .partition_point(|(free_address, _)| *free_address < address);
+
+        self.free_list.insert(position, (address, size));
+
+        // Merge with the next block, then with the previous one.
+        if let Some((next_address, next_size)) = self.free_list.get(position + 1).copied()
+            && address + size == next_address
+        {
+            self.free_list[position].1 += next_size;
+            self.free_list.remove(position + 1);
+        }
+
+        if position > 0 {
+            let (previous_address, previous_size) = self.free_list[position - 1];
+
+            if previous_address + previous_size == address {
+                self.free_list[position - 1].1 += self.free_list[position].1;
+                self.free_list.remove(position);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Free all blocks, poisoning the whole heap.
+    pub fn reset(&mut self, vm: &mut VirtualMachine) -> Result<()> {
+        self.allocated.clear();
+        self.free_list = alloc::vec![(self.base, self.size)];
+        self.redzones = Redzones::new();
+
+        self.add_redzone(vm, self.base, self.size)
+    }
+}
@@ -0,0 +1,109 @@
+//! CPU identity (MIDR_EL1) helpers.
+
+use crate::err::Result;
+use crate::reg::SystemRegister;
+use crate::vcpu::VirtualCpu;
+
+/// Arm Limited implementer code.
+pub const IMPLEMENTER_ARM: u8 = 0x41;
+
+/// Qualcomm implementer code.
+pub const IMPLEMENTER_QUALCOMM: u8 = 0x51;
+
+/// Apple implementer code.
+pub const IMPLEMENTER_APPLE: u8 = 0x61;
+
+/// Ampere Computing implementer code.
+pub const IMPLEMENTER_AMPERE: u8 = 0xC0;
+
+/// Architecture field value meaning "defined by the ID registers".
+const ARCHITECTURE_ID_REGISTERS: u8 = 0xF;
+
+/// Decoded value of the Main ID Register (MIDR_EL1).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Midr {
+    /// The implementer code.
+    pub implementer: u8,
+
+    /// The variant (major revision) number.
+    pub variant: u8,
+
+    /// The architecture code.
+    pub architecture: u8,
+
+    /// The primary part number.
+    pub part_number: u16,
+
+    /// The revision (minor revision) number.
+    pub revision: u8,
+}
+
+impl Midr {
+    /// Create a new MIDR_EL1 value for a given implementer and part.
+    pub const fn new(implementer: u8, part_number: u16, variant: u8, revision: u8) -> Self {
+        Midr {
+            implementer,
+            variant,
+            architecture: ARCHITECTURE_ID_REGISTERS,
+            part_number,
+            revision,
+        }
+    }
+
+    /// Arm Cortex-A53 (r0p4).
+    pub const CORTEX_A53: Midr = Midr::new(IMPLEMENTER_ARM, 0xD03, 0, 4);
+
+    /// Arm Cortex-A57 (r1p3).
+    pub const CORTEX_A57: Midr = Midr::new(IMPLEMENTER_ARM, 0xD07, 1, 3);
+
+    /// Arm Cortex-A72 (r0p3).
+    pub const CORTEX_A72: Midr = Midr::new(IMPLEMENTER_ARM, 0xD08, 0, 3);
+
+    /// Arm Neoverse N1 (r3p1).
+    pub const NEOVERSE_N1: Midr = Midr::new(IMPLEMENTER_ARM, 0xD0C, 3, 1);
+
+    /// Arm Neoverse V1 (r1p1).
+    pub const NEOVERSE_V1: Midr = Midr::new(IMPLEMENTER_ARM, 0xD40, 1, 1);
+}
+
+impl From<u64> for Midr {
+    fn from(value: u64) -> Midr {
+        Midr {
+            implementer: (value >> 24) as u8,
+            variant: ((value >> 20) & 0xF) as u8,
+            architecture: ((value >> 16) & 0xF) as u8,
+            part_number: ((value >> 4) & 0xFFF) as u16,
+            revision: (value & 0xF) as u8,
+        }
+    }
+}
+
+impl From<Midr> for u64 {
+    fn from(value: Midr) -> u64 {
+        u64::from(value.implementer) << 24
+            | u64::from(value.variant & 0xF) << 20
+            | u64::from(value.architecture & 0xF) << 16
+            | u64::from(value.part_number & 0xFFF) << 4
+            | u64::from(value.revision & 0xF)
+    }
+}
+
+impl VirtualCpu {
+    /// Gets the identity (MIDR_EL1) reported to the guest.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn get_midr(&mut self) -> Result<Midr> {
+        self.get_system_register(SystemRegister::MIDR_EL1)
+            .map(Midr::from)
+    }
+
+    /// Sets the identity (MIDR_EL1) reported to the guest.
+    ///
+    /// Only MIDR_EL1 is exposed by the Hypervisor, other identity registers (REVIDR_EL1,
+    /// AIDR_EL1) keep the host values.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn set_midr(&mut self, midr: Midr) -> Result<()> {
+        self.set_system_register(SystemRegister::MIDR_EL1, u64::from(midr))
+    }
+}
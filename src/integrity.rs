@@ -0,0 +1,175 @@
+//! Guest memory integrity checking.
+//!
+//! Regions expected to stay immutable (guest kernel text, read-only data, firmware tables) are
+//! registered with their SHA-256 digest and verified later. A modified region is reported, and
+//! when its mapping is also traced by an [crate::access_log::AccessTracer], the offending writes
+//! can be found in the access log with [IntegrityMonitor::offending_writes].
+
+use crate::access_log::AccessRecord;
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::sha256::sha256;
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl VirtualMachine {
+    /// Compute the SHA-256 digest of a guest memory range.
+    pub fn checksum_region(&self, address: hv_ipa_t, size: usize) -> Result<[u8; 32]> {
+        let mut contents = vec![0; size];
+
+        self.read_memory(address, &mut contents)?;
+
+        Ok(sha256(&contents))
+    }
+}
+
+/// Identifier of an immutable region.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ImmutableRegionHandle(pub u64);
+
+/// An immutable region was modified.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IntegrityViolation {
+    /// The modified region.
+    pub region: ImmutableRegionHandle,
+
+    /// The guest address of the region.
+    pub address: hv_ipa_t,
+
+    /// The size of the region.
+    pub size: usize,
+
+    /// The expected digest.
+    pub expected: [u8; 32],
+
+    /// The current digest.
+    pub actual: [u8; 32],
+}
+
+/// A region expected to stay immutable.
+#[derive(Copy, Clone, Debug)]
+struct ImmutableRegion {
+    /// Associated handle.
+    handle: ImmutableRegionHandle,
+
+    /// The guest address of the region.
+    address: hv_ipa_t,
+
+    /// The size of the region.
+    size: usize,
+
+    /// The digest of the region when registered.
+    checksum: [u8; 32],
+}
+
+/// A set of immutable regions to verify.
+#[derive(Clone, Debug, Default)]
+pub struct IntegrityMonitor {
+    /// List of all regions.
+    regions: Vec<ImmutableRegion>,
+
+    /// Last handle given.
+    last_handle: u64,
+}
+
+impl IntegrityMonitor {
+    /// Create a new monitor without any region.
+    pub fn new() -> Self {
+        IntegrityMonitor::default()
+    }
+
+    /// Mark a guest memory range as immutable, recording its current digest.
+    pub fn add(
+        &mut self,
+        vm: &VirtualMachine,
+        address: hv_ipa_t,
+        size: usize,
+    ) -> Result<ImmutableRegionHandle> {
+        if size == 0 {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        self.last_handle += 1;
+
+        let handle = ImmutableRegionHandle(self.last_handle);
+
+        self.regions.push(ImmutableRegion {
+            handle,
+            address,
+            size,
+            checksum: vm.checksum_region(address, size)?,
+        });
+
+        Ok(handle)
+    }
+
+    /// Stop verifying a region.
+    pub fn remove(&mut self, handle: ImmutableRegionHandle) -> Result<()> {
+        let index = self
+            .regions
+            .iter()
+            .position(|region| region.handle == handle)
+            .ok_or(HypervisorError::InvalidHandle)?;
+
+        self.regions.remove(index);
+
+        Ok(())
+    }
+
+    /// Record the current contents of a region as the expected ones, after a legitimate update.
+    pub fn update(&mut self, vm: &VirtualMachine, handle: ImmutableRegionHandle) -> Result<()> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|region| region.handle == handle)
+            .ok_or(HypervisorError::InvalidHandle)?;
+
+        region.checksum = vm.checksum_region(region.address, region.size)?;
+
+        Ok(())
+    }
+
+    /// Verify every region, returning the modified ones.
+    pub fn verify(&self, vm: &VirtualMachine) -> Result<Vec<IntegrityViolation>> {
+        let mut result = Vec::new();
+
+        for region in self.regions.iter() {
+            let actual = vm.checksum_region(region.address, region.size)?;
+
+            if actual != region.checksum {
+                result.push(IntegrityViolation {
+                    region: region.handle,
+                    address: region.address,
+                    size: region.size,
+                    expected: region.checksum,
+                    actual,
+                });
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Find the logged writes that touched an immutable region.
+    pub fn offending_writes<'a>(
+        &'a self,
+        log: &'a [AccessRecord],
+    ) -> impl Iterator<Item = (ImmutableRegionHandle, &'a AccessRecord)> + 'a {
+        log.iter()
+            .filter(|record| record.is_write)
+            .filter_map(|record| {
+                let start = record.address;
+                let end = start + u64::from(record.size);
+
+                self.regions
+                    .iter()
+                    .find(|region| {
+                        start < region.address + region.size as u64 && end > region.address
+                    })
+                    .map(|region| (region.handle, record))
+            })
+    }
+}
@@ -3,11 +3,54 @@
 //! This module contains the raw FFI bindings generated from the Hypervisor Framework headers.
 
 mod bindings;
+mod sha256;
 
+/// Raw FFI bindings to the Hypervisor Framework.
+#[cfg(feature = "sys")]
+pub mod sys {
+    pub use crate::bindings::*;
+}
+
+pub mod access_log;
+pub mod acpi;
+pub mod cache;
+pub mod call;
+pub mod capabilities;
+pub mod console;
+pub mod context;
+pub mod core_dump;
+pub mod coverage;
+pub mod debug;
+pub mod device;
+pub mod diff;
 pub mod err;
+pub mod event;
+pub mod fdt;
+pub mod features;
+pub mod forensics;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+pub mod gic;
+pub mod guest_panic;
+pub mod heap;
+pub mod identity;
+pub mod integrity;
+pub mod loader;
+pub mod machine;
+pub mod poison;
 pub mod reg;
+pub mod shutdown;
+pub mod smbios;
+pub mod smccc;
+pub mod snapshot;
+pub mod time_sync;
+pub mod timeline;
+pub mod topology;
+pub mod trace;
 pub mod vcpu;
+pub mod virtqueue;
 pub mod virtual_machine;
+pub mod watch;
 
 pub use err::*;
 pub use reg::*;
@@ -0,0 +1,173 @@
+//! AArch64 Linux kernel loader.
+//!
+//! The kernel `Image`, the initrd and the DTB are placed in guest RAM following the arm64 boot
+//! protocol (Documentation/arch/arm64/booting.rst):
+//!
+//! - The kernel is placed at `text_offset` from a 2MiB aligned RAM base.
+//! - The initrd follows the kernel (including its BSS) on the next 2MiB boundary.
+//! - The DTB follows the initrd on the next 2MiB boundary and must not exceed 2MiB.
+
+use super::reset_cpsr;
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::fdt::FdtWriter;
+use crate::reg::Register;
+use crate::vcpu::VirtualCpu;
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::string::String;
+
+/// Magic of an arm64 kernel `Image` header ("ARM\x64").
+const IMAGE_MAGIC: u32 = 0x644D_5241;
+
+/// Size of an arm64 kernel `Image` header.
+const IMAGE_HEADER_SIZE: usize = 64;
+
+/// Text offset used by kernels older than 3.17 which don't provide an image size.
+const LEGACY_TEXT_OFFSET: u64 = 0x8_0000;
+
+/// Alignment of the kernel base, the initrd and the DTB.
+const BOOT_ALIGNMENT: u64 = 0x20_0000;
+
+/// Maximum size of the DTB.
+pub const DTB_MAX_SIZE: usize = 0x20_0000;
+
+/// Align a value up to the next boot alignment boundary.
+fn align_up(value: u64) -> Result<u64> {
+    value
+        .checked_add(BOOT_ALIGNMENT - 1)
+        .map(|value| value & !(BOOT_ALIGNMENT - 1))
+        .ok_or(HypervisorError::BadArgument)
+}
+
+/// A Linux kernel loaded in a Virtual Machine.
+#[derive(Clone, Debug)]
+pub struct LinuxBoot {
+    /// The guest address of the kernel entry point.
+    pub kernel_address: hv_ipa_t,
+
+    /// The size of the kernel in memory (including its BSS).
+    pub kernel_size: u64,
+
+    /// The guest address range of the initrd, if any.
+    pub initrd: Option<(hv_ipa_t, hv_ipa_t)>,
+
+    /// The guest address reserved for the DTB.
+    pub dtb_address: hv_ipa_t,
+
+    /// The kernel command line.
+    pub command_line: String,
+}
+
+impl LinuxBoot {
+    /// Load a kernel `Image` and an optional initrd in the guest RAM.
+    ///
+    /// The RAM must already be mapped at `ram_base` (2MiB aligned) for `ram_size` bytes.
+    pub fn load(
+        vm: &mut VirtualMachine,
+        ram_base: hv_ipa_t,
+        ram_size: u64,
+        kernel: &[u8],
+        initrd: Option<&[u8]>,
+        command_line: &str,
+    ) -> Result<Self> {
+        if !ram_base.is_multiple_of(BOOT_ALIGNMENT) {
+            return Err(HypervisorError::MisalignedAddress);
+        }
+
+        if kernel.len() < IMAGE_HEADER_SIZE {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(kernel[offset..offset + 8].try_into().unwrap());
+
+        let magic = u32::from_le_bytes(kernel[56..60].try_into().unwrap());
+
+        if magic != IMAGE_MAGIC {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let (text_offset, image_size) = match read_u64(16) {
+            0 => (LEGACY_TEXT_OFFSET, kernel.len() as u64),
+            image_size => (read_u64(8), image_size),
+        };
+
+        // The header fields come from an untrusted image.
+        let checked_add = |value: u64, offset: u64| {
+            value
+                .checked_add(offset)
+                .ok_or(HypervisorError::BadArgument)
+        };
+
+        let ram_end = checked_add(ram_base, ram_size)?;
+        let kernel_address = checked_add(ram_base, text_offset)?;
+        let kernel_size = image_size.max(kernel.len() as u64);
+        let kernel_end = checked_add(kernel_address, kernel_size)?;
+
+        let initrd_range = match initrd {
+            Some(initrd) => {
+                let start = align_up(kernel_end)?;
+
+                Some((start, checked_add(start, initrd.len() as u64)?))
+            }
+            None => None,
+        };
+
+        let dtb_address = align_up(initrd_range.map_or(kernel_end, |(_, end)| end))?;
+
+        if checked_add(dtb_address, DTB_MAX_SIZE as u64)? > ram_end {
+            return Err(HypervisorError::NoResources);
+        }
+
+        vm.write_memory(kernel_address, kernel)?;
+        vm.clean_instruction_cache_range(kernel_address, kernel.len())?;
+
+        if let (Some(initrd), Some((start, _))) = (initrd, initrd_range) {
+            vm.write_memory(start, initrd)?;
+        }
+
+        Ok(LinuxBoot {
+            kernel_address,
+            kernel_size,
+            initrd: initrd_range,
+            dtb_address,
+            command_line: String::from(command_line),
+        })
+    }
+
+    /// Write the `chosen` node (command line and initrd location) to a device tree.
+    pub fn write_chosen(&self, fdt: &mut FdtWriter) {
+        fdt.begin_node("chosen");
+        fdt.property_string("bootargs", &self.command_line);
+
+        if let Some((start, end)) = self.initrd {
+            fdt.property_u64("linux,initrd-start", start);
+            fdt.property_u64("linux,initrd-end", end);
+        }
+
+        fdt.end_node();
+    }
+
+    /// Write the DTB at its reserved location in guest RAM.
+    pub fn load_dtb(&self, vm: &mut VirtualMachine, dtb: &[u8]) -> Result<()> {
+        if dtb.len() > DTB_MAX_SIZE {
+            return Err(HypervisorError::NoResources);
+        }
+
+        vm.write_memory(self.dtb_address, dtb)
+    }
+
+    /// Set up the boot state of the primary vCPU.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn setup_vcpu(&self, vcpu: &mut VirtualCpu) -> Result<()> {
+        vcpu.set_register(Register::X0, self.dtb_address)?;
+        vcpu.set_register(Register::X1, 0)?;
+        vcpu.set_register(Register::X2, 0)?;
+        vcpu.set_register(Register::X3, 0)?;
+        vcpu.set_register(Register::PC, self.kernel_address)?;
+        vcpu.set_register(Register::CPSR, reset_cpsr(vcpu))
+    }
+}
@@ -0,0 +1,110 @@
+//! Guest image loaders.
+//!
+//! Loaders place guest images in the Virtual Machine memory and set up the initial vCPU state
+//! expected by them.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::reg::{
+    EL2_SYSTEM_REGISTERS, GENERAL_PURPOSE_REGISTERS, Register, SIMD_FP_REGISTERS, SYSTEM_REGISTERS,
+    SimdFpValue, SystemRegister,
+};
+use crate::vcpu::{InterruptType, VirtualCpu};
+
+pub mod linux;
+pub mod uefi;
+
+/// CPSR value used on reset: EL1h with all exceptions (DAIF) masked.
+pub const RESET_CPSR: u64 = 0x3C5;
+
+/// CPSR value used on reset when the guest runs at EL2: EL2h with all exceptions (DAIF) masked.
+pub const RESET_CPSR_EL2: u64 = 0x3C9;
+
+/// SCTLR_EL1 value used on reset: RES1 bits set, MMU and caches off.
+pub const RESET_SCTLR_EL1: u64 = 0x30D0_0800;
+
+/// SCTLR_EL2 value used on reset when the guest runs at EL2: RES1 bits set, MMU and caches off.
+pub const RESET_SCTLR_EL2: u64 = 0x30C5_0830;
+
+/// Gets the CPSR value a vCPU should start with: EL2h if its Virtual Machine runs the guest at
+/// EL2, EL1h otherwise.
+pub fn reset_cpsr(vcpu: &VirtualCpu) -> u64 {
+    if vcpu.is_el2_enabled() {
+        RESET_CPSR_EL2
+    } else {
+        RESET_CPSR
+    }
+}
+
+/// Check if a system register identifies the vCPU (ID, MIDR and MPIDR registers), and so keeps
+/// its value across resets.
+fn is_identification_register(register: SystemRegister) -> bool {
+    matches!(
+        register,
+        SystemRegister::MIDR_EL1
+            | SystemRegister::MPIDR_EL1
+            | SystemRegister::ID_AA64PFR0_EL1
+            | SystemRegister::ID_AA64PFR1_EL1
+            | SystemRegister::ID_AA64DFR0_EL1
+            | SystemRegister::ID_AA64DFR1_EL1
+            | SystemRegister::ID_AA64ISAR0_EL1
+            | SystemRegister::ID_AA64ISAR1_EL1
+            | SystemRegister::ID_AA64MMFR0_EL1
+            | SystemRegister::ID_AA64MMFR1_EL1
+            | SystemRegister::ID_AA64MMFR2_EL1
+    )
+}
+
+/// Bring a vCPU back to its architectural reset state, ready to execute from `entry`.
+///
+/// Without an explicit entry point, the vCPU reset PC is used (the first ROM of the Virtual
+/// Machine, see [VirtualCpu::get_reset_pc]), failing with [HypervisorError::BadArgument] if
+/// there's none.
+///
+/// General purpose, SIMD and floating-point registers are cleared, as are all system registers
+/// but the identification ones (translation, timer and debug state included). SCTLR_EL1 is set to
+/// its reset value with the MMU and caches off, and pending interrupts are dropped. When the guest
+/// runs at EL2, the EL2 system registers are reset too and the vCPU starts in EL2h.
+///
+/// This is used when rebooting a Virtual Machine in place, the loader `setup_vcpu` functions
+/// should be called afterward to set up the boot arguments.
+///
+/// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+pub fn reset_vcpu(vcpu: &mut VirtualCpu, entry: Option<hv_ipa_t>) -> Result<()> {
+    let entry = entry
+        .or(vcpu.get_reset_pc())
+        .ok_or(HypervisorError::BadArgument)?;
+
+    for register in GENERAL_PURPOSE_REGISTERS {
+        vcpu.set_register(register, 0)?;
+    }
+
+    for register in SIMD_FP_REGISTERS {
+        vcpu.set_simd_fp_register(register, SimdFpValue::default())?;
+    }
+
+    vcpu.set_register(Register::FPCR, 0)?;
+    vcpu.set_register(Register::FPSR, 0)?;
+
+    for register in SYSTEM_REGISTERS {
+        if !is_identification_register(register) {
+            vcpu.set_system_register(register, 0)?;
+        }
+    }
+
+    vcpu.set_pending_interrupt(InterruptType::IRQ, false)?;
+    vcpu.set_pending_interrupt(InterruptType::FIQ, false)?;
+
+    vcpu.set_system_register(SystemRegister::SCTLR_EL1, RESET_SCTLR_EL1)?;
+
+    if vcpu.is_el2_enabled() {
+        for register in EL2_SYSTEM_REGISTERS {
+            vcpu.set_system_register(register, 0)?;
+        }
+
+        vcpu.set_system_register(SystemRegister::SCTLR_EL2, RESET_SCTLR_EL2)?;
+    }
+
+    vcpu.set_register(Register::PC, entry)?;
+    vcpu.set_register(Register::CPSR, reset_cpsr(vcpu))
+}
@@ -0,0 +1,91 @@
+//! AArch64 UEFI firmware (e.g. EDK2 `QEMU_EFI.fd`) loader.
+//!
+//! The firmware code and its variable store are mapped as flash at the conventional addresses of
+//! the QEMU "virt" board. Both mappings are read-only: writes to the variable store trap as data
+//! aborts so they can be handled by a flash emulation.
+
+use super::reset_cpsr;
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::reg::Register;
+use crate::vcpu::VirtualCpu;
+use crate::virtual_machine::{MappingHandle, MemoryPermission, VirtualMachine};
+
+/// Guest address of the firmware code flash.
+pub const CODE_BASE: hv_ipa_t = 0x0000_0000;
+
+/// Guest address of the firmware variable store flash.
+pub const VARS_BASE: hv_ipa_t = 0x0400_0000;
+
+/// Size of a flash bank.
+pub const FLASH_SIZE: usize = 0x0400_0000;
+
+/// A UEFI firmware loaded in a Virtual Machine.
+#[derive(Copy, Clone, Debug)]
+pub struct UefiFirmware {
+    /// The mapping of the firmware code.
+    pub code: MappingHandle,
+
+    /// The mapping of the firmware variable store.
+    pub vars: MappingHandle,
+
+    /// The guest address to start executing from.
+    pub entry: hv_ipa_t,
+}
+
+/// Value of an erased flash byte.
+const FLASH_ERASED: u8 = 0xFF;
+
+/// Map an image as a flash bank, padding it to the flash size with erased bytes.
+fn map_flash(
+    vm: &mut VirtualMachine,
+    image: &[u8],
+    guest_address: hv_ipa_t,
+    permission: MemoryPermission,
+) -> Result<MappingHandle> {
+    if image.len() > FLASH_SIZE {
+        return Err(HypervisorError::BadArgument);
+    }
+
+    let allocation_handle = vm.allocate(FLASH_SIZE)?;
+
+    let flash = vm.get_allocation_slice_mut(allocation_handle)?;
+
+    flash[..image.len()].copy_from_slice(image);
+    flash[image.len()..].fill(FLASH_ERASED);
+
+    vm.map(allocation_handle, guest_address, permission)
+        .inspect_err(|_| {
+            let _ = vm.deallocate(allocation_handle);
+        })
+}
+
+impl UefiFirmware {
+    /// Load a firmware image and an optional variable store in the Virtual Machine.
+    ///
+    /// When no variable store is given, an empty (erased) one is created.
+    pub fn load(vm: &mut VirtualMachine, code: &[u8], vars: Option<&[u8]>) -> Result<Self> {
+        let code = map_flash(vm, code, CODE_BASE, MemoryPermission::READ_EXECUTE)?;
+        let vars = map_flash(vm, vars.unwrap_or(&[]), VARS_BASE, MemoryPermission::READ)
+            .inspect_err(|_| {
+                if let Ok(mapping) = vm.get_mapping_info(code) {
+                    let _ = vm.unmap(code);
+                    let _ = vm.deallocate(mapping.allocation_handle);
+                }
+            })?;
+
+        Ok(UefiFirmware {
+            code,
+            vars,
+            entry: CODE_BASE,
+        })
+    }
+
+    /// Set up the reset state of a vCPU to boot the firmware.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn setup_vcpu(&self, vcpu: &mut VirtualCpu) -> Result<()> {
+        vcpu.set_register(Register::PC, self.entry)?;
+        vcpu.set_register(Register::CPSR, reset_cpsr(vcpu))
+    }
+}
@@ -0,0 +1,35 @@
+//! Machine presets.
+//!
+//! A preset describes the guest physical memory map and interrupt assignments of a board, so that
+//! firmware and kernels built for it boot unmodified.
+
+use crate::bindings::hv_ipa_t;
+
+pub mod virt;
+
+/// A region of the guest physical address space.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryRegion {
+    /// The guest address of the region.
+    pub base: hv_ipa_t,
+
+    /// The size of the region.
+    pub size: u64,
+}
+
+impl MemoryRegion {
+    /// Create a new region.
+    pub const fn new(base: hv_ipa_t, size: u64) -> Self {
+        MemoryRegion { base, size }
+    }
+
+    /// Gets the guest address following the region.
+    pub const fn end(&self) -> hv_ipa_t {
+        self.base + self.size
+    }
+
+    /// Check if a guest address is inside the region.
+    pub const fn contains(&self, address: hv_ipa_t) -> bool {
+        address >= self.base && address - self.base < self.size
+    }
+}
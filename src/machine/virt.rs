@@ -0,0 +1,181 @@
+//! Memory map of the QEMU aarch64 "virt" board (hw/arm/virt.c).
+//!
+//! Only the regions used by non-secure guests are described. Interrupts are given as SPI
+//! numbers, as used in device tree `interrupts` properties (GIC INTID minus 32).
+
+use super::MemoryRegion;
+use crate::acpi::{self, AcpiDevice, SPI_BASE};
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::virtual_machine::{MappingHandle, MemoryPermission, VirtualMachine};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Flash (two banks: firmware code then variable store).
+pub const FLASH: MemoryRegion = MemoryRegion::new(0x0000_0000, 0x0800_0000);
+
+/// GIC distributor.
+pub const GIC_DISTRIBUTOR: MemoryRegion = MemoryRegion::new(0x0800_0000, 0x0001_0000);
+
+/// GICv2 CPU interface.
+pub const GIC_CPU_INTERFACE: MemoryRegion = MemoryRegion::new(0x0801_0000, 0x0001_0000);
+
+/// GICv2m MSI frame.
+pub const GIC_V2M: MemoryRegion = MemoryRegion::new(0x0802_0000, 0x0000_1000);
+
+/// GICv3 ITS.
+pub const GIC_ITS: MemoryRegion = MemoryRegion::new(0x0808_0000, 0x0002_0000);
+
+/// GICv3 redistributors (up to 123 vCPUs).
+pub const GIC_REDISTRIBUTOR: MemoryRegion = MemoryRegion::new(0x080A_0000, 0x00F6_0000);
+
+/// PL011 UART.
+pub const UART: MemoryRegion = MemoryRegion::new(0x0900_0000, 0x0000_1000);
+
+/// PL031 RTC.
+pub const RTC: MemoryRegion = MemoryRegion::new(0x0901_0000, 0x0000_1000);
+
+/// fw_cfg interface.
+pub const FW_CFG: MemoryRegion = MemoryRegion::new(0x0902_0000, 0x0000_0018);
+
+/// PL061 GPIO controller.
+pub const GPIO: MemoryRegion = MemoryRegion::new(0x0903_0000, 0x0000_1000);
+
+/// Stolen time (pvtime) structures.
+pub const PVTIME: MemoryRegion = MemoryRegion::new(0x090A_0000, 0x0001_0000);
+
+/// virtio-mmio transports.
+pub const VIRTIO_MMIO: MemoryRegion = MemoryRegion::new(0x0A00_0000, 0x0000_4000);
+
+/// Platform bus (dynamic sysbus devices).
+pub const PLATFORM_BUS: MemoryRegion = MemoryRegion::new(0x0C00_0000, 0x0200_0000);
+
+/// PCIe MMIO window (32-bit).
+pub const PCIE_MMIO: MemoryRegion = MemoryRegion::new(0x1000_0000, 0x2EFF_0000);
+
+/// PCIe I/O port window.
+pub const PCIE_PIO: MemoryRegion = MemoryRegion::new(0x3EFF_0000, 0x0001_0000);
+
+/// PCIe ECAM configuration space.
+pub const PCIE_ECAM: MemoryRegion = MemoryRegion::new(0x3F00_0000, 0x0100_0000);
+
+/// Base of the RAM.
+pub const RAM_BASE: u64 = 0x4000_0000;
+
+/// Maximum size of the RAM below the high memory regions (255GiB).
+pub const RAM_MAX_SIZE: u64 = 255 << 30;
+
+/// Number of virtio-mmio transports.
+pub const VIRTIO_MMIO_COUNT: u32 = 32;
+
+/// Size of a virtio-mmio transport.
+pub const VIRTIO_MMIO_SIZE: u64 = 0x200;
+
+/// UART interrupt (SPI).
+pub const UART_IRQ: u32 = 1;
+
+/// RTC interrupt (SPI).
+pub const RTC_IRQ: u32 = 2;
+
+/// First PCIe legacy interrupt (SPI, INTA to INTD).
+pub const PCIE_IRQ: u32 = 3;
+
+/// GPIO interrupt (SPI).
+pub const GPIO_IRQ: u32 = 7;
+
+/// First virtio-mmio interrupt (SPI, one per transport).
+pub const VIRTIO_MMIO_IRQ: u32 = 16;
+
+/// First GICv2m MSI interrupt (SPI).
+pub const GIC_V2M_IRQ: u32 = 48;
+
+/// First platform bus interrupt (SPI).
+pub const PLATFORM_BUS_IRQ: u32 = 112;
+
+/// A "virt" board memory map with a given RAM size.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VirtBoard {
+    /// The size of the RAM.
+    ram_size: u64,
+}
+
+impl VirtBoard {
+    /// Create a new board with a given RAM size.
+    pub fn new(ram_size: u64) -> Result<Self> {
+        if ram_size == 0 || ram_size > RAM_MAX_SIZE {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        Ok(VirtBoard { ram_size })
+    }
+
+    /// Gets the RAM region.
+    pub fn ram(&self) -> MemoryRegion {
+        MemoryRegion::new(RAM_BASE, self.ram_size)
+    }
+
+    /// Gets the region of a virtio-mmio transport.
+    pub fn virtio_mmio(&self, index: u32) -> Option<MemoryRegion> {
+        if index >= VIRTIO_MMIO_COUNT {
+            return None;
+        }
+
+        Some(MemoryRegion::new(
+            VIRTIO_MMIO.base + u64::from(index) * VIRTIO_MMIO_SIZE,
+            VIRTIO_MMIO_SIZE,
+        ))
+    }
+
+    /// Gets the interrupt (SPI) of a virtio-mmio transport.
+    pub fn virtio_mmio_irq(&self, index: u32) -> Option<u32> {
+        (index < VIRTIO_MMIO_COUNT).then_some(VIRTIO_MMIO_IRQ + index)
+    }
+
+    /// Build the ACPI tables of the board, to load at a given guest address.
+    ///
+    /// The DSDT describes the UART and the virtio-mmio transports, and the MADT one GIC CPU
+    /// interface per MPIDR.
+    pub fn acpi_tables(&self, address: hv_ipa_t, mpidrs: &[u64]) -> Result<Vec<u8>> {
+        let names: Vec<String> = (0..VIRTIO_MMIO_COUNT)
+            .map(|index| format!("VR{index:02}"))
+            .collect();
+
+        let mut devices = Vec::from([AcpiDevice {
+            name: "COM0",
+            hid: "ARMH0011",
+            uid: 0,
+            region: UART,
+            interrupt: Some(SPI_BASE + UART_IRQ),
+        }]);
+
+        for (index, name) in (0..VIRTIO_MMIO_COUNT).zip(names.iter()) {
+            devices.push(AcpiDevice {
+                name,
+                hid: "LNRO0005",
+                uid: index,
+                region: self.virtio_mmio(index).unwrap(),
+                interrupt: self.virtio_mmio_irq(index).map(|irq| SPI_BASE + irq),
+            });
+        }
+
+        let dsdt = acpi::dsdt(&devices)?;
+        let madt = acpi::madt(GIC_DISTRIBUTOR.base, GIC_REDISTRIBUTOR, mpidrs);
+        let gtdt = acpi::gtdt();
+
+        Ok(acpi::build(address, &dsdt, &[&madt, &gtdt]))
+    }
+
+    /// Allocate and map the RAM.
+    pub fn map_ram(&self, vm: &mut VirtualMachine) -> Result<MappingHandle> {
+        let allocation_handle = vm.allocate(self.ram_size as usize)?;
+
+        vm.map(
+            allocation_handle,
+            RAM_BASE,
+            MemoryPermission::READ_WRITE_EXECUTE,
+        )
+    }
+}
@@ -0,0 +1,134 @@
+//! Guest memory poisoning and redzones.
+//!
+//! Redzones are guest memory ranges that guest code should never write, like guards placed next to
+//! unmapped regions or freed buffers. They are filled with a poison pattern before a run and
+//! verified afterward to catch silent out-of-bounds writes.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::Result;
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Default poison byte.
+pub const DEFAULT_POISON: u8 = 0xA5;
+
+/// A poisoned guest memory range.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Redzone {
+    /// The guest address of the range.
+    pub address: hv_ipa_t,
+
+    /// The size of the range.
+    pub size: usize,
+
+    /// The poison byte filling the range.
+    pub pattern: u8,
+}
+
+/// A write detected in a redzone.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RedzoneViolation {
+    /// The redzone that was written.
+    pub redzone: Redzone,
+
+    /// The guest address of the first modified byte.
+    pub address: hv_ipa_t,
+
+    /// The value found at this address.
+    pub value: u8,
+}
+
+/// Fill a guest memory range with a poison byte.
+pub fn poison(vm: &mut VirtualMachine, address: hv_ipa_t, size: usize, pattern: u8) -> Result<()> {
+    vm.write_memory(address, &vec![pattern; size])
+}
+
+/// Check that a guest memory range still holds a poison byte, returning the first modified
+/// address and its value.
+pub fn check_poison(
+    vm: &VirtualMachine,
+    address: hv_ipa_t,
+    size: usize,
+    pattern: u8,
+) -> Result<Option<(hv_ipa_t, u8)>> {
+    let mut buffer = vec![0; size];
+
+    vm.read_memory(address, &mut buffer)?;
+
+    Ok(buffer
+        .iter()
+        .position(|value| *value != pattern)
+        .map(|offset| (address + offset as u64, buffer[offset])))
+}
+
+/// A set of redzones.
+#[derive(Clone, Debug, Default)]
+pub struct Redzones {
+    /// List of all redzones.
+    redzones: Vec<Redzone>,
+}
+
+impl Redzones {
+    /// Create a new empty set.
+    pub fn new() -> Self {
+        Redzones::default()
+    }
+
+    /// Add a redzone filled with [DEFAULT_POISON].
+    pub fn add(&mut self, address: hv_ipa_t, size: usize) {
+        self.add_with_pattern(address, size, DEFAULT_POISON);
+    }
+
+    /// Add a redzone filled with a given poison byte.
+    pub fn add_with_pattern(&mut self, address: hv_ipa_t, size: usize, pattern: u8) {
+        self.redzones.push(Redzone {
+            address,
+            size,
+            pattern,
+        });
+    }
+
+    /// Remove all redzones overlapping a guest memory range, e.g. when it gets reused.
+    pub fn remove(&mut self, address: hv_ipa_t, size: usize) {
+        self.redzones.retain(|redzone| {
+            redzone.address + redzone.size as u64 <= address
+                || address + size as u64 <= redzone.address
+        });
+    }
+
+    /// Gets all redzones.
+    pub fn redzones(&self) -> &[Redzone] {
+        &self.redzones
+    }
+
+    /// Fill all redzones with their poison byte.
+    pub fn poison_all(&self, vm: &mut VirtualMachine) -> Result<()> {
+        for redzone in self.redzones.iter() {
+            poison(vm, redzone.address, redzone.size, redzone.pattern)?;
+        }
+
+        Ok(())
+    }
+
+    /// Verify all redzones, returning the violations found.
+    pub fn verify(&self, vm: &VirtualMachine) -> Result<Vec<RedzoneViolation>> {
+        let mut result = Vec::new();
+
+        for redzone in self.redzones.iter() {
+            if let Some((address, value)) =
+                check_poison(vm, redzone.address, redzone.size, redzone.pattern)?
+            {
+                result.push(RedzoneViolation {
+                    redzone: *redzone,
+                    address,
+                    value,
+                });
+            }
+        }
+
+        Ok(result)
+    }
+}
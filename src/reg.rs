@@ -1,7 +1,7 @@
 use crate::bindings::*;
 
 /// ARM register.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Register {
     /// X0 register.
@@ -116,6 +116,41 @@ pub enum Register {
     CPSR,
 }
 
+/// General purpose registers X0 to X30, in order.
+pub const GENERAL_PURPOSE_REGISTERS: [Register; 31] = [
+    Register::X0,
+    Register::X1,
+    Register::X2,
+    Register::X3,
+    Register::X4,
+    Register::X5,
+    Register::X6,
+    Register::X7,
+    Register::X8,
+    Register::X9,
+    Register::X10,
+    Register::X11,
+    Register::X12,
+    Register::X13,
+    Register::X14,
+    Register::X15,
+    Register::X16,
+    Register::X17,
+    Register::X18,
+    Register::X19,
+    Register::X20,
+    Register::X21,
+    Register::X22,
+    Register::X23,
+    Register::X24,
+    Register::X25,
+    Register::X26,
+    Register::X27,
+    Register::X28,
+    Register::X29,
+    Register::X30,
+];
+
 impl From<Register> for hv_reg_t {
     fn from(value: Register) -> hv_reg_t {
         match value {
@@ -160,6 +195,236 @@ impl From<Register> for hv_reg_t {
     }
 }
 
+/// ARM SIMD and floating-point register.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SimdFpRegister {
+    /// Q0 register.
+    Q0,
+
+    /// Q1 register.
+    Q1,
+
+    /// Q2 register.
+    Q2,
+
+    /// Q3 register.
+    Q3,
+
+    /// Q4 register.
+    Q4,
+
+    /// Q5 register.
+    Q5,
+
+    /// Q6 register.
+    Q6,
+
+    /// Q7 register.
+    Q7,
+
+    /// Q8 register.
+    Q8,
+
+    /// Q9 register.
+    Q9,
+
+    /// Q10 register.
+    Q10,
+
+    /// Q11 register.
+    Q11,
+
+    /// Q12 register.
+    Q12,
+
+    /// Q13 register.
+    Q13,
+
+    /// Q14 register.
+    Q14,
+
+    /// Q15 register.
+    Q15,
+
+    /// Q16 register.
+    Q16,
+
+    /// Q17 register.
+    Q17,
+
+    /// Q18 register.
+    Q18,
+
+    /// Q19 register.
+    Q19,
+
+    /// Q20 register.
+    Q20,
+
+    /// Q21 register.
+    Q21,
+
+    /// Q22 register.
+    Q22,
+
+    /// Q23 register.
+    Q23,
+
+    /// Q24 register.
+    Q24,
+
+    /// Q25 register.
+    Q25,
+
+    /// Q26 register.
+    Q26,
+
+    /// Q27 register.
+    Q27,
+
+    /// Q28 register.
+    Q28,
+
+    /// Q29 register.
+    Q29,
+
+    /// Q30 register.
+    Q30,
+
+    /// Q31 register.
+    Q31,
+}
+
+/// SIMD and floating-point registers Q0 to Q31, in order.
+pub const SIMD_FP_REGISTERS: [SimdFpRegister; 32] = [
+    SimdFpRegister::Q0,
+    SimdFpRegister::Q1,
+    SimdFpRegister::Q2,
+    SimdFpRegister::Q3,
+    SimdFpRegister::Q4,
+    SimdFpRegister::Q5,
+    SimdFpRegister::Q6,
+    SimdFpRegister::Q7,
+    SimdFpRegister::Q8,
+    SimdFpRegister::Q9,
+    SimdFpRegister::Q10,
+    SimdFpRegister::Q11,
+    SimdFpRegister::Q12,
+    SimdFpRegister::Q13,
+    SimdFpRegister::Q14,
+    SimdFpRegister::Q15,
+    SimdFpRegister::Q16,
+    SimdFpRegister::Q17,
+    SimdFpRegister::Q18,
+    SimdFpRegister::Q19,
+    SimdFpRegister::Q20,
+    SimdFpRegister::Q21,
+    SimdFpRegister::Q22,
+    SimdFpRegister::Q23,
+    SimdFpRegister::Q24,
+    SimdFpRegister::Q25,
+    SimdFpRegister::Q26,
+    SimdFpRegister::Q27,
+    SimdFpRegister::Q28,
+    SimdFpRegister::Q29,
+    SimdFpRegister::Q30,
+    SimdFpRegister::Q31,
+];
+
+impl From<SimdFpRegister> for hv_simd_fp_reg_t {
+    fn from(value: SimdFpRegister) -> hv_simd_fp_reg_t {
+        match value {
+            SimdFpRegister::Q0 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q0,
+            SimdFpRegister::Q1 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q1,
+            SimdFpRegister::Q2 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q2,
+            SimdFpRegister::Q3 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q3,
+            SimdFpRegister::Q4 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q4,
+            SimdFpRegister::Q5 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q5,
+            SimdFpRegister::Q6 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q6,
+            SimdFpRegister::Q7 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q7,
+            SimdFpRegister::Q8 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q8,
+            SimdFpRegister::Q9 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q9,
+            SimdFpRegister::Q10 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q10,
+            SimdFpRegister::Q11 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q11,
+            SimdFpRegister::Q12 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q12,
+            SimdFpRegister::Q13 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q13,
+            SimdFpRegister::Q14 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q14,
+            SimdFpRegister::Q15 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q15,
+            SimdFpRegister::Q16 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q16,
+            SimdFpRegister::Q17 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q17,
+            SimdFpRegister::Q18 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q18,
+            SimdFpRegister::Q19 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q19,
+            SimdFpRegister::Q20 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q20,
+            SimdFpRegister::Q21 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q21,
+            SimdFpRegister::Q22 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q22,
+            SimdFpRegister::Q23 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q23,
+            SimdFpRegister::Q24 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q24,
+            SimdFpRegister::Q25 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q25,
+            SimdFpRegister::Q26 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q26,
+            SimdFpRegister::Q27 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q27,
+            SimdFpRegister::Q28 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q28,
+            SimdFpRegister::Q29 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q29,
+            SimdFpRegister::Q30 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q30,
+            SimdFpRegister::Q31 => hv_simd_fp_reg_t_HV_SIMD_FP_REG_Q31,
+        }
+    }
+}
+
+/// Value of a 128-bit SIMD and floating-point register.
+///
+/// Byte 0 of the little-endian representation is the lowest byte of the register (lane 0 of
+/// vectors, and the low byte of the D, S and H views).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SimdFpValue(pub u128);
+
+impl SimdFpValue {
+    /// Create a value from its little-endian bytes.
+    pub const fn from_le_bytes(bytes: [u8; 16]) -> Self {
+        SimdFpValue(u128::from_le_bytes(bytes))
+    }
+
+    /// Gets the little-endian bytes of the value.
+    pub const fn to_le_bytes(self) -> [u8; 16] {
+        self.0.to_le_bytes()
+    }
+
+    /// Gets the low 64 bits (D view).
+    pub const fn low_u64(self) -> u64 {
+        self.0 as u64
+    }
+
+    /// Gets the high 64 bits.
+    pub const fn high_u64(self) -> u64 {
+        (self.0 >> 64) as u64
+    }
+}
+
+impl From<u128> for SimdFpValue {
+    fn from(value: u128) -> SimdFpValue {
+        SimdFpValue(value)
+    }
+}
+
+impl From<SimdFpValue> for u128 {
+    fn from(value: SimdFpValue) -> u128 {
+        value.0
+    }
+}
+
+/// General purpose registers, PC and CPSR of a vCPU, as transferred in one go by
+/// [VirtualCpu::get_general_registers](crate::VirtualCpu::get_general_registers).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GeneralRegisters {
+    /// X0 to X30.
+    pub x: [u64; 31],
+
+    /// The PC register.
+    pub pc: u64,
+
+    /// The CPSR register.
+    pub cpsr: u64,
+}
+
 #[derive(Copy, Clone, Debug)]
 #[allow(non_camel_case_types)]
 /// Feature register.
@@ -221,7 +486,7 @@ impl From<FeatureRegister> for hv_feature_reg_t {
 }
 
 /// ARM system register.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum SystemRegister {
     /// DBGBVR0_EL1 register.
@@ -559,8 +824,228 @@ pub enum SystemRegister {
 
     /// SP_EL1 register.
     SP_EL1,
+
+    /// CNTHCTL_EL2 register (only available with EL2 enabled).
+    CNTHCTL_EL2,
+
+    /// CNTHP_CTL_EL2 register (only available with EL2 enabled).
+    CNTHP_CTL_EL2,
+
+    /// CNTHP_CVAL_EL2 register (only available with EL2 enabled).
+    CNTHP_CVAL_EL2,
+
+    /// CNTHP_TVAL_EL2 register (only available with EL2 enabled).
+    CNTHP_TVAL_EL2,
+
+    /// CNTVOFF_EL2 register (only available with EL2 enabled).
+    CNTVOFF_EL2,
+
+    /// CPTR_EL2 register (only available with EL2 enabled).
+    CPTR_EL2,
+
+    /// ELR_EL2 register (only available with EL2 enabled).
+    ELR_EL2,
+
+    /// ESR_EL2 register (only available with EL2 enabled).
+    ESR_EL2,
+
+    /// FAR_EL2 register (only available with EL2 enabled).
+    FAR_EL2,
+
+    /// HCR_EL2 register (only available with EL2 enabled).
+    HCR_EL2,
+
+    /// HPFAR_EL2 register (only available with EL2 enabled).
+    HPFAR_EL2,
+
+    /// MAIR_EL2 register (only available with EL2 enabled).
+    MAIR_EL2,
+
+    /// MDCR_EL2 register (only available with EL2 enabled).
+    MDCR_EL2,
+
+    /// SCTLR_EL2 register (only available with EL2 enabled).
+    SCTLR_EL2,
+
+    /// SPSR_EL2 register (only available with EL2 enabled).
+    SPSR_EL2,
+
+    /// SP_EL2 register (only available with EL2 enabled).
+    SP_EL2,
+
+    /// TCR_EL2 register (only available with EL2 enabled).
+    TCR_EL2,
+
+    /// TPIDR_EL2 register (only available with EL2 enabled).
+    TPIDR_EL2,
+
+    /// TTBR0_EL2 register (only available with EL2 enabled).
+    TTBR0_EL2,
+
+    /// TTBR1_EL2 register (only available with EL2 enabled).
+    TTBR1_EL2,
+
+    /// VBAR_EL2 register (only available with EL2 enabled).
+    VBAR_EL2,
+
+    /// VMPIDR_EL2 register (only available with EL2 enabled).
+    VMPIDR_EL2,
+
+    /// VPIDR_EL2 register (only available with EL2 enabled).
+    VPIDR_EL2,
+
+    /// VTCR_EL2 register (only available with EL2 enabled).
+    VTCR_EL2,
+
+    /// VTTBR_EL2 register (only available with EL2 enabled).
+    VTTBR_EL2,
 }
 
+/// System registers available without EL2, in declaration order.
+pub const SYSTEM_REGISTERS: [SystemRegister; 112] = [
+    SystemRegister::DBGBVR0_EL1,
+    SystemRegister::DBGBCR0_EL1,
+    SystemRegister::DBGWVR0_EL1,
+    SystemRegister::DBGWCR0_EL1,
+    SystemRegister::DBGBVR1_EL1,
+    SystemRegister::DBGBCR1_EL1,
+    SystemRegister::DBGWVR1_EL1,
+    SystemRegister::DBGWCR1_EL1,
+    SystemRegister::MDCCINT_EL1,
+    SystemRegister::MDSCR_EL1,
+    SystemRegister::DBGBVR2_EL1,
+    SystemRegister::DBGBCR2_EL1,
+    SystemRegister::DBGWVR2_EL1,
+    SystemRegister::DBGWCR2_EL1,
+    SystemRegister::DBGBVR3_EL1,
+    SystemRegister::DBGBCR3_EL1,
+    SystemRegister::DBGWVR3_EL1,
+    SystemRegister::DBGWCR3_EL1,
+    SystemRegister::DBGBVR4_EL1,
+    SystemRegister::DBGBCR4_EL1,
+    SystemRegister::DBGWVR4_EL1,
+    SystemRegister::DBGWCR4_EL1,
+    SystemRegister::DBGBVR5_EL1,
+    SystemRegister::DBGBCR5_EL1,
+    SystemRegister::DBGWVR5_EL1,
+    SystemRegister::DBGWCR5_EL1,
+    SystemRegister::DBGBVR6_EL1,
+    SystemRegister::DBGBCR6_EL1,
+    SystemRegister::DBGWVR6_EL1,
+    SystemRegister::DBGWCR6_EL1,
+    SystemRegister::DBGBVR7_EL1,
+    SystemRegister::DBGBCR7_EL1,
+    SystemRegister::DBGWVR7_EL1,
+    SystemRegister::DBGWCR7_EL1,
+    SystemRegister::DBGBVR8_EL1,
+    SystemRegister::DBGBCR8_EL1,
+    SystemRegister::DBGWVR8_EL1,
+    SystemRegister::DBGWCR8_EL1,
+    SystemRegister::DBGBVR9_EL1,
+    SystemRegister::DBGBCR9_EL1,
+    SystemRegister::DBGWVR9_EL1,
+    SystemRegister::DBGWCR9_EL1,
+    SystemRegister::DBGBVR10_EL1,
+    SystemRegister::DBGBCR10_EL1,
+    SystemRegister::DBGWVR10_EL1,
+    SystemRegister::DBGWCR10_EL1,
+    SystemRegister::DBGBVR11_EL1,
+    SystemRegister::DBGBCR11_EL1,
+    SystemRegister::DBGWVR11_EL1,
+    SystemRegister::DBGWCR11_EL1,
+    SystemRegister::DBGBVR12_EL1,
+    SystemRegister::DBGBCR12_EL1,
+    SystemRegister::DBGWVR12_EL1,
+    SystemRegister::DBGWCR12_EL1,
+    SystemRegister::DBGBVR13_EL1,
+    SystemRegister::DBGBCR13_EL1,
+    SystemRegister::DBGWVR13_EL1,
+    SystemRegister::DBGWCR13_EL1,
+    SystemRegister::DBGBVR14_EL1,
+    SystemRegister::DBGBCR14_EL1,
+    SystemRegister::DBGWVR14_EL1,
+    SystemRegister::DBGWCR14_EL1,
+    SystemRegister::DBGBVR15_EL1,
+    SystemRegister::DBGBCR15_EL1,
+    SystemRegister::DBGWVR15_EL1,
+    SystemRegister::DBGWCR15_EL1,
+    SystemRegister::MIDR_EL1,
+    SystemRegister::MPIDR_EL1,
+    SystemRegister::ID_AA64PFR0_EL1,
+    SystemRegister::ID_AA64PFR1_EL1,
+    SystemRegister::ID_AA64DFR0_EL1,
+    SystemRegister::ID_AA64DFR1_EL1,
+    SystemRegister::ID_AA64ISAR0_EL1,
+    SystemRegister::ID_AA64ISAR1_EL1,
+    SystemRegister::ID_AA64MMFR0_EL1,
+    SystemRegister::ID_AA64MMFR1_EL1,
+    SystemRegister::ID_AA64MMFR2_EL1,
+    SystemRegister::SCTLR_EL1,
+    SystemRegister::CPACR_EL1,
+    SystemRegister::TTBR0_EL1,
+    SystemRegister::TTBR1_EL1,
+    SystemRegister::TCR_EL1,
+    SystemRegister::APIAKEYLO_EL1,
+    SystemRegister::APIAKEYHI_EL1,
+    SystemRegister::APIBKEYLO_EL1,
+    SystemRegister::APIBKEYHI_EL1,
+    SystemRegister::APDAKEYLO_EL1,
+    SystemRegister::APDAKEYHI_EL1,
+    SystemRegister::APDBKEYLO_EL1,
+    SystemRegister::APDBKEYHI_EL1,
+    SystemRegister::APGAKEYLO_EL1,
+    SystemRegister::APGAKEYHI_EL1,
+    SystemRegister::SPSR_EL1,
+    SystemRegister::ELR_EL1,
+    SystemRegister::SP_EL0,
+    SystemRegister::AFSR0_EL1,
+    SystemRegister::AFSR1_EL1,
+    SystemRegister::ESR_EL1,
+    SystemRegister::FAR_EL1,
+    SystemRegister::PAR_EL1,
+    SystemRegister::MAIR_EL1,
+    SystemRegister::AMAIR_EL1,
+    SystemRegister::VBAR_EL1,
+    SystemRegister::CONTEXTIDR_EL1,
+    SystemRegister::TPIDR_EL1,
+    SystemRegister::CNTKCTL_EL1,
+    SystemRegister::CSSELR_EL1,
+    SystemRegister::TPIDR_EL0,
+    SystemRegister::TPIDRRO_EL0,
+    SystemRegister::CNTV_CTL_EL0,
+    SystemRegister::CNTV_CVAL_EL0,
+    SystemRegister::SP_EL1,
+];
+
+/// System registers only available with EL2 enabled, in declaration order.
+pub const EL2_SYSTEM_REGISTERS: [SystemRegister; 25] = [
+    SystemRegister::CNTHCTL_EL2,
+    SystemRegister::CNTHP_CTL_EL2,
+    SystemRegister::CNTHP_CVAL_EL2,
+    SystemRegister::CNTHP_TVAL_EL2,
+    SystemRegister::CNTVOFF_EL2,
+    SystemRegister::CPTR_EL2,
+    SystemRegister::ELR_EL2,
+    SystemRegister::ESR_EL2,
+    SystemRegister::FAR_EL2,
+    SystemRegister::HCR_EL2,
+    SystemRegister::HPFAR_EL2,
+    SystemRegister::MAIR_EL2,
+    SystemRegister::MDCR_EL2,
+    SystemRegister::SCTLR_EL2,
+    SystemRegister::SPSR_EL2,
+    SystemRegister::SP_EL2,
+    SystemRegister::TCR_EL2,
+    SystemRegister::TPIDR_EL2,
+    SystemRegister::TTBR0_EL2,
+    SystemRegister::TTBR1_EL2,
+    SystemRegister::VBAR_EL2,
+    SystemRegister::VMPIDR_EL2,
+    SystemRegister::VPIDR_EL2,
+    SystemRegister::VTCR_EL2,
+    SystemRegister::VTTBR_EL2,
+];
+
 impl From<SystemRegister> for hv_sys_reg_t {
     fn from(value: SystemRegister) -> hv_sys_reg_t {
         match value {
@@ -676,6 +1161,31 @@ impl From<SystemRegister> for hv_sys_reg_t {
             SystemRegister::CNTV_CTL_EL0 => hv_sys_reg_t_HV_SYS_REG_CNTV_CTL_EL0,
             SystemRegister::CNTV_CVAL_EL0 => hv_sys_reg_t_HV_SYS_REG_CNTV_CVAL_EL0,
             SystemRegister::SP_EL1 => hv_sys_reg_t_HV_SYS_REG_SP_EL1,
+            SystemRegister::CNTHCTL_EL2 => hv_sys_reg_t_HV_SYS_REG_CNTHCTL_EL2,
+            SystemRegister::CNTHP_CTL_EL2 => hv_sys_reg_t_HV_SYS_REG_CNTHP_CTL_EL2,
+            SystemRegister::CNTHP_CVAL_EL2 => hv_sys_reg_t_HV_SYS_REG_CNTHP_CVAL_EL2,
+            SystemRegister::CNTHP_TVAL_EL2 => hv_sys_reg_t_HV_SYS_REG_CNTHP_TVAL_EL2,
+            SystemRegister::CNTVOFF_EL2 => hv_sys_reg_t_HV_SYS_REG_CNTVOFF_EL2,
+            SystemRegister::CPTR_EL2 => hv_sys_reg_t_HV_SYS_REG_CPTR_EL2,
+            SystemRegister::ELR_EL2 => hv_sys_reg_t_HV_SYS_REG_ELR_EL2,
+            SystemRegister::ESR_EL2 => hv_sys_reg_t_HV_SYS_REG_ESR_EL2,
+            SystemRegister::FAR_EL2 => hv_sys_reg_t_HV_SYS_REG_FAR_EL2,
+            SystemRegister::HCR_EL2 => hv_sys_reg_t_HV_SYS_REG_HCR_EL2,
+            SystemRegister::HPFAR_EL2 => hv_sys_reg_t_HV_SYS_REG_HPFAR_EL2,
+            SystemRegister::MAIR_EL2 => hv_sys_reg_t_HV_SYS_REG_MAIR_EL2,
+            SystemRegister::MDCR_EL2 => hv_sys_reg_t_HV_SYS_REG_MDCR_EL2,
+            SystemRegister::SCTLR_EL2 => hv_sys_reg_t_HV_SYS_REG_SCTLR_EL2,
+            SystemRegister::SPSR_EL2 => hv_sys_reg_t_HV_SYS_REG_SPSR_EL2,
+            SystemRegister::SP_EL2 => hv_sys_reg_t_HV_SYS_REG_SP_EL2,
+            SystemRegister::TCR_EL2 => hv_sys_reg_t_HV_SYS_REG_TCR_EL2,
+            SystemRegister::TPIDR_EL2 => hv_sys_reg_t_HV_SYS_REG_TPIDR_EL2,
+            SystemRegister::TTBR0_EL2 => hv_sys_reg_t_HV_SYS_REG_TTBR0_EL2,
+            SystemRegister::TTBR1_EL2 => hv_sys_reg_t_HV_SYS_REG_TTBR1_EL2,
+            SystemRegister::VBAR_EL2 => hv_sys_reg_t_HV_SYS_REG_VBAR_EL2,
+            SystemRegister::VMPIDR_EL2 => hv_sys_reg_t_HV_SYS_REG_VMPIDR_EL2,
+            SystemRegister::VPIDR_EL2 => hv_sys_reg_t_HV_SYS_REG_VPIDR_EL2,
+            SystemRegister::VTCR_EL2 => hv_sys_reg_t_HV_SYS_REG_VTCR_EL2,
+            SystemRegister::VTTBR_EL2 => hv_sys_reg_t_HV_SYS_REG_VTTBR_EL2,
         }
     }
 }
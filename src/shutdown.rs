@@ -0,0 +1,168 @@
+//! Signal-driven clean shutdown.
+//!
+//! Once [install_signal_handlers] is called, SIGINT and SIGTERM no longer kill the process:
+//! instead every vCPU registered with [register_vcpu] is forced to exit and
+//! [is_shutdown_requested] starts returning `true`. The run loops are expected to check it on
+//! [crate::vcpu::VirtualCpuExitReason::Cancelled] exits, drop their vCPU in their own thread and
+//! let the Virtual Machine be dropped last.
+//!
+//! [teardown] performs that ordered shutdown from the main thread: the hooks registered with
+//! [register_shutdown_hook] run first (flushing disks, consoles...), then the vCPUs are stopped and
+//! their threads joined, and the Virtual Machine is destroyed last.
+
+use crate::bindings::{hv_vcpu_t, hv_vcpus_exit};
+use crate::err::{HypervisorError, Result};
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+/// Maximum number of vCPUs that can be registered.
+const MAX_VCPUS: usize = 64;
+
+/// Marker of a free registration slot.
+const FREE_SLOT: u64 = u64::MAX;
+
+/// Set once a shutdown signal was received.
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Handles of the registered vCPUs.
+static VCPUS: [AtomicU64; MAX_VCPUS] = [const { AtomicU64::new(FREE_SLOT) }; MAX_VCPUS];
+
+/// A hook called by [teardown] before the vCPUs are stopped.
+pub type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+/// Hooks to call on teardown, in registration order.
+static SHUTDOWN_HOOKS: Mutex<Vec<ShutdownHook>> = Mutex::new(Vec::new());
+
+/// Register a vCPU to force exit on shutdown.
+pub fn register_vcpu(vcpu: hv_vcpu_t) -> Result<()> {
+    for slot in VCPUS.iter() {
+        if slot
+            .compare_exchange(FREE_SLOT, vcpu, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+
+    Err(HypervisorError::NoResources)
+}
+
+/// Unregister a vCPU, this must be called before destroying it.
+pub fn unregister_vcpu(vcpu: hv_vcpu_t) {
+    for slot in VCPUS.iter() {
+        let _ = slot.compare_exchange(vcpu, FREE_SLOT, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+/// Check if a shutdown was requested.
+pub fn is_shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Request a shutdown, as if a signal was received.
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+
+    let mut vcpus = [0; MAX_VCPUS];
+    let mut count = 0;
+
+    for slot in VCPUS.iter() {
+        let vcpu = slot.load(Ordering::SeqCst);
+
+        if vcpu != FREE_SLOT {
+            vcpus[count] = vcpu;
+            count += 1;
+        }
+    }
+
+    if count != 0 {
+        // Nothing can be done about errors in a signal handler.
+        unsafe {
+            hv_vcpus_exit(vcpus.as_mut_ptr(), count as u32);
+        }
+    }
+}
+
+/// Signal handler of SIGINT and SIGTERM.
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    request_shutdown();
+}
+
+/// Install the SIGINT and SIGTERM handlers.
+pub fn install_signal_handlers() -> Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let ret = unsafe {
+            let mut action: libc::sigaction = core::mem::zeroed();
+
+            action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            libc::sigaction(signal, &action, core::ptr::null_mut())
+        };
+
+        if ret != 0 {
+            return Err(HypervisorError::Error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Register a hook called by [teardown], in registration order, before the vCPUs are stopped.
+pub fn register_shutdown_hook<F>(hook: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    SHUTDOWN_HOOKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(Box::new(hook));
+}
+
+/// Shut down a Virtual Machine in order: run the shutdown hooks, stop the registered vCPUs and
+/// join their threads (which drop their vCPU on their way out), then destroy the Virtual Machine.
+///
+/// Returns the values returned by the vCPU threads, in the given order. All threads are joined
+/// and the Virtual Machine destroyed even if one of them panicked, in which case
+/// [HypervisorError::Error] is returned.
+///
+/// **This should be called from the thread owning the Virtual Machine, not from a vCPU thread.**
+pub fn teardown<T>(vm: VirtualMachine, vcpu_threads: Vec<JoinHandle<T>>) -> Result<Vec<T>> {
+    let hooks = core::mem::take(
+        &mut *SHUTDOWN_HOOKS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+
+    for hook in hooks {
+        hook();
+    }
+
+    request_shutdown();
+
+    let mut results = Vec::with_capacity(vcpu_threads.len());
+    let mut has_panicked = false;
+
+    for thread in vcpu_threads {
+        match thread.join() {
+            Ok(value) => results.push(value),
+            Err(_) => has_panicked = true,
+        }
+    }
+
+    drop(vm);
+
+    if has_panicked {
+        return Err(HypervisorError::Error);
+    }
+
+    Ok(results)
+}
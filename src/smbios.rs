@@ -0,0 +1,232 @@
+//! SMBIOS table generation.
+//!
+//! Describes the firmware and the system (vendor, product, serial number, UUID) to the guest,
+//! which is what DMI-based guest tooling (cloud-init datasource detection, `dmidecode`, ...)
+//! keys off. The tables are laid out behind a 64-bit (SMBIOS 3) entry point, to be published to
+//! the guest by the firmware (usually through the EFI configuration table).
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Size of the SMBIOS 3 entry point.
+pub const ENTRY_POINT_SIZE: usize = 24;
+
+/// SMBIOS version implemented (major, minor).
+const SMBIOS_VERSION: (u8, u8) = (3, 2);
+
+/// BIOS information structure type.
+const TYPE_BIOS_INFORMATION: u8 = 0;
+
+/// System information structure type.
+const TYPE_SYSTEM_INFORMATION: u8 = 1;
+
+/// End-of-table structure type.
+const TYPE_END_OF_TABLE: u8 = 127;
+
+/// BIOS characteristic: characteristics are not supported.
+const BIOS_CHARACTERISTICS_NOT_SUPPORTED: u64 = 1 << 3;
+
+/// BIOS characteristic extension byte 2: UEFI is supported.
+const BIOS_CHARACTERISTICS_EXT2_UEFI: u8 = 1 << 3;
+
+/// BIOS characteristic extension byte 2: the system is a virtual machine.
+const BIOS_CHARACTERISTICS_EXT2_VIRTUAL_MACHINE: u8 = 1 << 4;
+
+/// System wake-up type: power switch.
+const WAKE_UP_TYPE_POWER_SWITCH: u8 = 6;
+
+/// Identification strings and UUID reported by the SMBIOS tables.
+///
+/// Empty strings are reported as absent.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SmbiosInfo<'a> {
+    /// The firmware vendor.
+    pub bios_vendor: &'a str,
+
+    /// The firmware version.
+    pub bios_version: &'a str,
+
+    /// The firmware release date (`mm/dd/yyyy`).
+    pub bios_release_date: &'a str,
+
+    /// The system manufacturer (system vendor).
+    pub manufacturer: &'a str,
+
+    /// The system product name.
+    pub product_name: &'a str,
+
+    /// The system version.
+    pub version: &'a str,
+
+    /// The system serial number.
+    pub serial_number: &'a str,
+
+    /// The system UUID, in RFC 4122 byte order.
+    pub uuid: [u8; 16],
+
+    /// The system SKU number.
+    pub sku_number: &'a str,
+
+    /// The system family.
+    pub family: &'a str,
+}
+
+/// A structure being built, with its string set.
+struct Structure {
+    /// The formatted area.
+    formatted: Vec<u8>,
+
+    /// The string set.
+    strings: Vec<u8>,
+
+    /// Number of strings in the set.
+    string_count: u8,
+}
+
+impl Structure {
+    /// Start a new structure of a given type and handle.
+    fn new(structure_type: u8, handle: u16) -> Self {
+        let mut formatted = Vec::from([structure_type, 0]);
+
+        formatted.extend_from_slice(&handle.to_le_bytes());
+
+        Structure {
+            formatted,
+            strings: Vec::new(),
+            string_count: 0,
+        }
+    }
+
+    /// Append a string reference, adding the string to the set.
+    fn string(&mut self, value: &str) -> Result<&mut Self> {
+        if value.contains('\0') {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        if value.is_empty() {
+            self.formatted.push(0);
+        } else {
+            self.string_count += 1;
+            self.formatted.push(self.string_count);
+            self.strings.extend_from_slice(value.as_bytes());
+            self.strings.push(0);
+        }
+
+        Ok(self)
+    }
+
+    /// Append raw bytes to the formatted area.
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.formatted.extend_from_slice(value);
+
+        self
+    }
+
+    /// Append the structure to a table.
+    fn finish(mut self, table: &mut Vec<u8>) {
+        self.formatted[1] = self.formatted.len() as u8;
+
+        table.extend_from_slice(&self.formatted);
+        table.extend_from_slice(&self.strings);
+
+        // The string set ends with a double NUL, even when empty.
+        if self.strings.is_empty() {
+            table.push(0);
+        }
+
+        table.push(0);
+    }
+}
+
+/// Convert a UUID from RFC 4122 byte order to the SMBIOS one (first three fields little-endian).
+fn smbios_uuid(uuid: [u8; 16]) -> [u8; 16] {
+    let mut result = uuid;
+
+    result[0..4].reverse();
+    result[4..6].reverse();
+    result[6..8].reverse();
+
+    result
+}
+
+/// Build the SMBIOS structure table (BIOS information, system information, end of table).
+pub fn structure_table(info: &SmbiosInfo) -> Result<Vec<u8>> {
+    let mut table = Vec::new();
+
+    let mut bios = Structure::new(TYPE_BIOS_INFORMATION, 0);
+
+    bios.string(info.bios_vendor)?
+        .string(info.bios_version)?
+        .bytes(&0u16.to_le_bytes())
+        .string(info.bios_release_date)?
+        .bytes(&[0])
+        .bytes(&BIOS_CHARACTERISTICS_NOT_SUPPORTED.to_le_bytes())
+        .bytes(&[
+            0,
+            BIOS_CHARACTERISTICS_EXT2_UEFI | BIOS_CHARACTERISTICS_EXT2_VIRTUAL_MACHINE,
+        ])
+        // Unknown BIOS and embedded controller releases.
+        .bytes(&[0xFF, 0xFF, 0xFF, 0xFF])
+        .bytes(&0u16.to_le_bytes());
+    bios.finish(&mut table);
+
+    let mut system = Structure::new(TYPE_SYSTEM_INFORMATION, 1);
+
+    system
+        .string(info.manufacturer)?
+        .string(info.product_name)?
+        .string(info.version)?
+        .string(info.serial_number)?
+        .bytes(&smbios_uuid(info.uuid))
+        .bytes(&[WAKE_UP_TYPE_POWER_SWITCH])
+        .string(info.sku_number)?
+        .string(info.family)?;
+    system.finish(&mut table);
+
+    Structure::new(TYPE_END_OF_TABLE, 2).finish(&mut table);
+
+    Ok(table)
+}
+
+/// Build the SMBIOS 3 entry point of a structure table.
+pub fn entry_point(table_address: hv_ipa_t, table_size: usize) -> Result<[u8; ENTRY_POINT_SIZE]> {
+    let table_size = u32::try_from(table_size).map_err(|_| HypervisorError::BadArgument)?;
+
+    let mut result = [0u8; ENTRY_POINT_SIZE];
+
+    result[0..5].copy_from_slice(b"_SM3_");
+    result[6] = ENTRY_POINT_SIZE as u8;
+    result[7] = SMBIOS_VERSION.0;
+    result[8] = SMBIOS_VERSION.1;
+    // Entry point structure revision.
+    result[10] = 1;
+    result[12..16].copy_from_slice(&table_size.to_le_bytes());
+    result[16..24].copy_from_slice(&table_address.to_le_bytes());
+
+    result[5] = result
+        .iter()
+        .fold(0u8, |sum, value| sum.wrapping_add(*value))
+        .wrapping_neg();
+
+    Ok(result)
+}
+
+/// Lay out the entry point and the structure table in a blob to load at a given guest address.
+///
+/// The entry point is at the start of the blob, immediately followed by the structure table.
+pub fn build(address: hv_ipa_t, info: &SmbiosInfo) -> Result<Vec<u8>> {
+    let table = structure_table(info)?;
+
+    let mut result = Vec::with_capacity(ENTRY_POINT_SIZE + table.len());
+
+    result.extend_from_slice(&entry_point(
+        address + ENTRY_POINT_SIZE as u64,
+        table.len(),
+    )?);
+    result.extend_from_slice(&table);
+
+    Ok(result)
+}
@@ -0,0 +1,276 @@
+//! SMC Calling Convention (SMCCC) dispatching.
+//!
+//! This module parses the function identifiers of guest SMC/HVC calls, routes them to registered
+//! services and writes the return values back in the registers expected by the guest.
+
+use crate::err::Result;
+use crate::reg::Register;
+use crate::vcpu::{ExceptionClass, ExceptionExit, VirtualCpu};
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use core::ops::RangeInclusive;
+
+pub mod psci;
+pub mod trng;
+
+/// Return value used when a function isn't implemented.
+pub const NOT_SUPPORTED: u64 = -1i64 as u64;
+
+/// Return value used when a function receives invalid parameters.
+pub const INVALID_PARAMETERS: u64 = -2i64 as u64;
+
+/// SMCCC_VERSION function identifier.
+pub const SMCCC_VERSION: u32 = 0x8000_0000;
+
+/// SMCCC_ARCH_FEATURES function identifier.
+pub const SMCCC_ARCH_FEATURES: u32 = 0x8000_0001;
+
+/// The SMCCC version reported to the guest (v1.1).
+const VERSION_1_1: u64 = 0x1_0001;
+
+/// Owner of a SMCCC function.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ServiceOwner {
+    /// Arm Architecture calls.
+    Arm,
+
+    /// CPU service calls.
+    Cpu,
+
+    /// Silicon Partner service calls.
+    SiP,
+
+    /// OEM service calls.
+    Oem,
+
+    /// Standard secure service calls (PSCI, TRNG, ...).
+    Standard,
+
+    /// Standard hypervisor service calls.
+    StandardHypervisor,
+
+    /// Vendor specific hypervisor service calls.
+    VendorHypervisor,
+
+    /// Vendor specific EL3 monitor calls.
+    VendorEl3Monitor,
+
+    /// Trusted application calls.
+    TrustedApplication(u8),
+
+    /// Trusted OS calls.
+    TrustedOs(u8),
+
+    /// Reserved owner.
+    Reserved(u8),
+}
+
+impl From<u8> for ServiceOwner {
+    fn from(value: u8) -> ServiceOwner {
+        match value {
+            0 => ServiceOwner::Arm,
+            1 => ServiceOwner::Cpu,
+            2 => ServiceOwner::SiP,
+            3 => ServiceOwner::Oem,
+            4 => ServiceOwner::Standard,
+            5 => ServiceOwner::StandardHypervisor,
+            6 => ServiceOwner::VendorHypervisor,
+            7 => ServiceOwner::VendorEl3Monitor,
+            48..=49 => ServiceOwner::TrustedApplication(value),
+            50..=63 => ServiceOwner::TrustedOs(value),
+            _ => ServiceOwner::Reserved(value),
+        }
+    }
+}
+
+/// A SMCCC function identifier.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FunctionId(pub u32);
+
+impl FunctionId {
+    /// Check if this is a fast call (as opposed to a yielding call).
+    pub fn is_fast_call(&self) -> bool {
+        self.0 & (1 << 31) != 0
+    }
+
+    /// Check if this call uses the SMC64/HVC64 calling convention.
+    pub fn is_64bit(&self) -> bool {
+        self.0 & (1 << 30) != 0
+    }
+
+    /// Gets the owner of the function.
+    pub fn owner(&self) -> ServiceOwner {
+        ServiceOwner::from(((self.0 >> 24) & 0x3f) as u8)
+    }
+
+    /// Gets the function number.
+    pub fn number(&self) -> u16 {
+        self.0 as u16
+    }
+}
+
+/// The instruction used to issue a call.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Conduit {
+    /// HVC instruction.
+    Hvc,
+
+    /// SMC instruction.
+    Smc,
+}
+
+/// A SMCCC call issued by the guest.
+#[derive(Copy, Clone, Debug)]
+pub struct Call {
+    /// The instruction used to issue the call.
+    pub conduit: Conduit,
+
+    /// The function identifier (W0).
+    pub function: FunctionId,
+
+    /// The arguments of the call (X1 to X7).
+    pub arguments: [u64; 7],
+}
+
+/// A service handling SMCCC calls.
+pub trait Service {
+    /// Handle a call routed to this service.
+    ///
+    /// Returns the values of X0 to X3, or `None` if the function isn't implemented.
+    fn call(&mut self, call: &Call) -> Option<[u64; 4]>;
+}
+
+/// A registered service.
+struct ServiceEntry {
+    /// The owner of the functions handled by the service.
+    owner: ServiceOwner,
+
+    /// The range of function numbers handled by the service.
+    functions: RangeInclusive<u16>,
+
+    /// The service.
+    service: Box<dyn Service>,
+}
+
+/// Routes SMCCC calls to registered services.
+#[derive(Default)]
+pub struct Dispatcher {
+    /// List of all registered services.
+    services: Vec<ServiceEntry>,
+}
+
+impl core::fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("services", &self.services.len())
+            .finish()
+    }
+}
+
+impl Dispatcher {
+    /// Create a new dispatcher without any registered service.
+    pub fn new() -> Self {
+        Dispatcher::default()
+    }
+
+    /// Register a service for a range of function numbers of a given owner.
+    pub fn register(
+        &mut self,
+        owner: ServiceOwner,
+        functions: RangeInclusive<u16>,
+        service: Box<dyn Service>,
+    ) {
+        self.services.push(ServiceEntry {
+            owner,
+            functions,
+            service,
+        });
+    }
+
+    /// Find the service handling a given function.
+    fn find_service(&mut self, function: FunctionId) -> Option<&mut ServiceEntry> {
+        self.services.iter_mut().find(|entry| {
+            entry.owner == function.owner() && entry.functions.contains(&function.number())
+        })
+    }
+
+    /// Dispatch a call and return the values of X0 to X3.
+    pub fn dispatch(&mut self, call: &Call) -> [u64; 4] {
+        match call.function.0 {
+            SMCCC_VERSION => [VERSION_1_1, 0, 0, 0],
+            SMCCC_ARCH_FEATURES => {
+                let queried = FunctionId(call.arguments[0] as u32);
+
+                if queried.0 == SMCCC_VERSION || self.find_service(queried).is_some() {
+                    [0, 0, 0, 0]
+                } else {
+                    [NOT_SUPPORTED, 0, 0, 0]
+                }
+            }
+            _ => self
+                .find_service(call.function)
+                .and_then(|entry| entry.service.call(call))
+                .unwrap_or([NOT_SUPPORTED, 0, 0, 0]),
+        }
+    }
+
+    /// Handle a HVC or SMC exception exit of a vCPU.
+    ///
+    /// Returns `false` if the exception isn't a SMCCC call, in which case the vCPU is left untouched.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn handle_exit(
+        &mut self,
+        vcpu: &mut VirtualCpu,
+        exception: &ExceptionExit,
+    ) -> Result<bool> {
+        let conduit = match exception.class() {
+            ExceptionClass::HvcAArch64 => Conduit::Hvc,
+            ExceptionClass::SmcAArch64 => Conduit::Smc,
+            _ => return Ok(false),
+        };
+
+        const ARGUMENT_REGISTERS: [Register; 7] = [
+            Register::X1,
+            Register::X2,
+            Register::X3,
+            Register::X4,
+            Register::X5,
+            Register::X6,
+            Register::X7,
+        ];
+
+        let mut arguments = [0; 7];
+
+        for (argument, register) in arguments.iter_mut().zip(ARGUMENT_REGISTERS) {
+            *argument = vcpu.get_register(register)?;
+        }
+
+        let call = Call {
+            conduit,
+            function: FunctionId(vcpu.get_register(Register::X0)? as u32),
+            arguments,
+        };
+
+        let results = self.dispatch(&call);
+
+        const RESULT_REGISTERS: [Register; 4] =
+            [Register::X0, Register::X1, Register::X2, Register::X3];
+
+        for (value, register) in results.into_iter().zip(RESULT_REGISTERS) {
+            vcpu.set_register(register, value)?;
+        }
+
+        // A trapped SMC returns to the SMC instruction itself, skip over it.
+        if conduit == Conduit::Smc {
+            let pc = vcpu.get_register(Register::PC)?;
+
+            vcpu.set_register(Register::PC, pc + 4)?;
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,80 @@
+//! Power State Coordination Interface (DEN0022) system power functions.
+//!
+//! Only the functions affecting the whole system are implemented, they are reported to the
+//! embedder as [VmEvent]. The embedder is expected to stop running the vCPUs once one of them is
+//! received.
+
+use super::{Call, NOT_SUPPORTED, Service};
+use crate::event::VmEvent;
+
+extern crate alloc;
+use alloc::boxed::Box;
+
+use core::ops::RangeInclusive;
+
+/// Range of function numbers of the PSCI service (owned by [super::ServiceOwner::Standard]).
+pub const FUNCTIONS: RangeInclusive<u16> = 0x00..=0x1F;
+
+/// PSCI_VERSION function identifier.
+pub const PSCI_VERSION: u32 = 0x8400_0000;
+
+/// SYSTEM_OFF function identifier.
+pub const SYSTEM_OFF: u32 = 0x8400_0008;
+
+/// SYSTEM_RESET function identifier.
+pub const SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// PSCI_FEATURES function identifier.
+pub const PSCI_FEATURES: u32 = 0x8400_000A;
+
+/// The implemented version of the interface (v1.1).
+const VERSION_1_1: u64 = 0x1_0001;
+
+/// PSCI service reporting system power requests.
+pub struct Psci {
+    /// Callback receiving the events.
+    callback: Box<dyn FnMut(VmEvent)>,
+}
+
+impl core::fmt::Debug for Psci {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Psci").finish_non_exhaustive()
+    }
+}
+
+impl Psci {
+    /// Create a new PSCI service delivering events to the given callback.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: FnMut(VmEvent) + 'static,
+    {
+        Psci {
+            callback: Box::new(callback),
+        }
+    }
+}
+
+impl Service for Psci {
+    fn call(&mut self, call: &Call) -> Option<[u64; 4]> {
+        let result = match call.function.0 {
+            PSCI_VERSION => VERSION_1_1,
+            PSCI_FEATURES => match call.arguments[0] as u32 {
+                PSCI_VERSION | SYSTEM_OFF | SYSTEM_RESET | PSCI_FEATURES => 0,
+                _ => NOT_SUPPORTED,
+            },
+            SYSTEM_OFF => {
+                (self.callback)(VmEvent::PowerOff);
+
+                0
+            }
+            SYSTEM_RESET => {
+                (self.callback)(VmEvent::Reset);
+
+                0
+            }
+            _ => return None,
+        };
+
+        Some([result, 0, 0, 0])
+    }
+}
@@ -0,0 +1,121 @@
+//! Arm True Random Number Generator firmware interface (DEN0098).
+//!
+//! The entropy is provided by the host through `getentropy`.
+
+use super::{Call, INVALID_PARAMETERS, NOT_SUPPORTED, Service};
+
+use core::ffi::c_void;
+use core::ops::RangeInclusive;
+
+/// Range of function numbers of the TRNG service (owned by [super::ServiceOwner::Standard]).
+pub const FUNCTIONS: RangeInclusive<u16> = 0x50..=0x53;
+
+/// TRNG_VERSION function identifier.
+pub const TRNG_VERSION: u32 = 0x8400_0050;
+
+/// TRNG_FEATURES function identifier.
+pub const TRNG_FEATURES: u32 = 0x8400_0051;
+
+/// TRNG_GET_UUID function identifier.
+pub const TRNG_GET_UUID: u32 = 0x8400_0052;
+
+/// TRNG_RND function identifier (SMC32).
+pub const TRNG_RND32: u32 = 0x8400_0053;
+
+/// TRNG_RND function identifier (SMC64).
+pub const TRNG_RND64: u32 = 0xC400_0053;
+
+/// Return value used when no entropy is available.
+pub const NO_ENTROPY: u64 = -3i64 as u64;
+
+/// The implemented version of the interface (v1.0).
+const VERSION_1_0: u64 = 0x1_0000;
+
+/// The UUID identifying this TRNG implementation.
+const UUID: [u8; 16] = [
+    0x86, 0x59, 0x71, 0x5f, 0x37, 0x45, 0x4c, 0xda, 0x82, 0xcb, 0xb3, 0xe5, 0x90, 0xcc, 0x3e, 0x4b,
+];
+
+/// TRNG service backed by the host entropy.
+#[derive(Debug, Default)]
+pub struct Trng;
+
+impl Trng {
+    /// Create a new TRNG service.
+    pub fn new() -> Self {
+        Trng
+    }
+
+    /// Handle TRNG_RND, returning up to `register_bits * 3` bits of entropy in X1 to X3.
+    fn random(bits: u64, register_bits: u32) -> [u64; 4] {
+        let max_bits = u64::from(register_bits) * 3;
+
+        if bits == 0 || bits > max_bits {
+            return [INVALID_PARAMETERS, 0, 0, 0];
+        }
+
+        let mut entropy = [0u64; 3];
+
+        let ret = unsafe {
+            libc::getentropy(
+                entropy.as_mut_ptr() as *mut c_void,
+                core::mem::size_of_val(&entropy),
+            )
+        };
+
+        if ret != 0 {
+            return [NO_ENTROPY, 0, 0, 0];
+        }
+
+        // X3 holds the least significant bits, every bit above the requested count must be zero.
+        let mut result = [0; 4];
+        let mut remaining = bits;
+
+        for (index, value) in entropy.into_iter().enumerate() {
+            let register_mask = if register_bits == 64 {
+                u64::MAX
+            } else {
+                (1 << register_bits) - 1
+            };
+
+            let mask = if remaining >= u64::from(register_bits) {
+                register_mask
+            } else {
+                (1 << remaining) - 1
+            };
+
+            result[3 - index] = value & mask;
+            remaining = remaining.saturating_sub(u64::from(register_bits));
+        }
+
+        result
+    }
+}
+
+impl Service for Trng {
+    fn call(&mut self, call: &Call) -> Option<[u64; 4]> {
+        let result = match call.function.0 {
+            TRNG_VERSION => [VERSION_1_0, 0, 0, 0],
+            TRNG_FEATURES => match call.arguments[0] as u32 {
+                TRNG_VERSION | TRNG_FEATURES | TRNG_GET_UUID | TRNG_RND32 | TRNG_RND64 => {
+                    [0, 0, 0, 0]
+                }
+                _ => [NOT_SUPPORTED, 0, 0, 0],
+            },
+            TRNG_GET_UUID => {
+                let mut result = [0; 4];
+
+                for (value, bytes) in result.iter_mut().zip(UUID.chunks_exact(4)) {
+                    *value = u64::from(u32::from_le_bytes(bytes.try_into().unwrap()));
+                }
+
+                result
+            }
+            TRNG_RND32 => Trng::random(call.arguments[0], 32),
+            TRNG_RND64 => Trng::random(call.arguments[0], 64),
+            _ => return None,
+        };
+
+        Some(result)
+    }
+}
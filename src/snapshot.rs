@@ -0,0 +1,168 @@
+//! Whole virtual machine snapshots.
+//!
+//! A [VmSnapshot] captures the contents of every allocation, the mappings and ROMs of a
+//! [VirtualMachine], along with the [VcpuContext] of its vCPUs and optionally the GIC state.
+//! Restoring it brings the guest memory layout and contents back to the captured state, which is
+//! what fuzzers (reset after every iteration) and debuggers (checkpoints) need.
+//!
+//! vCPU states have to be saved and restored in the thread of each vCPU, so they are given to and
+//! taken from the snapshot by the caller, as is the GIC state.
+
+use crate::context::VcpuContext;
+use crate::err::{HypervisorError, Result};
+use crate::virtual_machine::{
+    AllocationHandle, MappingHandle, VirtualMachine, VirtualMachineMapping,
+};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// Captured contents of an allocation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AllocationSnapshot {
+    /// The allocation handle.
+    pub handle: AllocationHandle,
+
+    /// The allocation is external (host memory registered with
+    /// [VirtualMachine::allocate_external]), it can't be recreated on restore.
+    pub is_external: bool,
+
+    /// The contents of the allocation.
+    pub contents: Vec<u8>,
+}
+
+/// A captured state of a whole virtual machine.
+#[derive(Clone, Debug)]
+pub struct VmSnapshot {
+    /// The allocations and their contents.
+    pub allocations: Vec<AllocationSnapshot>,
+
+    /// The mappings.
+    pub mappings: Vec<VirtualMachineMapping>,
+
+    /// The ROM mappings with their original contents.
+    pub roms: Vec<(MappingHandle, Vec<u8>)>,
+
+    /// The state of each vCPU, in the order given on capture.
+    pub vcpus: Vec<VcpuContext>,
+
+    /// The state of the GIC, if captured.
+    pub gic: Option<Vec<u8>>,
+}
+
+impl VmSnapshot {
+    /// Capture the memory of a virtual machine, along with the state of its vCPUs (saved with
+    /// [crate::VirtualCpu::save_context] in their own threads).
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn capture(vm: &VirtualMachine, vcpus: Vec<VcpuContext>) -> Result<Self> {
+        let mut allocations = Vec::new();
+
+        for (handle, _, is_external) in vm.get_all_allocation_infos() {
+            allocations.push(AllocationSnapshot {
+                handle,
+                is_external,
+                contents: vm.get_allocation_slice(handle)?.to_vec(),
+            });
+        }
+
+        Ok(VmSnapshot {
+            allocations,
+            mappings: vm.get_all_mapping_infos(),
+            roms: vm.get_roms(),
+            vcpus,
+            gic: None,
+        })
+    }
+
+    /// Attach a GIC state captured by [Gic::save_state](crate::gic::Gic::save_state).
+    pub fn set_gic_state(&mut self, state: Vec<u8>) {
+        self.gic = Some(state);
+    }
+
+    /// Gets the saved state of a vCPU.
+    pub fn vcpu(&self, index: usize) -> Option<&VcpuContext> {
+        self.vcpus.get(index)
+    }
+
+    /// Gets the total size of the captured memory.
+    pub fn memory_size(&self) -> usize {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.contents.len())
+            .sum()
+    }
+
+    /// Restore the memory of a virtual machine to the captured state.
+    ///
+    /// Mappings and allocations created since the capture are removed, the removed ones are
+    /// recreated with their original handles, and the contents of every allocation are copied
+    /// back. External allocations can't be recreated and must still exist.
+    ///
+    /// The vCPUs must then be restored with [crate::VirtualCpu::restore_context] in their own
+    /// threads, and the GIC with [Gic::restore_state](crate::gic::Gic::restore_state).
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn restore(&self, vm: &mut VirtualMachine) -> Result<()> {
+        // Drop the mappings that changed or didn't exist at capture time.
+        for mapping in vm.get_all_mapping_infos() {
+            let is_captured = self.mappings.iter().any(|captured| {
+                captured.mapping_handle == mapping.mapping_handle
+                    && captured.allocation_handle == mapping.allocation_handle
+                    && captured.address == mapping.address
+            });
+
+            if !is_captured {
+                vm.unmap(mapping.mapping_handle)?;
+            }
+        }
+
+        let current_allocations = vm.get_all_allocation_infos();
+
+        for (handle, _, _) in current_allocations.iter() {
+            if !self
+                .allocations
+                .iter()
+                .any(|allocation| allocation.handle == *handle)
+            {
+                vm.deallocate(*handle)?;
+            }
+        }
+
+        for allocation in self.allocations.iter() {
+            let current = current_allocations
+                .iter()
+                .find(|(handle, _, _)| *handle == allocation.handle);
+
+            match current {
+                Some((_, size, _)) if *size != allocation.contents.len() => {
+                    return Err(HypervisorError::BadArgument);
+                }
+                Some(_) => {}
+                None if allocation.is_external => return Err(HypervisorError::InvalidHandle),
+                None => vm.allocate_with_handle(allocation.handle, allocation.contents.len())?,
+            }
+
+            vm.get_allocation_slice_mut(allocation.handle)?
+                .copy_from_slice(&allocation.contents);
+        }
+
+        for mapping in self.mappings.iter() {
+            match vm.get_mapping_info(mapping.mapping_handle) {
+                Ok(current) if current.permission != mapping.permission => {
+                    vm.reprotect(mapping.mapping_handle, mapping.permission)?;
+                }
+                Ok(current) if current.permission.is_executable() => {
+                    // The contents changed under an executable mapping.
+                    vm.clean_instruction_cache(mapping.allocation_handle)?;
+                }
+                Ok(_) => {}
+                Err(_) => vm.map_with_handle(*mapping)?,
+            }
+        }
+
+        vm.set_roms(self.roms.clone());
+
+        Ok(())
+    }
+}
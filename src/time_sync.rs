@@ -0,0 +1,181 @@
+//! Host time synchronization.
+//!
+//! The guest virtual counter keeps running at the host rate, but a guest clock still drifts away
+//! from the host one (while vCPUs are paused with a compensating vtimer offset, because of guest
+//! timekeeping errors, ...). [TimeSync] compares guest time reports, obtained by any guest agent
+//! channel, against the host wall clock and keeps the guest within a drift bound.
+//!
+//! A guest behind the host is caught up by gently moving the guest counter forward through the
+//! vtimer offset, never more than a configured step at a time. Moving the counter backward would
+//! break its monotonicity, so a guest ahead of the host is reported instead, for a guest agent to
+//! slew its own clock.
+
+use crate::device::pvclock::{MachTimebaseInfo, PvClock, mach_absolute_time};
+use crate::err::Result;
+use crate::vcpu::VirtualCpu;
+
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Action taken by a synchronization round.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyncAction {
+    /// No drift was measured, or it's within the bound.
+    None,
+
+    /// The guest counter was moved forward by a number of nanoseconds.
+    Adjusted(u64),
+
+    /// The guest is ahead of the host by a number of nanoseconds and must be notified.
+    NotifyGuest(u64),
+}
+
+/// Shared vtimer offset, applied to each vCPU in its own thread.
+#[derive(Clone, Debug)]
+pub struct VtimerOffsetHandle {
+    /// The vtimer offset to program.
+    offset: Arc<AtomicU64>,
+}
+
+impl VtimerOffsetHandle {
+    /// Gets the vtimer offset to program.
+    pub fn get(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// Program the vtimer offset in a vCPU if it changed.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn apply(&self, vcpu: &mut VirtualCpu) -> Result<()> {
+        let offset = self.get();
+
+        if vcpu.get_vtimer_offset()? != offset {
+            vcpu.set_vtimer_offset(offset)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Guest clock drift controller.
+#[derive(Debug)]
+pub struct TimeSync {
+    /// The maximum tolerated drift in nanoseconds.
+    max_drift_ns: u64,
+
+    /// The maximum adjustment of a single round in nanoseconds.
+    max_step_ns: u64,
+
+    /// The last measured drift (guest minus host) in nanoseconds.
+    drift_ns: Option<i64>,
+
+    /// The timebase of the guest virtual counter.
+    timebase: MachTimebaseInfo,
+
+    /// The vtimer offset programmed in the vCPUs.
+    offset: VtimerOffsetHandle,
+
+    /// The PV clocks publishing the guest counter, kept in sync with the offset.
+    pv_clocks: Vec<PvClock>,
+}
+
+impl TimeSync {
+    /// Create a new controller for vCPUs currently programmed with a given vtimer offset.
+    pub fn new(vtimer_offset: u64, max_drift_ns: u64, max_step_ns: u64) -> Result<Self> {
+        Ok(TimeSync {
+            max_drift_ns,
+            max_step_ns,
+            drift_ns: None,
+            timebase: MachTimebaseInfo::get()?,
+            offset: VtimerOffsetHandle {
+                offset: Arc::new(AtomicU64::new(vtimer_offset)),
+            },
+            pv_clocks: Vec::new(),
+        })
+    }
+
+    /// Keep a PV clock in sync with the vtimer offset, starting with the current one.
+    pub fn add_pv_clock(&mut self, clock: PvClock) {
+        clock.set_vtimer_offset(self.offset.get());
+
+        self.pv_clocks.push(clock);
+    }
+
+    /// Gets a handle to apply the vtimer offset to vCPUs.
+    pub fn vtimer_offset(&self) -> VtimerOffsetHandle {
+        self.offset.clone()
+    }
+
+    /// Gets the current host wall-clock time in nanoseconds since the Unix epoch.
+    pub fn host_time_ns() -> u64 {
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) };
+
+        now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+    }
+
+    /// Gets the guest virtual counter value matching the current vtimer offset.
+    pub fn guest_counter(&self) -> u64 {
+        unsafe { mach_absolute_time() }.wrapping_sub(self.offset.get())
+    }
+
+    /// Record a guest wall-clock time report (in nanoseconds since the Unix epoch) taken at a
+    /// given host time, and return the measured drift.
+    pub fn measure(&mut self, guest_time_ns: u64, host_time_ns: u64) -> i64 {
+        let drift = guest_time_ns.wrapping_sub(host_time_ns) as i64;
+
+        self.drift_ns = Some(drift);
+
+        drift
+    }
+
+    /// Gets the last measured drift (guest minus host) in nanoseconds.
+    pub fn drift(&self) -> Option<i64> {
+        self.drift_ns
+    }
+
+    /// Check if the last measured drift is within the bound.
+    pub fn is_within_bound(&self) -> bool {
+        self.drift_ns
+            .is_none_or(|drift| drift.unsigned_abs() <= self.max_drift_ns)
+    }
+
+    /// Convert nanoseconds to guest counter ticks.
+    fn ns_to_ticks(&self, ns: u64) -> u64 {
+        (u128::from(ns) * u128::from(self.timebase.denom) / u128::from(self.timebase.numer)) as u64
+    }
+
+    /// Run a synchronization round against the last measured drift.
+    ///
+    /// An adjustment updates the shared vtimer offset, which must then be applied to every vCPU
+    /// with [VtimerOffsetHandle::apply], and the PV clocks added with [TimeSync::add_pv_clock].
+    pub fn synchronize(&mut self) -> SyncAction {
+        let drift = match self.drift_ns {
+            Some(drift) if drift.unsigned_abs() > self.max_drift_ns => drift,
+            _ => return SyncAction::None,
+        };
+
+        if drift > 0 {
+            return SyncAction::NotifyGuest(drift as u64);
+        }
+
+        let step = drift.unsigned_abs().min(self.max_step_ns);
+        let offset = self.offset.get().wrapping_sub(self.ns_to_ticks(step));
+
+        self.offset.offset.store(offset, Ordering::SeqCst);
+
+        for clock in self.pv_clocks.iter() {
+            clock.set_vtimer_offset(offset);
+        }
+        self.drift_ns = Some(drift + step as i64);
+
+        SyncAction::Adjusted(step)
+    }
+}
@@ -0,0 +1,190 @@
+//! vCPU timeline export in the Chrome trace event format.
+//!
+//! The produced JSON can be opened with Perfetto (ui.perfetto.dev) or `chrome://tracing`. Every
+//! vCPU is shown as a thread of a single process, timestamps are given in nanoseconds.
+
+use crate::bindings::hv_vcpu_t;
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Kind of a timeline event.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum TimelineEventKind {
+    /// An interval (Chrome "complete" event).
+    Interval {
+        /// Duration in nanoseconds.
+        duration: u64,
+    },
+
+    /// A point in time (Chrome "instant" event).
+    Instant,
+}
+
+/// An event of a vCPU timeline.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct TimelineEvent {
+    /// The vCPU.
+    vcpu: hv_vcpu_t,
+
+    /// Category of the event.
+    category: &'static str,
+
+    /// Name of the event.
+    name: String,
+
+    /// Start time in nanoseconds.
+    timestamp: u64,
+
+    /// Kind of the event.
+    kind: TimelineEventKind,
+}
+
+/// Recorder of vCPU timelines.
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    /// All recorded events.
+    events: Vec<TimelineEvent>,
+}
+
+/// Append a JSON string literal.
+pub(crate) fn write_json_string(output: &mut String, value: &str) {
+    output.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+}
+
+/// Append a nanosecond time as microseconds, the unit used by the trace event format.
+fn write_microseconds(output: &mut String, nanoseconds: u64) {
+    let _ = write!(output, "{}.{:03}", nanoseconds / 1000, nanoseconds % 1000);
+}
+
+impl Timeline {
+    /// Create a new empty timeline.
+    pub fn new() -> Self {
+        Timeline::default()
+    }
+
+    /// Record an interval.
+    fn interval(
+        &mut self,
+        vcpu: hv_vcpu_t,
+        category: &'static str,
+        name: &str,
+        start: u64,
+        end: u64,
+    ) {
+        self.events.push(TimelineEvent {
+            vcpu,
+            category,
+            name: String::from(name),
+            timestamp: start,
+            kind: TimelineEventKind::Interval {
+                duration: end.saturating_sub(start),
+            },
+        });
+    }
+
+    /// Record an instant.
+    fn instant(&mut self, vcpu: hv_vcpu_t, category: &'static str, name: &str, timestamp: u64) {
+        self.events.push(TimelineEvent {
+            vcpu,
+            category,
+            name: String::from(name),
+            timestamp,
+            kind: TimelineEventKind::Instant,
+        });
+    }
+
+    /// Record the vCPU running guest code between two points in time.
+    pub fn record_run(&mut self, vcpu: hv_vcpu_t, start: u64, end: u64) {
+        self.interval(vcpu, "run", "guest", start, end);
+    }
+
+    /// Record a vCPU exit, `reason` being usually the [crate::vcpu::VirtualCpuExitReason]
+    /// formatted for display.
+    pub fn record_exit(&mut self, vcpu: hv_vcpu_t, timestamp: u64, reason: &str) {
+        self.instant(vcpu, "exit", reason, timestamp);
+    }
+
+    /// Record an interrupt injected in a vCPU by a device.
+    pub fn record_interrupt(&mut self, vcpu: hv_vcpu_t, timestamp: u64, device: &str) {
+        self.instant(vcpu, "interrupt", device, timestamp);
+    }
+
+    /// Record an exit handler (device emulation, hypercall...) running on the vCPU thread.
+    pub fn record_handler(&mut self, vcpu: hv_vcpu_t, name: &str, start: u64, end: u64) {
+        self.interval(vcpu, "handler", name, start, end);
+    }
+
+    /// Gets the number of recorded events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Check if no event was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Export the timeline as a Chrome trace event JSON document.
+    pub fn to_json(&self) -> String {
+        let mut output = String::from("{\"displayTimeUnit\":\"ns\",\"traceEvents\":[");
+
+        let mut vcpus: Vec<hv_vcpu_t> = self.events.iter().map(|event| event.vcpu).collect();
+
+        vcpus.sort_unstable();
+        vcpus.dedup();
+
+        for (index, vcpu) in vcpus.iter().enumerate() {
+            if index != 0 {
+                output.push(',');
+            }
+
+            let _ = write!(
+                output,
+                "{{\"ph\":\"M\",\"pid\":0,\"tid\":{vcpu},\"name\":\"thread_name\",\"args\":{{\"name\":\"vCPU {vcpu}\"}}}}"
+            );
+        }
+
+        // Thread names are always emitted first when there are events.
+        for event in self.events.iter() {
+            output.push_str(",{\"name\":");
+            write_json_string(&mut output, &event.name);
+            let _ = write!(
+                output,
+                ",\"cat\":\"{}\",\"pid\":0,\"tid\":{},\"ts\":",
+                event.category, event.vcpu
+            );
+            write_microseconds(&mut output, event.timestamp);
+
+            match event.kind {
+                TimelineEventKind::Interval { duration } => {
+                    output.push_str(",\"ph\":\"X\",\"dur\":");
+                    write_microseconds(&mut output, duration);
+                }
+                TimelineEventKind::Instant => output.push_str(",\"ph\":\"i\",\"s\":\"t\""),
+            }
+
+            output.push('}');
+        }
+
+        output.push_str("]}");
+
+        output
+    }
+}
@@ -0,0 +1,136 @@
+//! CPU topology and MPIDR_EL1 assignment.
+
+use crate::err::{HypervisorError, Result};
+use crate::fdt::FdtWriter;
+use crate::reg::SystemRegister;
+use crate::vcpu::VirtualCpu;
+
+extern crate alloc;
+use alloc::format;
+
+/// MPIDR_EL1 bit 31 is RES1.
+const MPIDR_RES1: u64 = 1 << 31;
+
+/// Represent the topology of the vCPUs of a Virtual Machine.
+///
+/// vCPUs are numbered linearly, cores first. The affinity levels are assigned as follow:
+/// Aff0 is the core, Aff1 the cluster and Aff2 the socket.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Topology {
+    /// Number of sockets.
+    pub sockets: u8,
+
+    /// Number of clusters per socket.
+    pub clusters: u8,
+
+    /// Number of cores per cluster.
+    pub cores: u8,
+}
+
+impl Topology {
+    /// Create a new topology.
+    pub const fn new(sockets: u8, clusters: u8, cores: u8) -> Self {
+        Topology {
+            sockets,
+            clusters,
+            cores,
+        }
+    }
+
+    /// Create a flat topology of a single cluster.
+    pub const fn flat(cores: u8) -> Self {
+        Topology::new(1, 1, cores)
+    }
+
+    /// Gets the total number of vCPUs.
+    pub fn vcpu_count(&self) -> u32 {
+        u32::from(self.sockets) * u32::from(self.clusters) * u32::from(self.cores)
+    }
+
+    /// Gets the (socket, cluster, core) position of a vCPU.
+    pub fn position(&self, index: u32) -> Option<(u8, u8, u8)> {
+        if index >= self.vcpu_count() {
+            return None;
+        }
+
+        let core = index % u32::from(self.cores);
+        let cluster = (index / u32::from(self.cores)) % u32::from(self.clusters);
+        let socket = index / (u32::from(self.cores) * u32::from(self.clusters));
+
+        Some((socket as u8, cluster as u8, core as u8))
+    }
+
+    /// Gets the MPIDR_EL1 value of a vCPU.
+    pub fn mpidr(&self, index: u32) -> Option<u64> {
+        self.position(index).map(|(socket, cluster, core)| {
+            MPIDR_RES1 | u64::from(socket) << 16 | u64::from(cluster) << 8 | u64::from(core)
+        })
+    }
+
+    /// Gets the index of the vCPU with a given MPIDR_EL1 affinity.
+    pub fn index_of(&self, mpidr: u64) -> Option<u32> {
+        (0..self.vcpu_count()).find(|index| {
+            self.mpidr(*index).map(|value| value & 0xFF_FFFF) == Some(mpidr & 0xFF_FFFF)
+        })
+    }
+
+    /// Sets MPIDR_EL1 of a vCPU according to its index in the topology.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn set_mpidr(&self, vcpu: &mut VirtualCpu, index: u32) -> Result<()> {
+        let value = self.mpidr(index).ok_or(HypervisorError::BadArgument)?;
+
+        vcpu.set_system_register(SystemRegister::MPIDR_EL1, value)
+    }
+
+    /// Write the `cpus` node, including the `cpu-map`, to a device tree.
+    ///
+    /// The cpu nodes are assigned phandles starting at `first_phandle`, the next free phandle is
+    /// returned.
+    pub fn write_cpus(&self, fdt: &mut FdtWriter, enable_method: &str, first_phandle: u32) -> u32 {
+        fdt.begin_node("cpus");
+        fdt.property_u32("#address-cells", 1);
+        fdt.property_u32("#size-cells", 0);
+
+        for index in 0..self.vcpu_count() {
+            let affinity = self.mpidr(index).unwrap() & 0xFF_FFFF;
+
+            fdt.begin_node(&format!("cpu@{affinity:x}"));
+            fdt.property_string("device_type", "cpu");
+            fdt.property_string("compatible", "arm,arm-v8");
+            fdt.property_u32("reg", affinity as u32);
+            fdt.property_string("enable-method", enable_method);
+            fdt.property_u32("phandle", first_phandle + index);
+            fdt.end_node();
+        }
+
+        fdt.begin_node("cpu-map");
+
+        for socket in 0..self.sockets {
+            fdt.begin_node(&format!("socket{socket}"));
+
+            for cluster in 0..self.clusters {
+                fdt.begin_node(&format!("cluster{cluster}"));
+
+                for core in 0..self.cores {
+                    let index = (u32::from(socket) * u32::from(self.clusters) + u32::from(cluster))
+                        * u32::from(self.cores)
+                        + u32::from(core);
+
+                    fdt.begin_node(&format!("core{core}"));
+                    fdt.property_u32("cpu", first_phandle + index);
+                    fdt.end_node();
+                }
+
+                fdt.end_node();
+            }
+
+            fdt.end_node();
+        }
+
+        fdt.end_node();
+        fdt.end_node();
+
+        first_phandle + self.vcpu_count()
+    }
+}
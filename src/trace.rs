@@ -0,0 +1,491 @@
+//! Compact binary execution traces.
+//!
+//! A trace starts with a header (the `AHVT` magic followed by a little-endian u16 version) and
+//! is followed by records. Each record is a tag byte followed by its fields, all encoded as
+//! unsigned LEB128 integers. Register values are recorded as deltas: only the registers that
+//! changed since the previous sample of the same vCPU are written.
+//!
+//! [attach] records the exits and MMIO accesses of a vCPU from its run loop, other records are
+//! added by the caller.
+
+use crate::bindings::{hv_ipa_t, hv_vcpu_t};
+use crate::device::pvclock::mach_absolute_time;
+use crate::diff::{REGISTER_COUNT, RegisterState};
+use crate::err::{HypervisorError, Result};
+use crate::reg::GENERAL_PURPOSE_REGISTERS;
+use crate::vcpu::{ExceptionClass, ExceptionExit, HookAction, VirtualCpu, VirtualCpuExitReason};
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use core::cell::{Cell, RefCell};
+
+/// Magic at the start of a trace.
+const TRACE_MAGIC: &[u8; 4] = b"AHVT";
+
+/// Version of the trace format.
+const TRACE_VERSION: u16 = 1;
+
+/// Size of the trace header.
+const TRACE_HEADER_SIZE: usize = 6;
+
+/// Tag of an exit record.
+const TAG_EXIT: u8 = 0;
+
+/// Tag of a PC sample record.
+const TAG_PC_SAMPLE: u8 = 1;
+
+/// Tag of a register delta record.
+const TAG_REGISTER: u8 = 2;
+
+/// Tag of a MMIO transaction record.
+const TAG_MMIO: u8 = 3;
+
+/// Exit kind of a cancelled exit.
+const EXIT_CANCELLED: u64 = 0;
+
+/// Exit kind of a guest exception, followed by its syndrome and addresses.
+const EXIT_EXCEPTION: u64 = 1;
+
+/// Exit kind of a virtual timer activation.
+const EXIT_VTIMER_ACTIVATED: u64 = 2;
+
+/// Exit kind of an unknown exit, followed by the raw reason.
+const EXIT_UNKNOWN: u64 = 3;
+
+/// A record of an execution trace.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceRecord {
+    /// A vCPU exited.
+    Exit {
+        /// The vCPU.
+        vcpu: hv_vcpu_t,
+
+        /// The time of the exit, in a unit chosen by the writer (usually mach_absolute_time()).
+        timestamp: u64,
+
+        /// The exit reason.
+        reason: VirtualCpuExitReason,
+    },
+
+    /// The PC of a vCPU was sampled.
+    PcSample {
+        /// The vCPU.
+        vcpu: hv_vcpu_t,
+
+        /// The time of the sample.
+        timestamp: u64,
+
+        /// The sampled PC.
+        pc: u64,
+    },
+
+    /// A register of a vCPU changed.
+    Register {
+        /// The vCPU.
+        vcpu: hv_vcpu_t,
+
+        /// The register index in [crate::diff::REGISTER_NAMES].
+        index: u8,
+
+        /// The new value.
+        value: u64,
+    },
+
+    /// A MMIO transaction was handled.
+    Mmio {
+        /// The vCPU.
+        vcpu: hv_vcpu_t,
+
+        /// The guest address accessed.
+        address: hv_ipa_t,
+
+        /// The size of the access in bytes.
+        size: u8,
+
+        /// The access is a write.
+        is_write: bool,
+
+        /// The value read or written.
+        value: u64,
+    },
+}
+
+/// Append an unsigned LEB128 integer.
+fn write_uleb128(buffer: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+
+        value >>= 7;
+
+        if value == 0 {
+            buffer.push(byte);
+            break;
+        }
+
+        buffer.push(byte | 0x80);
+    }
+}
+
+/// Writer of an execution trace.
+#[derive(Clone, Debug)]
+pub struct TraceWriter {
+    /// The encoded trace.
+    buffer: Vec<u8>,
+
+    /// The last register state recorded for each vCPU.
+    last_registers: BTreeMap<hv_vcpu_t, [u64; REGISTER_COUNT]>,
+}
+
+impl Default for TraceWriter {
+    fn default() -> Self {
+        TraceWriter::new()
+    }
+}
+
+impl TraceWriter {
+    /// Create a new trace.
+    pub fn new() -> Self {
+        let mut buffer = Vec::new();
+
+        buffer.extend_from_slice(TRACE_MAGIC);
+        buffer.extend_from_slice(&TRACE_VERSION.to_le_bytes());
+
+        TraceWriter {
+            buffer,
+            last_registers: BTreeMap::new(),
+        }
+    }
+
+    /// Append a record.
+    pub fn record(&mut self, record: &TraceRecord) {
+        let buffer = &mut self.buffer;
+
+        match *record {
+            TraceRecord::Exit {
+                vcpu,
+                timestamp,
+                reason,
+            } => {
+                buffer.push(TAG_EXIT);
+                write_uleb128(buffer, vcpu);
+                write_uleb128(buffer, timestamp);
+
+                match reason {
+                    VirtualCpuExitReason::Cancelled => write_uleb128(buffer, EXIT_CANCELLED),
+                    VirtualCpuExitReason::Exception { exception } => {
+                        write_uleb128(buffer, EXIT_EXCEPTION);
+                        write_uleb128(buffer, exception.syndrome);
+                        write_uleb128(buffer, exception.virtual_address);
+                        write_uleb128(buffer, exception.physical_address);
+                    }
+                    VirtualCpuExitReason::VTimerActivated => {
+                        write_uleb128(buffer, EXIT_VTIMER_ACTIVATED)
+                    }
+                    VirtualCpuExitReason::Unknown(reason) => {
+                        write_uleb128(buffer, EXIT_UNKNOWN);
+                        write_uleb128(buffer, u64::from(reason));
+                    }
+                }
+            }
+            TraceRecord::PcSample {
+                vcpu,
+                timestamp,
+                pc,
+            } => {
+                buffer.push(TAG_PC_SAMPLE);
+                write_uleb128(buffer, vcpu);
+                write_uleb128(buffer, timestamp);
+                write_uleb128(buffer, pc);
+            }
+            TraceRecord::Register { vcpu, index, value } => {
+                buffer.push(TAG_REGISTER);
+                write_uleb128(buffer, vcpu);
+                write_uleb128(buffer, u64::from(index));
+                write_uleb128(buffer, value);
+            }
+            TraceRecord::Mmio {
+                vcpu,
+                address,
+                size,
+                is_write,
+                value,
+            } => {
+                buffer.push(TAG_MMIO);
+                write_uleb128(buffer, vcpu);
+                write_uleb128(buffer, address);
+                write_uleb128(buffer, u64::from(size) << 1 | u64::from(is_write));
+                write_uleb128(buffer, value);
+            }
+        }
+    }
+
+    /// Record a vCPU exit, as returned by [crate::vcpu::VirtualCpu::run].
+    pub fn record_exit(&mut self, vcpu: hv_vcpu_t, timestamp: u64, reason: VirtualCpuExitReason) {
+        self.record(&TraceRecord::Exit {
+            vcpu,
+            timestamp,
+            reason,
+        });
+    }
+
+    /// Record the registers of a vCPU that changed since its last recorded state.
+    pub fn record_registers(&mut self, vcpu: hv_vcpu_t, state: &RegisterState) {
+        let values = state.values();
+        let previous = self.last_registers.insert(vcpu, values);
+
+        for (index, value) in values.iter().enumerate() {
+            if previous.is_some_and(|previous| previous[index] == *value) {
+                continue;
+            }
+
+            self.record(&TraceRecord::Register {
+                vcpu,
+                index: index as u8,
+                value: *value,
+            });
+        }
+    }
+
+    /// Gets the encoded trace so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Finish the trace and return its encoded form.
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// A MMIO access decoded from a data abort with a valid syndrome.
+#[derive(Copy, Clone, Debug)]
+struct MmioAccess {
+    /// The guest address accessed.
+    address: hv_ipa_t,
+
+    /// The size of the access in bytes.
+    size: u8,
+
+    /// The access is a write.
+    is_write: bool,
+
+    /// The general purpose register index (31 being XZR).
+    register: usize,
+}
+
+impl MmioAccess {
+    /// Decode a data abort exit, if its syndrome describes the access.
+    fn decode(exception: &ExceptionExit) -> Option<Self> {
+        let iss = exception.iss();
+
+        if exception.class() != ExceptionClass::DataAbort || iss & (1 << 24) == 0 {
+            return None;
+        }
+
+        Some(MmioAccess {
+            address: exception.physical_address,
+            size: 1 << ((iss >> 22) & 0b11),
+            is_write: iss & (1 << 6) != 0,
+            register: ((iss >> 16) & 0x1F) as usize,
+        })
+    }
+
+    /// Gets the value of the access from its register, truncated to the access size.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    fn value(&self, vcpu: &mut VirtualCpu) -> Result<u64> {
+        let Some(register) = GENERAL_PURPOSE_REGISTERS.get(self.register) else {
+            return Ok(0);
+        };
+
+        Ok(vcpu.get_register(*register)? & (u64::MAX >> (64 - u32::from(self.size) * 8)))
+    }
+
+    /// Build the trace record of the access.
+    fn record(&self, vcpu: hv_vcpu_t, value: u64) -> TraceRecord {
+        TraceRecord::Mmio {
+            vcpu,
+            address: self.address,
+            size: self.size,
+            is_write: self.is_write,
+            value,
+        }
+    }
+}
+
+/// Record the exits and MMIO accesses of a vCPU in a trace, from its run loop.
+///
+/// A [VirtualCpu::after_exit] hook records every exit, timestamped with `mach_absolute_time()`,
+/// along with the MMIO writes of data aborts with a valid syndrome. MMIO reads are recorded by a
+/// [VirtualCpu::before_run] hook on the next run, once the exit handler emulated the access and
+/// placed the value read in the target register. The hooks never handle exits themselves.
+///
+/// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+pub fn attach(vcpu: &mut VirtualCpu, writer: &Rc<RefCell<TraceWriter>>) {
+    let pending_read: Rc<Cell<Option<MmioAccess>>> = Rc::default();
+
+    let before_writer = writer.clone();
+    let before_read = pending_read.clone();
+
+    vcpu.before_run(move |vcpu| {
+        if let Some(access) = before_read.take() {
+            let value = access.value(vcpu)?;
+
+            before_writer
+                .borrow_mut()
+                .record(&access.record(vcpu.handle, value));
+        }
+
+        Ok(())
+    });
+
+    let writer = writer.clone();
+
+    vcpu.after_exit(move |vcpu, reason| {
+        let mut writer = writer.borrow_mut();
+
+        writer.record_exit(vcpu.handle, unsafe { mach_absolute_time() }, *reason);
+
+        if let VirtualCpuExitReason::Exception { exception } = reason
+            && let Some(access) = MmioAccess::decode(exception)
+        {
+            if access.is_write {
+                let value = access.value(vcpu)?;
+
+                writer.record(&access.record(vcpu.handle, value));
+            } else {
+                pending_read.set(Some(access));
+            }
+        }
+
+        Ok(HookAction::Ignore)
+    });
+}
+
+/// Reader of an execution trace.
+#[derive(Clone, Debug)]
+pub struct TraceReader<'a> {
+    /// The encoded trace.
+    data: &'a [u8],
+
+    /// Offset of the next record.
+    offset: usize,
+}
+
+impl<'a> TraceReader<'a> {
+    /// Create a new reader, checking the trace header.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < TRACE_HEADER_SIZE
+            || &data[..4] != TRACE_MAGIC
+            || u16::from_le_bytes([data[4], data[5]]) != TRACE_VERSION
+        {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        Ok(TraceReader {
+            data,
+            offset: TRACE_HEADER_SIZE,
+        })
+    }
+
+    /// Read an unsigned LEB128 integer.
+    fn read_uleb128(&mut self) -> Result<u64> {
+        let mut result = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.offset)
+                .ok_or(HypervisorError::BadArgument)?;
+
+            self.offset += 1;
+            result |= u64::from(byte & 0x7F) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+
+        Err(HypervisorError::BadArgument)
+    }
+
+    /// Read the next record, or `None` at the end of the trace.
+    pub fn read_record(&mut self) -> Result<Option<TraceRecord>> {
+        let Some(tag) = self.data.get(self.offset).copied() else {
+            return Ok(None);
+        };
+
+        self.offset += 1;
+
+        let vcpu = self.read_uleb128()?;
+
+        let record = match tag {
+            TAG_EXIT => {
+                let timestamp = self.read_uleb128()?;
+
+                let reason = match self.read_uleb128()? {
+                    EXIT_CANCELLED => VirtualCpuExitReason::Cancelled,
+                    EXIT_EXCEPTION => VirtualCpuExitReason::Exception {
+                        exception: ExceptionExit {
+                            syndrome: self.read_uleb128()?,
+                            virtual_address: self.read_uleb128()?,
+                            physical_address: self.read_uleb128()?,
+                        },
+                    },
+                    EXIT_VTIMER_ACTIVATED => VirtualCpuExitReason::VTimerActivated,
+                    EXIT_UNKNOWN => VirtualCpuExitReason::Unknown(self.read_uleb128()? as u32),
+                    _ => return Err(HypervisorError::BadArgument),
+                };
+
+                TraceRecord::Exit {
+                    vcpu,
+                    timestamp,
+                    reason,
+                }
+            }
+            TAG_PC_SAMPLE => TraceRecord::PcSample {
+                vcpu,
+                timestamp: self.read_uleb128()?,
+                pc: self.read_uleb128()?,
+            },
+            TAG_REGISTER => TraceRecord::Register {
+                vcpu,
+                index: self.read_uleb128()? as u8,
+                value: self.read_uleb128()?,
+            },
+            TAG_MMIO => {
+                let address = self.read_uleb128()?;
+                let size_and_direction = self.read_uleb128()?;
+
+                TraceRecord::Mmio {
+                    vcpu,
+                    address,
+                    size: (size_and_direction >> 1) as u8,
+                    is_write: size_and_direction & 1 != 0,
+                    value: self.read_uleb128()?,
+                }
+            }
+            _ => return Err(HypervisorError::BadArgument),
+        };
+
+        Ok(Some(record))
+    }
+}
+
+impl Iterator for TraceReader<'_> {
+    type Item = Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.read_record();
+
+        // Stop on malformed records.
+        if result.is_err() {
+            self.offset = self.data.len();
+        }
+
+        result.transpose()
+    }
+}
@@ -2,6 +2,11 @@ use crate::bindings::*;
 use crate::err::{HypervisorError, Result, convert_hv_return};
 use crate::reg::*;
 use core::ffi::c_void;
+use core::fmt;
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 /// Cache type.
 #[derive(Copy, Clone, Debug)]
@@ -75,7 +80,44 @@ impl VirtualCpuConfiguration {
 }
 
 unsafe extern "C" {
-    fn os_release(object: *mut c_void);
+    pub(crate) fn os_release(object: *mut c_void);
+}
+
+/// Call `hv_vcpu_set_simd_fp_reg`.
+///
+/// The value is a `simd_uchar16_t` vector passed in V0, which can't be declared in FFI on stable
+/// Rust (the generated binding passes an array instead), so the call is made by hand.
+#[cfg(target_arch = "aarch64")]
+unsafe fn set_simd_fp_reg(
+    vcpu: hv_vcpu_t,
+    register: hv_simd_fp_reg_t,
+    value: hv_simd_fp_uchar16_t,
+) -> hv_return_t {
+    let value: core::arch::aarch64::uint8x16_t = unsafe { core::mem::transmute(value) };
+    let ret: u64;
+
+    unsafe {
+        core::arch::asm!(
+            "blr {function}",
+            function = in(reg) hv_vcpu_set_simd_fp_reg as *const () as usize,
+            inlateout("x0") vcpu => ret,
+            in("x1") u64::from(register),
+            in("v0") value,
+            clobber_abi("C"),
+        );
+    }
+
+    ret as hv_return_t
+}
+
+/// Call `hv_vcpu_set_simd_fp_reg` (only available on Apple Silicon).
+#[cfg(not(target_arch = "aarch64"))]
+unsafe fn set_simd_fp_reg(
+    _vcpu: hv_vcpu_t,
+    _register: hv_simd_fp_reg_t,
+    _value: hv_simd_fp_uchar16_t,
+) -> hv_return_t {
+    HV_UNSUPPORTED
 }
 
 impl Drop for VirtualCpuConfiguration {
@@ -105,7 +147,351 @@ impl From<InterruptType> for hv_interrupt_type_t {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Exception class of a guest exception (ESR_EL2.EC).
+///
+/// Guest exceptions are always taken from a lower exception level, the classes of exceptions
+/// taken from EL2 itself are reported as [ExceptionClass::Other].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExceptionClass {
+    /// Unknown reason (undefined instruction for example).
+    Unknown,
+
+    /// Trapped WFI or WFE instruction.
+    Wfx,
+
+    /// Trapped SIMD or floating-point access.
+    SimdFpAccess,
+
+    /// Illegal execution state.
+    IllegalExecutionState,
+
+    /// SVC instruction in AArch32 state.
+    SvcAArch32,
+
+    /// HVC instruction in AArch32 state.
+    HvcAArch32,
+
+    /// SMC instruction in AArch32 state.
+    SmcAArch32,
+
+    /// SVC instruction in AArch64 state.
+    SvcAArch64,
+
+    /// HVC instruction in AArch64 state.
+    HvcAArch64,
+
+    /// SMC instruction in AArch64 state.
+    SmcAArch64,
+
+    /// Trapped MSR, MRS or system instruction.
+    MsrTrap,
+
+    /// Trapped SVE access.
+    SveAccess,
+
+    /// Instruction abort.
+    InstructionAbort,
+
+    /// PC alignment fault.
+    PcAlignment,
+
+    /// Data abort.
+    DataAbort,
+
+    /// SP alignment fault.
+    SpAlignment,
+
+    /// Floating-point exception in AArch64 state.
+    FpException,
+
+    /// SError interrupt.
+    SError,
+
+    /// Hardware breakpoint.
+    Breakpoint,
+
+    /// Software step.
+    SoftwareStep,
+
+    /// Watchpoint.
+    Watchpoint,
+
+    /// BRK instruction in AArch64 state.
+    Brk,
+
+    /// Any other exception class.
+    Other(u8),
+}
+
+impl From<u8> for ExceptionClass {
+    fn from(value: u8) -> ExceptionClass {
+        match value {
+            0x00 => ExceptionClass::Unknown,
+            0x01 => ExceptionClass::Wfx,
+            0x07 => ExceptionClass::SimdFpAccess,
+            0x0E => ExceptionClass::IllegalExecutionState,
+            0x11 => ExceptionClass::SvcAArch32,
+            0x12 => ExceptionClass::HvcAArch32,
+            0x13 => ExceptionClass::SmcAArch32,
+            0x15 => ExceptionClass::SvcAArch64,
+            0x16 => ExceptionClass::HvcAArch64,
+            0x17 => ExceptionClass::SmcAArch64,
+            0x18 => ExceptionClass::MsrTrap,
+            0x19 => ExceptionClass::SveAccess,
+            0x20 => ExceptionClass::InstructionAbort,
+            0x22 => ExceptionClass::PcAlignment,
+            0x24 => ExceptionClass::DataAbort,
+            0x26 => ExceptionClass::SpAlignment,
+            0x2C => ExceptionClass::FpException,
+            0x2F => ExceptionClass::SError,
+            0x30 => ExceptionClass::Breakpoint,
+            0x32 => ExceptionClass::SoftwareStep,
+            0x34 => ExceptionClass::Watchpoint,
+            0x3C => ExceptionClass::Brk,
+            _ => ExceptionClass::Other(value),
+        }
+    }
+}
+
+impl From<ExceptionClass> for u8 {
+    fn from(value: ExceptionClass) -> u8 {
+        match value {
+            ExceptionClass::Unknown => 0x00,
+            ExceptionClass::Wfx => 0x01,
+            ExceptionClass::SimdFpAccess => 0x07,
+            ExceptionClass::IllegalExecutionState => 0x0E,
+            ExceptionClass::SvcAArch32 => 0x11,
+            ExceptionClass::HvcAArch32 => 0x12,
+            ExceptionClass::SmcAArch32 => 0x13,
+            ExceptionClass::SvcAArch64 => 0x15,
+            ExceptionClass::HvcAArch64 => 0x16,
+            ExceptionClass::SmcAArch64 => 0x17,
+            ExceptionClass::MsrTrap => 0x18,
+            ExceptionClass::SveAccess => 0x19,
+            ExceptionClass::InstructionAbort => 0x20,
+            ExceptionClass::PcAlignment => 0x22,
+            ExceptionClass::DataAbort => 0x24,
+            ExceptionClass::SpAlignment => 0x26,
+            ExceptionClass::FpException => 0x2C,
+            ExceptionClass::SError => 0x2F,
+            ExceptionClass::Breakpoint => 0x30,
+            ExceptionClass::SoftwareStep => 0x32,
+            ExceptionClass::Watchpoint => 0x34,
+            ExceptionClass::Brk => 0x3C,
+            ExceptionClass::Other(value) => value,
+        }
+    }
+}
+
+/// Informations about a guest exception that caused a vCPU exit.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ExceptionExit {
+    /// The exception syndrome (ESR_EL2).
+    pub syndrome: u64,
+
+    /// The guest virtual address that caused the exception (FAR_EL2).
+    pub virtual_address: u64,
+
+    /// The guest physical address that caused the exception (HPFAR_EL2).
+    pub physical_address: hv_ipa_t,
+}
+
+impl ExceptionExit {
+    /// Gets the exception class.
+    pub fn class(&self) -> ExceptionClass {
+        ExceptionClass::from(((self.syndrome >> 26) & 0x3F) as u8)
+    }
+
+    /// Gets the instruction specific syndrome (ESR_EL2.ISS).
+    pub fn iss(&self) -> u32 {
+        (self.syndrome & 0x1FF_FFFF) as u32
+    }
+
+    /// Gets the length of the trapped instruction in bytes (ESR_EL2.IL).
+    pub fn instruction_length(&self) -> u64 {
+        if self.syndrome & (1 << 25) != 0 { 4 } else { 2 }
+    }
+
+    /// Gets the raw exception informations as returned by the Hypervisor.
+    #[cfg(feature = "sys")]
+    pub fn raw(&self) -> hv_vcpu_exit_exception_t {
+        hv_vcpu_exit_exception_t {
+            syndrome: self.syndrome,
+            virtual_address: self.virtual_address,
+            physical_address: self.physical_address,
+        }
+    }
+}
+
+impl From<hv_vcpu_exit_exception_t> for ExceptionExit {
+    fn from(value: hv_vcpu_exit_exception_t) -> ExceptionExit {
+        ExceptionExit {
+            syndrome: value.syndrome,
+            virtual_address: value.virtual_address,
+            physical_address: value.physical_address,
+        }
+    }
+}
+
+/// Gets the name of an exception class (ESR_EL2.EC).
+fn exception_class_name(class: ExceptionClass) -> Option<&'static str> {
+    let name = match class {
+        ExceptionClass::Unknown => "unknown reason",
+        ExceptionClass::Wfx => "trapped WFI/WFE",
+        ExceptionClass::SimdFpAccess => "trapped SIMD/FP access",
+        ExceptionClass::IllegalExecutionState => "illegal execution state",
+        ExceptionClass::SvcAArch32 => "SVC (AArch32)",
+        ExceptionClass::HvcAArch32 => "HVC (AArch32)",
+        ExceptionClass::SmcAArch32 => "SMC (AArch32)",
+        ExceptionClass::SvcAArch64 => "SVC (AArch64)",
+        ExceptionClass::HvcAArch64 => "HVC (AArch64)",
+        ExceptionClass::SmcAArch64 => "SMC (AArch64)",
+        ExceptionClass::MsrTrap => "trapped MSR/MRS/system instruction",
+        ExceptionClass::SveAccess => "trapped SVE access",
+        ExceptionClass::InstructionAbort => "instruction abort (lower EL)",
+        ExceptionClass::Other(0x21) => "instruction abort (same EL)",
+        ExceptionClass::PcAlignment => "PC alignment fault",
+        ExceptionClass::DataAbort => "data abort (lower EL)",
+        ExceptionClass::Other(0x25) => "data abort (same EL)",
+        ExceptionClass::SpAlignment => "SP alignment fault",
+        ExceptionClass::FpException => "floating-point exception (AArch64)",
+        ExceptionClass::SError => "SError interrupt",
+        ExceptionClass::Breakpoint => "breakpoint (lower EL)",
+        ExceptionClass::Other(0x31) => "breakpoint (same EL)",
+        ExceptionClass::SoftwareStep => "software step (lower EL)",
+        ExceptionClass::Other(0x33) => "software step (same EL)",
+        ExceptionClass::Watchpoint => "watchpoint (lower EL)",
+        ExceptionClass::Other(0x35) => "watchpoint (same EL)",
+        ExceptionClass::Brk => "BRK (AArch64)",
+        ExceptionClass::Other(_) => return None,
+    };
+
+    Some(name)
+}
+
+/// Gets the name of an abort fault status code (DFSC/IFSC).
+fn fault_status_name(status: u32) -> Option<&'static str> {
+    let name = match status {
+        0b000000..=0b000011 => "address size fault",
+        0b000100..=0b000111 => "translation fault",
+        0b001001..=0b001011 => "access flag fault",
+        0b001101..=0b001111 => "permission fault",
+        0b010000 => "synchronous external abort",
+        0b100001 => "alignment fault",
+        0b110000 => "TLB conflict abort",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Writes a general purpose register name, handling the zero register.
+fn write_register_name(f: &mut fmt::Formatter<'_>, index: u32) -> fmt::Result {
+    if index == 31 {
+        write!(f, "xzr")
+    } else {
+        write!(f, "x{index}")
+    }
+}
+
+impl fmt::Display for ExceptionExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = self.class();
+        let iss = self.iss();
+
+        match exception_class_name(class) {
+            Some(name) => write!(f, "{name}")?,
+            None => write!(f, "exception class {:#x}", u8::from(class))?,
+        }
+
+        match class {
+            // Instruction and data aborts.
+            ExceptionClass::InstructionAbort
+            | ExceptionClass::Other(0x21)
+            | ExceptionClass::DataAbort
+            | ExceptionClass::Other(0x25) => {
+                write!(
+                    f,
+                    " at VA {:#x} (IPA {:#x})",
+                    self.virtual_address, self.physical_address
+                )?;
+
+                let status = iss & 0x3f;
+
+                match fault_status_name(status) {
+                    Some(name) if status <= 0b001111 => {
+                        write!(f, ": {name}, level {}", status & 0b11)?
+                    }
+                    Some(name) => write!(f, ": {name}")?,
+                    None => write!(f, ": fault status {status:#x}")?,
+                }
+
+                // Data aborts with a valid instruction syndrome.
+                if matches!(
+                    class,
+                    ExceptionClass::DataAbort | ExceptionClass::Other(0x25)
+                ) && iss & (1 << 24) != 0
+                {
+                    let size = 1 << ((iss >> 22) & 0b11);
+                    let register = (iss >> 16) & 0x1f;
+
+                    if iss & (1 << 6) != 0 {
+                        write!(f, ", {size}-byte write from ")?;
+                    } else {
+                        write!(f, ", {size}-byte read into ")?;
+                    }
+
+                    write_register_name(f, register)?;
+                }
+            }
+
+            // HVC, SMC and SVC calls.
+            ExceptionClass::SvcAArch32
+            | ExceptionClass::HvcAArch32
+            | ExceptionClass::SmcAArch32
+            | ExceptionClass::SvcAArch64
+            | ExceptionClass::HvcAArch64
+            | ExceptionClass::SmcAArch64 => {
+                write!(f, " #{:#x}", iss & 0xffff)?;
+            }
+
+            // BRK instruction.
+            ExceptionClass::Brk => {
+                write!(f, " #{:#x} at VA {:#x}", iss & 0xffff, self.virtual_address)?;
+            }
+
+            // Watchpoints.
+            ExceptionClass::Watchpoint | ExceptionClass::Other(0x35) => {
+                write!(f, " at VA {:#x}", self.virtual_address)?;
+            }
+
+            // MSR/MRS and system instructions.
+            ExceptionClass::MsrTrap => {
+                let op0 = (iss >> 20) & 0b11;
+                let op2 = (iss >> 17) & 0b111;
+                let op1 = (iss >> 14) & 0b111;
+                let crn = (iss >> 10) & 0xf;
+                let register = (iss >> 5) & 0x1f;
+                let crm = (iss >> 1) & 0xf;
+
+                if iss & 1 != 0 {
+                    write!(f, ": MRS ")?;
+                    write_register_name(f, register)?;
+                    write!(f, ", S{op0}_{op1}_C{crn}_C{crm}_{op2}")?;
+                } else {
+                    write!(f, ": MSR S{op0}_{op1}_C{crn}_C{crm}_{op2}, ")?;
+                    write_register_name(f, register)?;
+                }
+            }
+
+            _ => {}
+        }
+
+        write!(f, " [ESR {:#x}]", self.syndrome)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 /// Exit reason of a vCPU.
 pub enum VirtualCpuExitReason {
     /// Asynchronous exit.
@@ -114,14 +500,28 @@ pub enum VirtualCpuExitReason {
     /// Guest exception.
     Exception {
         /// The informations about the guest exception.
-        exception: hv_vcpu_exit_exception_t,
+        exception: ExceptionExit,
     },
 
     /// Virtual Timer enters the pending state.
     VTimerActivated,
 
     /// Unexpected exit.
-    Unknown,
+    Unknown(
+        /// The raw exit reason returned by the Hypervisor.
+        u32,
+    ),
+}
+
+impl fmt::Display for VirtualCpuExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VirtualCpuExitReason::Cancelled => write!(f, "cancelled"),
+            VirtualCpuExitReason::Exception { exception } => write!(f, "exception: {exception}"),
+            VirtualCpuExitReason::VTimerActivated => write!(f, "virtual timer activated"),
+            VirtualCpuExitReason::Unknown(reason) => write!(f, "unknown exit (reason {reason})"),
+        }
+    }
 }
 
 impl From<hv_vcpu_exit_t> for VirtualCpuExitReason {
@@ -129,19 +529,99 @@ impl From<hv_vcpu_exit_t> for VirtualCpuExitReason {
         match value.reason {
             hv_exit_reason_t::HV_EXIT_REASON_CANCELED => VirtualCpuExitReason::Cancelled,
             hv_exit_reason_t::HV_EXIT_REASON_EXCEPTION => VirtualCpuExitReason::Exception {
-                exception: value.exception,
+                exception: ExceptionExit::from(value.exception),
             },
             hv_exit_reason_t::HV_EXIT_REASON_VTIMER_ACTIVATED => {
                 VirtualCpuExitReason::VTimerActivated
             }
-            hv_exit_reason_t::HV_EXIT_REASON_UNKNOWN => VirtualCpuExitReason::Unknown,
-
-            // Unexpected unknown
-            _ => VirtualCpuExitReason::Unknown,
+            // Unknown to the Hypervisor or not yet described by the bindings, keep the raw value
+            // around so that newer exit reasons are still detectable.
+            reason => VirtualCpuExitReason::Unknown(reason),
         }
     }
 }
 
+/// A hook called before every run of a vCPU.
+pub type BeforeRunHook = Box<dyn FnMut(&mut VirtualCpu) -> Result<()>>;
+
+/// Action to take after an exit, as returned by a hook.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HookAction {
+    /// The hook didn't handle the exit, leave the decision to the other hooks.
+    Ignore,
+
+    /// The exit was handled, resume the guest.
+    Continue,
+
+    /// Return the exit from [VirtualCpu::run], even if another hook asked to resume.
+    Stop,
+
+    /// Skip the trapped instruction that caused the exit and resume the guest.
+    ///
+    /// The PC is only advanced for trapped instructions it doesn't already point past (it does for
+    /// HVC), and at most once per exit.
+    SkipInstruction,
+
+    /// Write the given register values and resume the guest.
+    ModifyAndContinue(Vec<(Register, u64)>),
+}
+
+/// Check if the PC still points at the instruction that trapped for an exception class.
+///
+/// HVC is the only trapped instruction for which the PC was already advanced.
+fn is_pc_at_trapped_instruction(class: ExceptionClass) -> bool {
+    matches!(
+        class,
+        ExceptionClass::Wfx
+            | ExceptionClass::SimdFpAccess
+            | ExceptionClass::SmcAArch32
+            | ExceptionClass::SmcAArch64
+            | ExceptionClass::MsrTrap
+            | ExceptionClass::SveAccess
+            | ExceptionClass::DataAbort
+            | ExceptionClass::Brk
+    )
+}
+
+/// A hook called after every exit of a vCPU.
+pub type AfterExitHook =
+    Box<dyn FnMut(&mut VirtualCpu, &VirtualCpuExitReason) -> Result<HookAction>>;
+
+/// Hooks registered on a vCPU.
+#[derive(Default)]
+pub(crate) struct VirtualCpuHooks {
+    /// Hooks called before every run.
+    before_run: Vec<BeforeRunHook>,
+
+    /// Hooks called after every exit.
+    after_exit: Vec<AfterExitHook>,
+}
+
+impl fmt::Debug for VirtualCpuHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtualCpuHooks")
+            .field("before_run", &self.before_run.len())
+            .field("after_exit", &self.after_exit.len())
+            .finish()
+    }
+}
+
+/// Error returned by [VirtualCpu::destroy].
+#[derive(Debug)]
+pub struct DestroyError {
+    /// The error reported.
+    pub error: HypervisorError,
+
+    /// The vCPU, given back when it wasn't destroyed because of the calling thread.
+    pub vcpu: Option<VirtualCpu>,
+}
+
+impl From<DestroyError> for HypervisorError {
+    fn from(value: DestroyError) -> HypervisorError {
+        value.error
+    }
+}
+
 /// vCPU for a Virtual Machine.
 #[derive(Debug)]
 pub struct VirtualCpu {
@@ -150,10 +630,30 @@ pub struct VirtualCpu {
 
     /// vCPU exit informations.
     pub vcpu_exit: *const hv_vcpu_exit_t,
+
+    /// The thread that created the vCPU, the only one allowed to destroy it.
+    pub(crate) owner: libc::pthread_t,
+
+    /// Hooks called around runs.
+    pub(crate) hooks: VirtualCpuHooks,
+
+    /// The vCPU starts and may run at EL2.
+    pub(crate) el2_enabled: bool,
+
+    /// The vCPU has a GIC CPU interface.
+    pub(crate) has_gic: bool,
+
+    /// The default entry point on reset.
+    pub(crate) reset_pc: Option<u64>,
 }
 
 impl Drop for VirtualCpu {
     fn drop(&mut self) {
+        assert!(
+            self.is_owner_thread(),
+            "vCPU dropped outside of the thread that created it!"
+        );
+
         self.exit().expect("Cannot exit vCPU on drop!");
 
         let ret = unsafe { hv_vcpu_destroy(self.handle) };
@@ -168,6 +668,54 @@ impl VirtualCpu {
         self.handle
     }
 
+    /// Gets the default entry point of [crate::loader::reset_vcpu], the address of the first ROM
+    /// of the Virtual Machine when the vCPU got created (see [crate::VirtualMachine::get_reset_pc]).
+    pub fn get_reset_pc(&self) -> Option<u64> {
+        self.reset_pc
+    }
+
+    /// Set the default entry point of [crate::loader::reset_vcpu].
+    pub fn set_reset_pc(&mut self, address: Option<u64>) {
+        self.reset_pc = address;
+    }
+
+    /// Check if the vCPU belongs to a Virtual Machine running the guest at EL2.
+    pub fn is_el2_enabled(&self) -> bool {
+        self.el2_enabled
+    }
+
+    /// Check if the current thread is the one that created the vCPU.
+    pub fn is_owner_thread(&self) -> bool {
+        unsafe { libc::pthread_equal(self.owner, libc::pthread_self()) != 0 }
+    }
+
+    /// Destroy the vCPU, reporting errors instead of panicking like on drop.
+    ///
+    /// If called outside of the thread that created the vCPU, [HypervisorError::WrongThread] is
+    /// returned along with the vCPU, as the Hypervisor can't destroy it from there.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn destroy(mut self) -> core::result::Result<(), DestroyError> {
+        if !self.is_owner_thread() {
+            return Err(DestroyError {
+                error: HypervisorError::WrongThread,
+                vcpu: Some(self),
+            });
+        }
+
+        let mut handle = self.handle;
+
+        drop(core::mem::take(&mut self.hooks));
+        core::mem::forget(self);
+
+        // The vCPU is destroyed even if it couldn't be forced to exit.
+        let _ = unsafe { hv_vcpus_exit(&mut handle, 1) };
+
+        let ret = unsafe { hv_vcpu_destroy(handle) };
+
+        convert_hv_return(ret).map_err(|error| DestroyError { error, vcpu: None })
+    }
+
     /// Gets a register value.
     ///
     /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
@@ -197,7 +745,101 @@ impl VirtualCpu {
         convert_hv_return(ret)
     }
 
-    // TODO: SIMD APIs
+    /// Gets the values of multiple registers, in the given order.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn get_registers(&mut self, registers: &[Register]) -> Result<Vec<u64>> {
+        registers
+            .iter()
+            .map(|register| self.get_register(*register))
+            .collect()
+    }
+
+    /// Sets multiple register values, in the given order.
+    ///
+    /// Stops at the first error, leaving the previous registers set.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn set_registers(&mut self, values: &[(Register, u64)]) -> Result<()> {
+        for (register, value) in values {
+            self.set_register(*register, *value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets all general purpose registers, PC and CPSR.
+    ///
+    /// Unlike [VirtualCpu::get_registers], nothing is allocated, which suits per-exit handlers.
+    /// Hypervisor.framework has no batch accessor though, so this still makes one call per
+    /// register.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn get_general_registers(&mut self) -> Result<GeneralRegisters> {
+        let mut result = GeneralRegisters::default();
+
+        for (value, register) in result.x.iter_mut().zip(GENERAL_PURPOSE_REGISTERS) {
+            *value = self.get_register(register)?;
+        }
+
+        result.pc = self.get_register(Register::PC)?;
+        result.cpsr = self.get_register(Register::CPSR)?;
+
+        Ok(result)
+    }
+
+    /// Sets all general purpose registers, PC and CPSR.
+    ///
+    /// Like [VirtualCpu::get_general_registers], this makes one call per register.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn set_general_registers(&mut self, registers: &GeneralRegisters) -> Result<()> {
+        for (value, register) in registers.x.iter().zip(GENERAL_PURPOSE_REGISTERS) {
+            self.set_register(register, *value)?;
+        }
+
+        self.set_register(Register::PC, registers.pc)?;
+        self.set_register(Register::CPSR, registers.cpsr)
+    }
+
+    /// Gets a SIMD and floating-point register value.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn get_simd_fp_register(&mut self, register: SimdFpRegister) -> Result<SimdFpValue> {
+        let mut result = [0; 16];
+
+        let ret = unsafe {
+            hv_vcpu_get_simd_fp_reg(
+                self.handle,
+                hv_simd_fp_reg_t::from(register),
+                &mut result as *mut hv_simd_fp_uchar16_t,
+            )
+        };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(SimdFpValue::from_le_bytes(result))
+    }
+
+    /// Set a SIMD and floating-point register value.
+    ///
+    /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
+    pub fn set_simd_fp_register(
+        &mut self,
+        register: SimdFpRegister,
+        value: SimdFpValue,
+    ) -> Result<()> {
+        let ret = unsafe {
+            set_simd_fp_reg(
+                self.handle,
+                hv_simd_fp_reg_t::from(register),
+                value.to_le_bytes(),
+            )
+        };
+
+        convert_hv_return(ret)
+    }
 
     /// Gets a system register value.
     ///
@@ -315,11 +957,98 @@ impl VirtualCpu {
     ///
     /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
     pub fn run(&mut self) -> Result<VirtualCpuExitReason> {
-        let ret = unsafe { hv_vcpu_run(self.handle) };
+        // Hooks are taken out while running so they can borrow the vCPU.
+        let mut hooks = core::mem::take(&mut self.hooks);
 
-        convert_hv_return(ret)?;
+        let result = self.run_with_hooks(&mut hooks);
+
+        // Keep hooks registered by the hooks themselves.
+        hooks.before_run.append(&mut self.hooks.before_run);
+        hooks.after_exit.append(&mut self.hooks.after_exit);
+        self.hooks = hooks;
+
+        result
+    }
+
+    /// Runs the vCPU, calling the given hooks.
+    fn run_with_hooks(&mut self, hooks: &mut VirtualCpuHooks) -> Result<VirtualCpuExitReason> {
+        loop {
+            for hook in hooks.before_run.iter_mut() {
+                hook(self)?;
+            }
+
+            let ret = unsafe { hv_vcpu_run(self.handle) };
+
+            convert_hv_return(ret)?;
+
+            let reason = self.last_exit();
+
+            // The exit is returned unless a hook asks to resume and none to stop.
+            let mut resume = false;
+            let mut stop = false;
+            let mut skip = false;
+
+            for hook in hooks.after_exit.iter_mut() {
+                match hook(self, &reason)? {
+                    HookAction::Ignore => {}
+                    HookAction::Continue => resume = true,
+                    HookAction::Stop => stop = true,
+                    HookAction::SkipInstruction => {
+                        resume = true;
+                        skip = true;
+                    }
+                    HookAction::ModifyAndContinue(registers) => {
+                        resume = true;
+
+                        for (register, value) in registers {
+                            self.set_register(register, value)?;
+                        }
+                    }
+                }
+            }
+
+            if skip
+                && let VirtualCpuExitReason::Exception { exception } = reason
+                && is_pc_at_trapped_instruction(exception.class())
+            {
+                let pc = self.get_register(Register::PC)?;
+
+                self.set_register(Register::PC, pc + exception.instruction_length())?;
+            }
+
+            // A cancellation (shutdown, debugger interrupt...) always reaches the caller.
+            if stop || !resume || reason == VirtualCpuExitReason::Cancelled {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Register a hook called before every run of the vCPU, in registration order.
+    ///
+    /// An error returned by the hook aborts the run.
+    pub fn before_run<F>(&mut self, hook: F)
+    where
+        F: FnMut(&mut VirtualCpu) -> Result<()> + 'static,
+    {
+        self.hooks.before_run.push(Box::new(hook));
+    }
+
+    /// Register a hook called after every exit of the vCPU, in registration order.
+    ///
+    /// All hooks are called on every exit and their actions applied in order. The guest is resumed
+    /// without returning from [VirtualCpu::run] only if a hook asks to resume and none returns
+    /// [HookAction::Stop]. [VirtualCpuExitReason::Cancelled] exits are always returned. An error
+    /// returned by the hook is returned by [VirtualCpu::run].
+    pub fn after_exit<F>(&mut self, hook: F)
+    where
+        F: FnMut(&mut VirtualCpu, &VirtualCpuExitReason) -> Result<HookAction> + 'static,
+    {
+        self.hooks.after_exit.push(Box::new(hook));
+    }
 
-        Ok(VirtualCpuExitReason::from(unsafe { *self.vcpu_exit }))
+    /// Gets the exit reason of the last run of the vCPU without running it again.
+    pub fn last_exit(&self) -> VirtualCpuExitReason {
+        VirtualCpuExitReason::from(unsafe { *self.vcpu_exit })
     }
 
     /// Forces exit the vCPU.
@@ -0,0 +1,390 @@
+//! Virtqueue handling for virtio device backends.
+//!
+//! Device backends pop descriptor chains made available by the driver, process the buffers they
+//! describe and return them as used. Guest memory is accessed through the [GuestMemory] trait so
+//! queues can be driven against a [VirtualMachine] or any other memory, typically a
+//! [crate::device::dma::DmaMemory] only allowing the [Virtqueue::regions] and the buffers area of
+//! the device.
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::machine::MemoryRegion;
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+pub mod packed;
+pub mod split;
+
+use packed::PackedQueue;
+use split::SplitQueue;
+
+/// The buffer continues in the next descriptor.
+pub const VIRTQ_DESC_F_NEXT: u16 = 1;
+
+/// The buffer is write-only for the device (otherwise read-only).
+pub const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// The buffer contains a table of indirect descriptors.
+pub const VIRTQ_DESC_F_INDIRECT: u16 = 4;
+
+/// Size of a descriptor in a descriptor table.
+pub const DESCRIPTOR_SIZE: u64 = 16;
+
+/// Feature bit of indirect descriptors.
+pub const VIRTIO_F_INDIRECT_DESC: u64 = 1 << 28;
+
+/// Feature bit of the used and available event index fields.
+pub const VIRTIO_F_EVENT_IDX: u64 = 1 << 29;
+
+/// Feature bit of VIRTIO 1.x compliance.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Feature bit of packed virtqueues.
+pub const VIRTIO_F_RING_PACKED: u64 = 1 << 34;
+
+/// Feature bits supported by the virtqueue engine.
+pub const VIRTQUEUE_FEATURES: u64 =
+    VIRTIO_F_INDIRECT_DESC | VIRTIO_F_EVENT_IDX | VIRTIO_F_VERSION_1 | VIRTIO_F_RING_PACKED;
+
+/// Negotiate the features offered by a device against the ones acknowledged by the driver.
+///
+/// Fails if the driver acknowledged features that were not offered, or didn't acknowledge
+/// VIRTIO_F_VERSION_1 (legacy interfaces aren't supported).
+pub fn negotiate_features(offered: u64, acknowledged: u64) -> Result<u64> {
+    if acknowledged & !offered != 0 || acknowledged & VIRTIO_F_VERSION_1 == 0 {
+        return Err(HypervisorError::BadArgument);
+    }
+
+    Ok(acknowledged)
+}
+
+/// Offset a guest address programmed by the driver, failing if it overflows.
+pub(crate) fn offset_address(address: hv_ipa_t, offset: u64) -> Result<hv_ipa_t> {
+    address
+        .checked_add(offset)
+        .ok_or(HypervisorError::IllegalGuestState)
+}
+
+/// Accessor to the guest memory holding virtqueues and buffers.
+pub trait GuestMemory {
+    /// Read guest memory starting at a given guest address.
+    fn read(&self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()>;
+
+    /// Write guest memory starting at a given guest address.
+    fn write(&mut self, address: hv_ipa_t, data: &[u8]) -> Result<()>;
+
+    /// Read a little-endian u16.
+    fn read_u16(&self, address: hv_ipa_t) -> Result<u16> {
+        let mut value = [0; 2];
+
+        self.read(address, &mut value)?;
+
+        Ok(u16::from_le_bytes(value))
+    }
+
+    /// Read a little-endian u32.
+    fn read_u32(&self, address: hv_ipa_t) -> Result<u32> {
+        let mut value = [0; 4];
+
+        self.read(address, &mut value)?;
+
+        Ok(u32::from_le_bytes(value))
+    }
+
+    /// Read a little-endian u64.
+    fn read_u64(&self, address: hv_ipa_t) -> Result<u64> {
+        let mut value = [0; 8];
+
+        self.read(address, &mut value)?;
+
+        Ok(u64::from_le_bytes(value))
+    }
+
+    /// Write a little-endian u16.
+    fn write_u16(&mut self, address: hv_ipa_t, value: u16) -> Result<()> {
+        self.write(address, &value.to_le_bytes())
+    }
+
+    /// Write a little-endian u32.
+    fn write_u32(&mut self, address: hv_ipa_t, value: u32) -> Result<()> {
+        self.write(address, &value.to_le_bytes())
+    }
+}
+
+impl<M: GuestMemory + ?Sized> GuestMemory for &mut M {
+    fn read(&self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()> {
+        (**self).read(address, buffer)
+    }
+
+    fn write(&mut self, address: hv_ipa_t, data: &[u8]) -> Result<()> {
+        (**self).write(address, data)
+    }
+}
+
+impl GuestMemory for VirtualMachine {
+    fn read(&self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()> {
+        self.read_memory(address, buffer)
+    }
+
+    fn write(&mut self, address: hv_ipa_t, data: &[u8]) -> Result<()> {
+        self.write_memory(address, data)
+    }
+}
+
+/// A buffer described by a descriptor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Descriptor {
+    /// The guest address of the buffer.
+    pub address: hv_ipa_t,
+
+    /// The length of the buffer.
+    pub length: u32,
+
+    /// The buffer is write-only for the device.
+    pub is_write_only: bool,
+}
+
+/// A chain of buffers made available by the driver.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DescriptorChain {
+    /// The identifier to return the chain as used (the head descriptor index, or the buffer ID
+    /// of packed queues).
+    pub id: u16,
+
+    /// The buffers of the chain, in order.
+    pub descriptors: Vec<Descriptor>,
+}
+
+impl DescriptorChain {
+    /// Gets the buffers readable by the device.
+    pub fn readable(&self) -> impl Iterator<Item = &Descriptor> {
+        self.descriptors
+            .iter()
+            .filter(|descriptor| !descriptor.is_write_only)
+    }
+
+    /// Gets the buffers writable by the device.
+    pub fn writable(&self) -> impl Iterator<Item = &Descriptor> {
+        self.descriptors
+            .iter()
+            .filter(|descriptor| descriptor.is_write_only)
+    }
+
+    /// Read all device-readable buffers into a single vector.
+    ///
+    /// The buffer lengths are set by the driver, so the total is bounded by `limit`: longer
+    /// chains fail with [HypervisorError::IllegalGuestState] before anything is allocated.
+    pub fn read_all<M: GuestMemory>(&self, memory: &M, limit: usize) -> Result<Vec<u8>> {
+        let size = self
+            .readable()
+            .try_fold(0usize, |size, descriptor| {
+                size.checked_add(descriptor.length as usize)
+            })
+            .filter(|size| *size <= limit)
+            .ok_or(HypervisorError::IllegalGuestState)?;
+
+        let mut result = Vec::with_capacity(size);
+
+        for descriptor in self.readable() {
+            let start = result.len();
+
+            result.resize(start + descriptor.length as usize, 0);
+            memory.read(descriptor.address, &mut result[start..])?;
+        }
+
+        Ok(result)
+    }
+
+    /// Write data across the device-writable buffers, returning the number of bytes written.
+    pub fn write_all<M: GuestMemory>(&self, memory: &mut M, data: &[u8]) -> Result<usize> {
+        let mut offset = 0;
+
+        for descriptor in self.writable() {
+            if offset == data.len() {
+                break;
+            }
+
+            let size = (descriptor.length as usize).min(data.len() - offset);
+
+            memory.write(descriptor.address, &data[offset..offset + size])?;
+            offset += size;
+        }
+
+        Ok(offset)
+    }
+}
+
+/// A virtqueue of the layout selected by the negotiated features.
+#[derive(Clone, Debug)]
+pub enum Virtqueue {
+    /// A split virtqueue.
+    Split(SplitQueue),
+
+    /// A packed virtqueue (VIRTIO_F_RING_PACKED).
+    Packed(PackedQueue),
+}
+
+impl Virtqueue {
+    /// Create a new queue from the negotiated features and the addresses programmed by the
+    /// driver (descriptor area, driver area and device area).
+    pub fn new(
+        features: u64,
+        size: u16,
+        descriptor_area: hv_ipa_t,
+        driver_area: hv_ipa_t,
+        device_area: hv_ipa_t,
+    ) -> Result<Self> {
+        let event_idx = features & VIRTIO_F_EVENT_IDX != 0;
+
+        if features & VIRTIO_F_RING_PACKED != 0 {
+            PackedQueue::new(size, descriptor_area, driver_area, device_area, event_idx)
+                .map(Virtqueue::Packed)
+        } else {
+            SplitQueue::new(size, descriptor_area, driver_area, device_area, event_idx)
+                .map(Virtqueue::Split)
+        }
+    }
+
+    /// Gets the number of descriptors.
+    pub fn size(&self) -> u16 {
+        match self {
+            Virtqueue::Split(queue) => queue.size(),
+            Virtqueue::Packed(queue) => queue.size(),
+        }
+    }
+
+    /// Gets the guest memory regions of the queue structures.
+    pub fn regions(&self) -> [MemoryRegion; 3] {
+        match self {
+            Virtqueue::Split(queue) => queue.regions(),
+            Virtqueue::Packed(queue) => queue.regions(),
+        }
+    }
+
+    /// Pop the next descriptor chain made available by the driver, if any.
+    pub fn pop<M: GuestMemory>(&mut self, memory: &M) -> Result<Option<DescriptorChain>> {
+        match self {
+            Virtqueue::Split(queue) => queue.pop(memory),
+            Virtqueue::Packed(queue) => queue.pop(memory),
+        }
+    }
+
+    /// Return a descriptor chain to the driver, with the number of bytes written in it.
+    pub fn add_used<M: GuestMemory>(&mut self, memory: &mut M, id: u16, length: u32) -> Result<()> {
+        match self {
+            Virtqueue::Split(queue) => queue.add_used(memory, id, length),
+            Virtqueue::Packed(queue) => queue.add_used(memory, id, length),
+        }
+    }
+
+    /// Check if the driver should be interrupted for the chains used since the last check.
+    pub fn needs_notification<M: GuestMemory>(&mut self, memory: &M) -> Result<bool> {
+        match self {
+            Virtqueue::Split(queue) => queue.needs_notification(memory),
+            Virtqueue::Packed(queue) => queue.needs_notification(memory),
+        }
+    }
+
+    /// Enable or disable driver notifications of newly available chains.
+    pub fn set_notification<M: GuestMemory>(&mut self, memory: &mut M, enable: bool) -> Result<()> {
+        match self {
+            Virtqueue::Split(queue) => queue.set_notification(memory, enable),
+            Virtqueue::Packed(queue) => queue.set_notification(memory, enable),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use alloc::vec;
+
+    /// Guest memory backed by a vector, starting at guest address 0.
+    pub(crate) struct TestMemory(Vec<u8>);
+
+    impl TestMemory {
+        /// Create a zeroed memory of a given size.
+        pub(crate) fn new(size: usize) -> Self {
+            TestMemory(vec![0; size])
+        }
+
+        /// Gets a range of the memory.
+        fn range(address: hv_ipa_t, size: usize) -> Result<core::ops::Range<usize>> {
+            let start = usize::try_from(address).map_err(|_| HypervisorError::UnmappedAddress)?;
+            let end = start
+                .checked_add(size)
+                .ok_or(HypervisorError::UnmappedAddress)?;
+
+            Ok(start..end)
+        }
+    }
+
+    impl GuestMemory for TestMemory {
+        fn read(&self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()> {
+            buffer.copy_from_slice(
+                self.0
+                    .get(Self::range(address, buffer.len())?)
+                    .ok_or(HypervisorError::UnmappedAddress)?,
+            );
+
+            Ok(())
+        }
+
+        fn write(&mut self, address: hv_ipa_t, data: &[u8]) -> Result<()> {
+            self.0
+                .get_mut(Self::range(address, data.len())?)
+                .ok_or(HypervisorError::UnmappedAddress)?
+                .copy_from_slice(data);
+
+            Ok(())
+        }
+    }
+
+    /// Build a chain of device-readable buffers of the given lengths, laid out back to back.
+    fn readable_chain(lengths: &[u32]) -> DescriptorChain {
+        let mut address = 0;
+
+        DescriptorChain {
+            id: 0,
+            descriptors: lengths
+                .iter()
+                .map(|length| {
+                    let descriptor = Descriptor {
+                        address,
+                        length: *length,
+                        is_write_only: false,
+                    };
+
+                    address += u64::from(*length);
+
+                    descriptor
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn read_all_concatenates_buffers() {
+        let mut memory = TestMemory::new(0x100);
+
+        memory.write(0, b"hello world").unwrap();
+
+        let chain = readable_chain(&[5, 6]);
+
+        assert_eq!(chain.read_all(&memory, 11).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn read_all_rejects_chains_over_limit() {
+        let memory = TestMemory::new(0x100);
+        let chain = readable_chain(&[0x10, u32::MAX]);
+
+        assert!(matches!(
+            chain.read_all(&memory, 0x100),
+            Err(HypervisorError::IllegalGuestState)
+        ));
+    }
+}
@@ -0,0 +1,434 @@
+//! Packed virtqueues (VIRTIO 1.1, section 2.8).
+
+use super::{
+    DESCRIPTOR_SIZE, Descriptor, DescriptorChain, GuestMemory, VIRTQ_DESC_F_INDIRECT,
+    VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE, offset_address,
+};
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::machine::MemoryRegion;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{Ordering, fence};
+
+/// The descriptor is available (driver wrap counter).
+pub const VIRTQ_DESC_F_AVAIL: u16 = 1 << 7;
+
+/// The descriptor is used (device wrap counter).
+pub const VIRTQ_DESC_F_USED: u16 = 1 << 15;
+
+/// Notifications are enabled.
+pub const RING_EVENT_FLAGS_ENABLE: u16 = 0;
+
+/// Notifications are disabled.
+pub const RING_EVENT_FLAGS_DISABLE: u16 = 1;
+
+/// Notifications are enabled for a specific descriptor (with VIRTIO_F_EVENT_IDX).
+pub const RING_EVENT_FLAGS_DESC: u16 = 2;
+
+/// Wrap counter bit of an event offset.
+const EVENT_WRAP: u16 = 1 << 15;
+
+/// A packed virtqueue, as configured by the driver.
+#[derive(Clone, Debug)]
+pub struct PackedQueue {
+    /// Number of descriptors.
+    size: u16,
+
+    /// Guest address of the descriptor ring.
+    descriptor_ring: hv_ipa_t,
+
+    /// Guest address of the driver event suppression structure.
+    driver_event: hv_ipa_t,
+
+    /// Guest address of the device event suppression structure.
+    device_event: hv_ipa_t,
+
+    /// VIRTIO_F_EVENT_IDX was negotiated.
+    event_idx: bool,
+
+    /// Index of the next descriptor to process.
+    next_available: u16,
+
+    /// Wrap counter of the next descriptor to process.
+    available_wrap: bool,
+
+    /// Index of the next descriptor to write as used.
+    next_used: u16,
+
+    /// Wrap counter of the next descriptor to write as used.
+    used_wrap: bool,
+
+    /// Number of descriptors used since the last notification check.
+    pending_used: u32,
+
+    /// Number of ring descriptors of each in-flight chain, by buffer ID.
+    chain_lengths: Vec<u16>,
+}
+
+impl PackedQueue {
+    /// Create a new queue from the addresses programmed by the driver.
+    pub fn new(
+        size: u16,
+        descriptor_ring: hv_ipa_t,
+        driver_event: hv_ipa_t,
+        device_event: hv_ipa_t,
+        event_idx: bool,
+    ) -> Result<Self> {
+        if size == 0 || size >= EVENT_WRAP {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        Ok(PackedQueue {
+            size,
+            descriptor_ring,
+            driver_event,
+            device_event,
+            event_idx,
+            next_available: 0,
+            available_wrap: true,
+            next_used: 0,
+            used_wrap: true,
+            pending_used: 0,
+            chain_lengths: vec![0; size as usize],
+        })
+    }
+
+    /// Gets the number of descriptors.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Gets the guest memory regions of the descriptor ring, driver event suppression and device
+    /// event suppression structures.
+    pub fn regions(&self) -> [MemoryRegion; 3] {
+        [
+            MemoryRegion::new(self.descriptor_ring, u64::from(self.size) * DESCRIPTOR_SIZE),
+            MemoryRegion::new(self.driver_event, 4),
+            MemoryRegion::new(self.device_event, 4),
+        ]
+    }
+
+    /// Read a descriptor of a ring or table as (descriptor, id, flags).
+    fn read_descriptor<M: GuestMemory>(
+        memory: &M,
+        table: hv_ipa_t,
+        index: u32,
+    ) -> Result<(Descriptor, u16, u16)> {
+        let address = offset_address(table, u64::from(index) * DESCRIPTOR_SIZE)?;
+
+        // The whole descriptor must be addressable.
+        offset_address(address, DESCRIPTOR_SIZE - 1)?;
+
+        let flags = memory.read_u16(address + 14)?;
+
+        Ok((
+            Descriptor {
+                address: memory.read_u64(address)?,
+                length: memory.read_u32(address + 8)?,
+                is_write_only: flags & VIRTQ_DESC_F_WRITE != 0,
+            },
+            memory.read_u16(address + 12)?,
+            flags,
+        ))
+    }
+
+    /// Check if flags mark a descriptor as available for a wrap counter.
+    fn is_available(flags: u16, wrap: bool) -> bool {
+        (flags & VIRTQ_DESC_F_AVAIL != 0) == wrap && (flags & VIRTQ_DESC_F_USED != 0) != wrap
+    }
+
+    /// Advance a ring index, toggling the wrap counter when it wraps.
+    fn advance(&self, index: &mut u16, wrap: &mut bool, count: u16) {
+        *index += count;
+
+        if *index >= self.size {
+            *index -= self.size;
+            *wrap = !*wrap;
+        }
+    }
+
+    /// Read an indirect descriptor table, of at most as many descriptors as the queue.
+    fn read_indirect<M: GuestMemory>(
+        &self,
+        memory: &M,
+        table: &Descriptor,
+        descriptors: &mut Vec<Descriptor>,
+    ) -> Result<()> {
+        let count = table.length / DESCRIPTOR_SIZE as u32;
+
+        if !table.length.is_multiple_of(DESCRIPTOR_SIZE as u32) || count > u32::from(self.size) {
+            return Err(HypervisorError::IllegalGuestState);
+        }
+
+        for index in 0..count {
+            let (descriptor, _, flags) = Self::read_descriptor(memory, table.address, index)?;
+
+            if flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                return Err(HypervisorError::IllegalGuestState);
+            }
+
+            descriptors.push(descriptor);
+        }
+
+        Ok(())
+    }
+
+    /// Pop the next descriptor chain made available by the driver, if any.
+    pub fn pop<M: GuestMemory>(&mut self, memory: &M) -> Result<Option<DescriptorChain>> {
+        let (_, _, flags) =
+            Self::read_descriptor(memory, self.descriptor_ring, u32::from(self.next_available))?;
+
+        if !Self::is_available(flags, self.available_wrap) {
+            return Ok(None);
+        }
+
+        // Read the descriptors only after observing the flags.
+        fence(Ordering::Acquire);
+
+        let mut descriptors = Vec::new();
+        let mut index = self.next_available;
+        let mut wrap = self.available_wrap;
+        let mut count = 0;
+
+        let id = loop {
+            if count == self.size {
+                return Err(HypervisorError::IllegalGuestState);
+            }
+
+            let (descriptor, id, flags) =
+                Self::read_descriptor(memory, self.descriptor_ring, u32::from(index))?;
+
+            if !Self::is_available(flags, wrap) {
+                return Err(HypervisorError::IllegalGuestState);
+            }
+
+            if flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                self.read_indirect(memory, &descriptor, &mut descriptors)?;
+            } else {
+                descriptors.push(descriptor);
+            }
+
+            count += 1;
+            self.advance(&mut index, &mut wrap, 1);
+
+            // The buffer ID is the one of the last descriptor of the chain.
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                break id;
+            }
+        };
+
+        if id >= self.size {
+            return Err(HypervisorError::IllegalGuestState);
+        }
+
+        self.next_available = index;
+        self.available_wrap = wrap;
+        self.chain_lengths[id as usize] = count;
+
+        Ok(Some(DescriptorChain { id, descriptors }))
+    }
+
+    /// Return a descriptor chain to the driver, with the number of bytes written in it.
+    pub fn add_used<M: GuestMemory>(&mut self, memory: &mut M, id: u16, length: u32) -> Result<()> {
+        let count = self
+            .chain_lengths
+            .get_mut(id as usize)
+            .map(core::mem::take)
+            .filter(|&count| count != 0)
+            .ok_or(HypervisorError::BadArgument)?;
+
+        let address = offset_address(
+            self.descriptor_ring,
+            u64::from(self.next_used) * DESCRIPTOR_SIZE,
+        )?;
+        let flags = if self.used_wrap {
+            VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_USED
+        } else {
+            0
+        };
+
+        memory.write_u32(address + 8, length)?;
+        memory.write_u16(address + 12, id)?;
+
+        // Publish the element before the flags.
+        fence(Ordering::Release);
+
+        memory.write_u16(address + 14, flags)?;
+
+        let (mut index, mut wrap) = (self.next_used, self.used_wrap);
+
+        self.advance(&mut index, &mut wrap, count);
+        self.next_used = index;
+        self.used_wrap = wrap;
+        self.pending_used = self.pending_used.saturating_add(u32::from(count));
+
+        Ok(())
+    }
+
+    /// Check if the driver should be interrupted for the chains used since the last check.
+    pub fn needs_notification<M: GuestMemory>(&mut self, memory: &M) -> Result<bool> {
+        // Ring indices wrap at the queue size: count the used descriptors instead of comparing
+        // them, the old index may be "negative" relative to the new one.
+        let used = core::mem::take(&mut self.pending_used);
+        let new = self.next_used;
+        let old = new.wrapping_sub(used.min(u32::from(u16::MAX)) as u16);
+
+        fence(Ordering::SeqCst);
+
+        let offset_wrap = memory.read_u16(self.driver_event)?;
+        let flags = memory.read_u16(offset_address(self.driver_event, 2)?)?;
+
+        match flags {
+            RING_EVENT_FLAGS_DISABLE => Ok(false),
+            RING_EVENT_FLAGS_DESC if self.event_idx => {
+                let mut offset = offset_wrap & !EVENT_WRAP;
+
+                if (offset_wrap & EVENT_WRAP != 0) != self.used_wrap {
+                    offset = offset.wrapping_sub(self.size);
+                }
+
+                // vring_need_event()
+                Ok(new.wrapping_sub(offset).wrapping_sub(1) < new.wrapping_sub(old))
+            }
+            _ => Ok(used != 0),
+        }
+    }
+
+    /// Enable or disable driver notifications of newly available chains.
+    ///
+    /// With VIRTIO_F_EVENT_IDX, enabling requests a notification for the next available chain.
+    pub fn set_notification<M: GuestMemory>(&mut self, memory: &mut M, enable: bool) -> Result<()> {
+        if !enable {
+            memory.write_u16(
+                offset_address(self.device_event, 2)?,
+                RING_EVENT_FLAGS_DISABLE,
+            )?;
+        } else if self.event_idx {
+            let wrap = if self.available_wrap { EVENT_WRAP } else { 0 };
+
+            memory.write_u16(self.device_event, self.next_available | wrap)?;
+            memory.write_u16(offset_address(self.device_event, 2)?, RING_EVENT_FLAGS_DESC)?;
+        } else {
+            memory.write_u16(
+                offset_address(self.device_event, 2)?,
+                RING_EVENT_FLAGS_ENABLE,
+            )?;
+        }
+
+        fence(Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtqueue::tests::TestMemory;
+
+    /// Queue size used by the tests.
+    const SIZE: u16 = 4;
+
+    /// Guest address of the descriptor ring.
+    const RING: hv_ipa_t = 0x1000;
+
+    /// Guest address of the driver event suppression structure.
+    const DRIVER_EVENT: hv_ipa_t = 0x2000;
+
+    /// Guest address of the device event suppression structure.
+    const DEVICE_EVENT: hv_ipa_t = 0x2100;
+
+    /// Guest address of an indirect descriptor table.
+    const INDIRECT: hv_ipa_t = 0x3000;
+
+    /// Write a descriptor in a ring or table.
+    fn write_descriptor(
+        memory: &mut TestMemory,
+        table: hv_ipa_t,
+        index: u16,
+        length: u32,
+        id: u16,
+        flags: u16,
+    ) {
+        let entry = table + u64::from(index) * DESCRIPTOR_SIZE;
+
+        memory.write(entry, &0x100u64.to_le_bytes()).unwrap();
+        memory.write_u32(entry + 8, length).unwrap();
+        memory.write_u16(entry + 12, id).unwrap();
+        memory.write_u16(entry + 14, flags).unwrap();
+    }
+
+    fn queue() -> PackedQueue {
+        PackedQueue::new(SIZE, RING, DRIVER_EVENT, DEVICE_EVENT, false).unwrap()
+    }
+
+    #[test]
+    fn pop_indirect_table() {
+        let mut memory = TestMemory::new(0x4000);
+        let mut queue = queue();
+
+        write_descriptor(
+            &mut memory,
+            RING,
+            0,
+            2 * DESCRIPTOR_SIZE as u32,
+            0,
+            VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_INDIRECT,
+        );
+        write_descriptor(&mut memory, INDIRECT, 0, 8, 0, 0);
+        write_descriptor(&mut memory, INDIRECT, 1, 16, 0, VIRTQ_DESC_F_WRITE);
+        memory.write(RING, &INDIRECT.to_le_bytes()).unwrap();
+
+        let chain = queue.pop(&memory).unwrap().unwrap();
+
+        assert_eq!(chain.descriptors.len(), 2);
+        assert_eq!(chain.writable().count(), 1);
+    }
+
+    #[test]
+    fn pop_rejects_oversized_indirect_table() {
+        let mut memory = TestMemory::new(0x4000);
+        let mut queue = queue();
+
+        write_descriptor(
+            &mut memory,
+            RING,
+            0,
+            u32::from(SIZE + 1) * DESCRIPTOR_SIZE as u32,
+            0,
+            VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_INDIRECT,
+        );
+        memory.write(RING, &INDIRECT.to_le_bytes()).unwrap();
+
+        assert!(matches!(
+            queue.pop(&memory),
+            Err(HypervisorError::IllegalGuestState)
+        ));
+    }
+
+    #[test]
+    fn notification_after_full_ring() {
+        let mut memory = TestMemory::new(0x4000);
+        let mut queue = queue();
+
+        for index in 0..SIZE {
+            write_descriptor(&mut memory, RING, index, 8, index, VIRTQ_DESC_F_AVAIL);
+        }
+
+        for id in 0..SIZE {
+            assert_eq!(queue.pop(&memory).unwrap().unwrap().id, id);
+        }
+
+        for id in 0..SIZE {
+            queue.add_used(&mut memory, id, 0).unwrap();
+        }
+
+        // The used index wrapped back to where it was at the last check.
+        assert!(queue.needs_notification(&memory).unwrap());
+        assert!(!queue.needs_notification(&memory).unwrap());
+    }
+}
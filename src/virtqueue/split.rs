@@ -0,0 +1,525 @@
+//! Split virtqueues (VIRTIO 1.x, section 2.7).
+
+use super::{
+    DESCRIPTOR_SIZE, Descriptor, DescriptorChain, GuestMemory, VIRTQ_DESC_F_INDIRECT,
+    VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE, offset_address,
+};
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::machine::MemoryRegion;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{Ordering, fence};
+
+/// The driver doesn't want interrupts (available ring flag).
+pub const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+/// The device doesn't want notifications (used ring flag).
+pub const VIRTQ_USED_F_NO_NOTIFY: u16 = 1;
+
+/// Size of an element of the used ring.
+const USED_ELEMENT_SIZE: u64 = 8;
+
+/// A split virtqueue, as configured by the driver.
+#[derive(Clone, Debug)]
+pub struct SplitQueue {
+    /// Number of descriptors (a power of 2).
+    size: u16,
+
+    /// Guest address of the descriptor table.
+    descriptor_table: hv_ipa_t,
+
+    /// Guest address of the available ring.
+    available_ring: hv_ipa_t,
+
+    /// Guest address of the used ring.
+    used_ring: hv_ipa_t,
+
+    /// VIRTIO_F_EVENT_IDX was negotiated.
+    event_idx: bool,
+
+    /// Index of the next available entry to process.
+    next_available: u16,
+
+    /// Index of the next used entry to fill.
+    next_used: u16,
+
+    /// Value of the used index at the last notification check.
+    signalled_used: u16,
+}
+
+impl SplitQueue {
+    /// Create a new queue from the addresses programmed by the driver.
+    pub fn new(
+        size: u16,
+        descriptor_table: hv_ipa_t,
+        available_ring: hv_ipa_t,
+        used_ring: hv_ipa_t,
+        event_idx: bool,
+    ) -> Result<Self> {
+        if size == 0 || !size.is_power_of_two() {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        Ok(SplitQueue {
+            size,
+            descriptor_table,
+            available_ring,
+            used_ring,
+            event_idx,
+            next_available: 0,
+            next_used: 0,
+            signalled_used: 0,
+        })
+    }
+
+    /// Gets the number of descriptors.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Gets the guest memory regions of the descriptor table, available ring and used ring.
+    pub fn regions(&self) -> [MemoryRegion; 3] {
+        let size = u64::from(self.size);
+
+        [
+            MemoryRegion::new(self.descriptor_table, size * DESCRIPTOR_SIZE),
+            MemoryRegion::new(self.available_ring, 6 + size * 2),
+            MemoryRegion::new(self.used_ring, 6 + size * USED_ELEMENT_SIZE),
+        ]
+    }
+
+    /// Read a descriptor of a table as (descriptor, flags, next).
+    fn read_descriptor<M: GuestMemory>(
+        memory: &M,
+        table: hv_ipa_t,
+        index: u16,
+    ) -> Result<(Descriptor, u16, u16)> {
+        let address = offset_address(table, u64::from(index) * DESCRIPTOR_SIZE)?;
+
+        // The whole descriptor must be addressable.
+        offset_address(address, DESCRIPTOR_SIZE - 1)?;
+
+        let flags = memory.read_u16(address + 12)?;
+
+        Ok((
+            Descriptor {
+                address: memory.read_u64(address)?,
+                length: memory.read_u32(address + 8)?,
+                is_write_only: flags & VIRTQ_DESC_F_WRITE != 0,
+            },
+            flags,
+            memory.read_u16(address + 14)?,
+        ))
+    }
+
+    /// Walk a descriptor chain from its head.
+    fn read_chain<M: GuestMemory>(&self, memory: &M, head: u16) -> Result<DescriptorChain> {
+        let mut descriptors = Vec::new();
+
+        let mut table = self.descriptor_table;
+        let mut table_size = u32::from(self.size);
+        let mut index = head;
+        let mut is_indirect = false;
+
+        loop {
+            if u32::from(index) >= table_size || descriptors.len() as u32 > table_size {
+                return Err(HypervisorError::IllegalGuestState);
+            }
+
+            let (descriptor, flags, next) = Self::read_descriptor(memory, table, index)?;
+
+            if flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                // Nested indirect tables and indirect descriptors in a chain are forbidden, and a
+                // table can't hold more descriptors than the queue.
+                if is_indirect
+                    || !descriptor.length.is_multiple_of(DESCRIPTOR_SIZE as u32)
+                    || descriptor.length / DESCRIPTOR_SIZE as u32 > u32::from(self.size)
+                {
+                    return Err(HypervisorError::IllegalGuestState);
+                }
+
+                table = descriptor.address;
+                table_size = descriptor.length / DESCRIPTOR_SIZE as u32;
+                index = 0;
+                is_indirect = true;
+                descriptors.clear();
+
+                continue;
+            }
+
+            descriptors.push(descriptor);
+
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+
+            index = next;
+        }
+
+        Ok(DescriptorChain {
+            id: head,
+            descriptors,
+        })
+    }
+
+    /// Pop the next descriptor chain made available by the driver, if any.
+    pub fn pop<M: GuestMemory>(&mut self, memory: &M) -> Result<Option<DescriptorChain>> {
+        let available_index = memory.read_u16(offset_address(self.available_ring, 2)?)?;
+
+        if available_index == self.next_available {
+            return Ok(None);
+        }
+
+        // Read the ring entry only after observing the index.
+        fence(Ordering::Acquire);
+
+        let slot = u64::from(self.next_available % self.size);
+        let head = memory.read_u16(offset_address(self.available_ring, 4 + slot * 2)?)?;
+
+        self.next_available = self.next_available.wrapping_add(1);
+
+        self.read_chain(memory, head).map(Some)
+    }
+
+    /// Return a descriptor chain to the driver, with the number of bytes written in it.
+    pub fn add_used<M: GuestMemory>(&mut self, memory: &mut M, id: u16, length: u32) -> Result<()> {
+        let slot = u64::from(self.next_used % self.size);
+        let element = offset_address(self.used_ring, 4 + slot * USED_ELEMENT_SIZE)?;
+
+        memory.write_u32(element, u32::from(id))?;
+        memory.write_u32(offset_address(element, 4)?, length)?;
+
+        self.next_used = self.next_used.wrapping_add(1);
+
+        // Publish the element before the index.
+        fence(Ordering::Release);
+
+        memory.write_u16(offset_address(self.used_ring, 2)?, self.next_used)
+    }
+
+    /// Check if the driver should be interrupted for the chains used since the last check.
+    pub fn needs_notification<M: GuestMemory>(&mut self, memory: &M) -> Result<bool> {
+        let old = self.signalled_used;
+        let new = self.next_used;
+
+        self.signalled_used = new;
+
+        fence(Ordering::SeqCst);
+
+        if self.event_idx {
+            let used_event = memory.read_u16(offset_address(
+                self.available_ring,
+                4 + u64::from(self.size) * 2,
+            )?)?;
+
+            // vring_need_event()
+            Ok(new.wrapping_sub(used_event).wrapping_sub(1) < new.wrapping_sub(old))
+        } else {
+            let flags = memory.read_u16(self.available_ring)?;
+
+            Ok(flags & VIRTQ_AVAIL_F_NO_INTERRUPT == 0 && old != new)
+        }
+    }
+
+    /// Enable or disable driver notifications of newly available chains.
+    ///
+    /// With VIRTIO_F_EVENT_IDX, enabling requests a notification for the next available chain.
+    pub fn set_notification<M: GuestMemory>(&mut self, memory: &mut M, enable: bool) -> Result<()> {
+        if self.event_idx {
+            if enable {
+                memory.write_u16(
+                    offset_address(self.used_ring, 4 + u64::from(self.size) * USED_ELEMENT_SIZE)?,
+                    self.next_available,
+                )?;
+            }
+        } else {
+            let flags = memory.read_u16(self.used_ring)?;
+            let flags = if enable {
+                flags & !VIRTQ_USED_F_NO_NOTIFY
+            } else {
+                flags | VIRTQ_USED_F_NO_NOTIFY
+            };
+
+            memory.write_u16(self.used_ring, flags)?;
+        }
+
+        fence(Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtqueue::tests::TestMemory;
+
+    /// Queue size used by the tests.
+    const SIZE: u16 = 8;
+
+    /// Guest address of the descriptor table.
+    const TABLE: hv_ipa_t = 0x1000;
+
+    /// Guest address of the available ring.
+    const AVAILABLE: hv_ipa_t = 0x2000;
+
+    /// Guest address of the used ring.
+    const USED: hv_ipa_t = 0x3000;
+
+    /// Guest address of an indirect descriptor table.
+    const INDIRECT: hv_ipa_t = 0x4000;
+
+    impl TestMemory {
+        /// Write a descriptor in a table.
+        fn descriptor(
+            &mut self,
+            table: hv_ipa_t,
+            index: u16,
+            address: u64,
+            length: u32,
+            flags: u16,
+            next: u16,
+        ) {
+            let entry = table + u64::from(index) * DESCRIPTOR_SIZE;
+
+            self.write(entry, &address.to_le_bytes()).unwrap();
+            self.write_u32(entry + 8, length).unwrap();
+            self.write_u16(entry + 12, flags).unwrap();
+            self.write_u16(entry + 14, next).unwrap();
+        }
+
+        /// Make a chain available.
+        fn make_available(&mut self, head: u16) {
+            let index = self.read_u16(AVAILABLE + 2).unwrap();
+
+            self.write_u16(AVAILABLE + 4 + u64::from(index % SIZE) * 2, head)
+                .unwrap();
+            self.write_u16(AVAILABLE + 2, index.wrapping_add(1))
+                .unwrap();
+        }
+    }
+
+    fn queue(event_idx: bool) -> SplitQueue {
+        SplitQueue::new(SIZE, TABLE, AVAILABLE, USED, event_idx).unwrap()
+    }
+
+    #[test]
+    fn pop_descriptor_chain() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        memory.descriptor(TABLE, 3, 0x100, 16, VIRTQ_DESC_F_NEXT, 5);
+        memory.descriptor(TABLE, 5, 0x200, 32, VIRTQ_DESC_F_WRITE, 0);
+        memory.make_available(3);
+
+        let chain = queue.pop(&memory).unwrap().unwrap();
+
+        assert_eq!(chain.id, 3);
+        assert_eq!(
+            chain.descriptors,
+            [
+                Descriptor {
+                    address: 0x100,
+                    length: 16,
+                    is_write_only: false,
+                },
+                Descriptor {
+                    address: 0x200,
+                    length: 32,
+                    is_write_only: true,
+                },
+            ]
+        );
+        assert_eq!(queue.pop(&memory).unwrap(), None);
+    }
+
+    #[test]
+    fn pop_rejects_looping_chain() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        memory.descriptor(TABLE, 0, 0x100, 16, VIRTQ_DESC_F_NEXT, 1);
+        memory.descriptor(TABLE, 1, 0x200, 16, VIRTQ_DESC_F_NEXT, 0);
+        memory.make_available(0);
+
+        assert!(matches!(
+            queue.pop(&memory),
+            Err(HypervisorError::IllegalGuestState)
+        ));
+    }
+
+    #[test]
+    fn pop_indirect_table() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        memory.descriptor(
+            TABLE,
+            2,
+            INDIRECT,
+            2 * DESCRIPTOR_SIZE as u32,
+            VIRTQ_DESC_F_INDIRECT,
+            0,
+        );
+        memory.descriptor(INDIRECT, 0, 0x100, 8, VIRTQ_DESC_F_NEXT, 1);
+        memory.descriptor(INDIRECT, 1, 0x200, 64, VIRTQ_DESC_F_WRITE, 0);
+        memory.make_available(2);
+
+        let chain = queue.pop(&memory).unwrap().unwrap();
+
+        assert_eq!(chain.id, 2);
+        assert_eq!(chain.readable().count(), 1);
+        assert_eq!(
+            chain
+                .writable()
+                .map(|descriptor| descriptor.length)
+                .sum::<u32>(),
+            64
+        );
+    }
+
+    #[test]
+    fn pop_rejects_nested_indirect_table() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        memory.descriptor(
+            TABLE,
+            0,
+            INDIRECT,
+            DESCRIPTOR_SIZE as u32,
+            VIRTQ_DESC_F_INDIRECT,
+            0,
+        );
+        memory.descriptor(
+            INDIRECT,
+            0,
+            INDIRECT,
+            DESCRIPTOR_SIZE as u32,
+            VIRTQ_DESC_F_INDIRECT,
+            0,
+        );
+        memory.make_available(0);
+
+        assert!(matches!(
+            queue.pop(&memory),
+            Err(HypervisorError::IllegalGuestState)
+        ));
+    }
+
+    #[test]
+    fn pop_rejects_overflowing_table() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        memory.descriptor(
+            TABLE,
+            0,
+            u64::MAX - 8,
+            4 * DESCRIPTOR_SIZE as u32,
+            VIRTQ_DESC_F_INDIRECT,
+            0,
+        );
+        memory.make_available(0);
+
+        assert!(matches!(
+            queue.pop(&memory),
+            Err(HypervisorError::IllegalGuestState)
+        ));
+    }
+
+    #[test]
+    fn pop_rejects_oversized_indirect_table() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        memory.descriptor(
+            TABLE,
+            0,
+            INDIRECT,
+            u32::from(SIZE + 1) * DESCRIPTOR_SIZE as u32,
+            VIRTQ_DESC_F_INDIRECT,
+            0,
+        );
+        memory.make_available(0);
+
+        assert!(matches!(
+            queue.pop(&memory),
+            Err(HypervisorError::IllegalGuestState)
+        ));
+    }
+
+    #[test]
+    fn add_used_updates_ring() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        for id in 0..SIZE + 1 {
+            queue
+                .add_used(&mut memory, id, 0x10 + u32::from(id))
+                .unwrap();
+        }
+
+        // The ring wrapped around, the first element was overwritten.
+        assert_eq!(memory.read_u16(USED + 2).unwrap(), SIZE + 1);
+        assert_eq!(memory.read_u32(USED + 4).unwrap(), u32::from(SIZE));
+        assert_eq!(memory.read_u32(USED + 8).unwrap(), 0x10 + u32::from(SIZE));
+        assert_eq!(memory.read_u32(USED + 4 + USED_ELEMENT_SIZE).unwrap(), 1);
+    }
+
+    #[test]
+    fn notification_flags() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(false);
+
+        assert!(!queue.needs_notification(&memory).unwrap());
+
+        queue.add_used(&mut memory, 0, 0).unwrap();
+        assert!(queue.needs_notification(&memory).unwrap());
+
+        memory
+            .write_u16(AVAILABLE, VIRTQ_AVAIL_F_NO_INTERRUPT)
+            .unwrap();
+        queue.add_used(&mut memory, 1, 0).unwrap();
+        assert!(!queue.needs_notification(&memory).unwrap());
+
+        queue.set_notification(&mut memory, false).unwrap();
+        assert_eq!(memory.read_u16(USED).unwrap(), VIRTQ_USED_F_NO_NOTIFY);
+    }
+
+    #[test]
+    fn event_idx_suppression() {
+        let mut memory = TestMemory::new(0x5000);
+        let mut queue = queue(true);
+        let used_event = AVAILABLE + 4 + u64::from(SIZE) * 2;
+
+        // The driver asks to be interrupted once the used index goes past 2.
+        memory.write_u16(used_event, 2).unwrap();
+
+        queue.add_used(&mut memory, 0, 0).unwrap();
+        queue.add_used(&mut memory, 1, 0).unwrap();
+        assert!(!queue.needs_notification(&memory).unwrap());
+
+        queue.add_used(&mut memory, 2, 0).unwrap();
+        assert!(queue.needs_notification(&memory).unwrap());
+
+        queue.add_used(&mut memory, 3, 0).unwrap();
+        assert!(!queue.needs_notification(&memory).unwrap());
+
+        // Enabling notifications publishes the next available index as the avail event.
+        memory.make_available(0);
+        memory.descriptor(TABLE, 0, 0x100, 8, 0, 0);
+        queue.pop(&memory).unwrap();
+        queue.set_notification(&mut memory, true).unwrap();
+
+        assert_eq!(
+            memory
+                .read_u16(USED + 4 + u64::from(SIZE) * USED_ELEMENT_SIZE)
+                .unwrap(),
+            1
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::bindings::*;
+use crate::capabilities::require_macos;
 use crate::err::{HypervisorError, Result, convert_hv_return};
+use crate::event::{EventRegistry, VmEvent};
 use crate::vcpu::*;
 
 extern crate alloc;
@@ -8,6 +10,13 @@ use alloc::vec::Vec;
 
 use core::ffi::c_void;
 
+unsafe extern "C" {
+    fn sys_icache_invalidate(start: *mut c_void, len: usize);
+}
+
+/// First macOS release with the EL2 configuration functions.
+const EL2_MIN_MACOS_VERSION: u32 = 15;
+
 /// Represent the configuration of a Virtual Machine.
 #[derive(Debug)]
 pub struct VirtualMachineConfiguration {
@@ -18,15 +27,121 @@ pub struct VirtualMachineConfiguration {
 impl VirtualMachineConfiguration {
     /// Create a new Virtual Machine configuration instance.
     pub fn new() -> Result<Self> {
-        Ok(VirtualMachineConfiguration {
-            // NOTE: no configuration APIs are availaible for the VM at the time of writting, as such this is set to null.
-            handle: core::ptr::null_mut(),
-        })
+        let handle = unsafe { hv_vm_config_create() };
+
+        if handle.is_null() {
+            return Err(HypervisorError::NoResources);
+        }
+
+        Ok(VirtualMachineConfiguration { handle })
+    }
+
+    /// Gets the default guest physical address size in bits.
+    pub fn default_ipa_size() -> Result<u32> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_vm_config_get_default_ipa_size(&mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Gets the maximum guest physical address size supported by the host in bits.
+    pub fn max_ipa_size() -> Result<u32> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_vm_config_get_max_ipa_size(&mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Gets the guest physical address size in bits.
+    pub fn get_ipa_size(&self) -> Result<u32> {
+        let mut result = 0;
+
+        let ret = unsafe { hv_vm_config_get_ipa_size(self.handle, &mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Set the guest physical address size in bits.
+    ///
+    /// The size can't exceed [VirtualMachineConfiguration::max_ipa_size].
+    pub fn set_ipa_size(&mut self, ipa_size: u32) -> Result<()> {
+        let ret = unsafe { hv_vm_config_set_ipa_size(self.handle, ipa_size) };
+
+        convert_hv_return(ret)
+    }
+
+    /// Check if the host supports running guests at EL2 (nested virtualization).
+    ///
+    /// This requires macOS 15 on an Apple M3 or later. Fails with [HypervisorError::Unsupported]
+    /// on older macOS releases.
+    pub fn is_el2_supported() -> Result<bool> {
+        require_macos(EL2_MIN_MACOS_VERSION)?;
+
+        let mut result = false;
+
+        let ret = unsafe { hv_vm_config_get_el2_supported(&mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Check if the guest runs at EL2.
+    ///
+    /// Fails with [HypervisorError::Unsupported] on macOS releases older than 15.
+    pub fn is_el2_enabled(&self) -> Result<bool> {
+        require_macos(EL2_MIN_MACOS_VERSION)?;
+
+        let mut result = false;
+
+        let ret = unsafe { hv_vm_config_get_el2_enabled(self.handle, &mut result) };
+
+        // Ensure no error got reported
+        convert_hv_return(ret)?;
+
+        Ok(result)
+    }
+
+    /// Enable or disable EL2 for the guest, so it can run its own hypervisor.
+    ///
+    /// With EL2 enabled, vCPUs start at EL2: the loaders set CPSR to EL2h (see
+    /// [crate::loader::reset_cpsr]). Fails with [HypervisorError::Unsupported] if the host or its
+    /// macOS release doesn't support it.
+    pub fn set_el2_enabled(&mut self, enabled: bool) -> Result<()> {
+        require_macos(EL2_MIN_MACOS_VERSION)?;
+
+        if enabled && !VirtualMachineConfiguration::is_el2_supported()? {
+            return Err(HypervisorError::Unsupported);
+        }
+
+        let ret = unsafe { hv_vm_config_set_el2_enabled(self.handle, enabled) };
+
+        convert_hv_return(ret)
+    }
+}
+
+impl Drop for VirtualMachineConfiguration {
+    fn drop(&mut self) {
+        unsafe {
+            os_release(self.handle as *mut c_void);
+        }
     }
 }
 
 /// Represent the permission of a memory region.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct MemoryPermission {
     /// Read.
     read: bool,
@@ -48,6 +163,21 @@ impl MemoryPermission {
         }
     }
 
+    /// Check if reads are allowed.
+    pub const fn is_readable(&self) -> bool {
+        self.read
+    }
+
+    /// Check if writes are allowed.
+    pub const fn is_writable(&self) -> bool {
+        self.write
+    }
+
+    /// Check if execution is allowed.
+    pub const fn is_executable(&self) -> bool {
+        self.execute
+    }
+
     /// Read-only.
     pub const READ: MemoryPermission = MemoryPermission::new(true, false, false);
 
@@ -141,12 +271,17 @@ struct VirtualMachineAllocation {
 
     /// Associated handle.
     handle: AllocationHandle,
+
+    /// The memory is owned by the allocation (and not provided by the caller).
+    owned: bool,
 }
 
 impl Drop for VirtualMachineAllocation {
     fn drop(&mut self) {
-        unsafe {
-            alloc::alloc::dealloc(self.base_address, self.layout);
+        if self.owned {
+            unsafe {
+                alloc::alloc::dealloc(self.base_address, self.layout);
+            }
         }
     }
 }
@@ -166,6 +301,7 @@ impl VirtualMachineAllocation {
                 base_address: alloc::alloc::alloc_zeroed(layout),
                 layout,
                 handle: AllocationHandle(0),
+                owned: true,
             }
         }
     }
@@ -185,6 +321,21 @@ pub struct VirtualMachine {
 
     /// List of all mappings.
     mapping_list: Vec<VirtualMachineMapping>,
+
+    /// Callbacks registered for lifecycle events.
+    event_registry: EventRegistry,
+
+    /// List of ROM mappings with their original contents.
+    rom_list: Vec<(MappingHandle, Vec<u8>)>,
+
+    /// Perform cache maintenance automatically when granting execute permission.
+    cache_maintenance: bool,
+
+    /// The guest runs at EL2.
+    el2_enabled: bool,
+
+    /// The GIC got created.
+    pub(crate) has_gic: bool,
 }
 
 impl VirtualMachine {
@@ -192,10 +343,16 @@ impl VirtualMachine {
     ///
     /// **There should be only one instance living in the same process.**
     pub fn new(config: Option<VirtualMachineConfiguration>) -> Result<Self> {
+        // The configuration must stay alive until the Virtual Machine is created.
         let handle: hv_vm_config_t = config
+            .as_ref()
             .map(|value| value.handle)
             .unwrap_or(core::ptr::null_mut());
 
+        let el2_enabled = config
+            .as_ref()
+            .is_some_and(|value| value.is_el2_enabled().unwrap_or(false));
+
         let ret = unsafe { hv_vm_create(handle) };
 
         convert_hv_return(ret).map(|_| VirtualMachine {
@@ -203,9 +360,19 @@ impl VirtualMachine {
             mapping_counter: Counter::default(),
             allocation_list: Vec::new(),
             mapping_list: Vec::new(),
+            event_registry: EventRegistry::default(),
+            rom_list: Vec::new(),
+            cache_maintenance: true,
+            el2_enabled,
+            has_gic: false,
         })
     }
 
+    /// Check if the guest runs at EL2, as set with [VirtualMachineConfiguration::set_el2_enabled].
+    pub fn is_el2_enabled(&self) -> bool {
+        self.el2_enabled
+    }
+
     /// Create a new allocation that can be used in the Virtual Machine.
     pub fn allocate(&mut self, size: usize) -> Result<AllocationHandle> {
         let mut allocation = VirtualMachineAllocation::new(size);
@@ -233,6 +400,35 @@ impl VirtualMachine {
         }
     }
 
+    /// Register host memory not owned by the Virtual Machine (e.g. shared memory) as an
+    /// allocation that can be mapped.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be page aligned, `size` bytes long and stay valid until the allocation is
+    /// deallocated or the Virtual Machine dropped.
+    pub unsafe fn allocate_external(
+        &mut self,
+        base_address: *mut u8,
+        size: usize,
+    ) -> Result<AllocationHandle> {
+        if !(base_address as usize).is_multiple_of(PAGE_SIZE) || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(HypervisorError::MisalignedAddress);
+        }
+
+        let handle = AllocationHandle(self.allocation_counter.get_next_value());
+
+        self.allocation_list.push(VirtualMachineAllocation {
+            base_address,
+            layout: Layout::from_size_align(size, PAGE_SIZE)
+                .map_err(|_| HypervisorError::BadArgument)?,
+            handle,
+            owned: false,
+        });
+
+        Ok(handle)
+    }
+
     /// Find an allocation by handle.
     fn find_allocation_by_handle(
         &self,
@@ -313,6 +509,126 @@ impl VirtualMachine {
         Ok(slice)
     }
 
+    /// Find the mapping containing a guest address.
+    fn find_mapping_by_address(&self, address: hv_ipa_t) -> Result<VirtualMachineMapping> {
+        for entry in self.mapping_list.iter() {
+            if address >= entry.address && address - entry.address < entry.size as u64 {
+                return Ok(*entry);
+            }
+        }
+
+        Err(HypervisorError::UnmappedAddress)
+    }
+
+    /// Read guest memory starting at a given guest address.
+    ///
+    /// The range may span multiple contiguous mappings.
+    pub fn read_memory(&self, address: hv_ipa_t, buffer: &mut [u8]) -> Result<()> {
+        let mut offset = 0;
+
+        while offset < buffer.len() {
+            let current_address = address + offset as u64;
+            let mapping = self.find_mapping_by_address(current_address)?;
+            let source = self.get_allocation_slice(mapping.allocation_handle)?;
+
+            let start = (current_address - mapping.address) as usize;
+            let size = (mapping.size - start).min(buffer.len() - offset);
+
+            buffer[offset..offset + size].copy_from_slice(&source[start..start + size]);
+            offset += size;
+        }
+
+        Ok(())
+    }
+
+    /// Write guest memory starting at a given guest address.
+    ///
+    /// The range may span multiple contiguous mappings. Mapping permissions are ignored.
+    pub fn write_memory(&mut self, address: hv_ipa_t, data: &[u8]) -> Result<()> {
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let current_address = address + offset as u64;
+            let mapping = self.find_mapping_by_address(current_address)?;
+            let destination = self.get_allocation_slice_mut(mapping.allocation_handle)?;
+
+            let start = (current_address - mapping.address) as usize;
+            let size = (mapping.size - start).min(data.len() - offset);
+
+            destination[start..start + size].copy_from_slice(&data[offset..offset + size]);
+            offset += size;
+        }
+
+        Ok(())
+    }
+
+    /// Reset the guest memory for a reboot.
+    ///
+    /// Every allocation mapped writable is zeroed, read-only mappings (firmware, ROMs) are left
+    /// untouched and ROM contents are restored. Guest images must then be loaded again, and every
+    /// vCPU reset with [crate::loader::reset_vcpu] in its own thread.
+    ///
+    /// **All vCPUs should be stopped before calling this.**
+    pub fn reset(&mut self) -> Result<()> {
+        for mapping in self.get_all_mapping_infos() {
+            if mapping.permission.write {
+                self.get_allocation_slice_mut(mapping.allocation_handle)?
+                    .fill(0);
+
+                if mapping.permission.execute {
+                    self.maintain_instruction_cache(mapping.allocation_handle)?;
+                }
+            }
+        }
+
+        for (mapping_handle, contents) in self.rom_list.clone() {
+            let (_, mapping) = self.find_mapping_by_handle(mapping_handle)?;
+            let allocation_handle = mapping.allocation_handle;
+
+            let destination = self.get_allocation_slice_mut(allocation_handle)?;
+
+            destination.fill(0);
+            destination[..contents.len()].copy_from_slice(&contents);
+
+            self.maintain_instruction_cache(allocation_handle)?;
+        }
+
+        Ok(())
+    }
+
+    /// Map a read-only boot ROM in the Virtual Machine.
+    ///
+    /// The ROM contents are restored on every [VirtualMachine::reset], and the address of the first
+    /// ROM mapped is reported by [VirtualMachine::get_reset_pc].
+    pub fn map_rom(&mut self, guest_address: hv_ipa_t, contents: &[u8]) -> Result<MappingHandle> {
+        let allocation_handle = self.allocate_from(contents)?;
+        let mapping_handle = self
+            .map(
+                allocation_handle,
+                guest_address,
+                MemoryPermission::READ_EXECUTE,
+            )
+            .inspect_err(|_| {
+                let _ = self.deallocate(allocation_handle);
+            })?;
+
+        self.rom_list.push((mapping_handle, contents.to_vec()));
+
+        Ok(mapping_handle)
+    }
+
+    /// Gets the address of the first ROM mapped, the default entry point of
+    /// [crate::loader::reset_vcpu].
+    ///
+    /// vCPUs capture it when created, ROMs should be mapped before.
+    pub fn get_reset_pc(&self) -> Option<hv_ipa_t> {
+        self.rom_list.first().and_then(|(mapping_handle, _)| {
+            self.find_mapping_by_handle(*mapping_handle)
+                .ok()
+                .map(|(_, mapping)| mapping.address)
+        })
+    }
+
     /// Map an allocation in the Virtual Machine.
     pub fn map(
         &mut self,
@@ -320,6 +636,30 @@ impl VirtualMachine {
         guest_address: hv_ipa_t,
         permission: MemoryPermission,
     ) -> Result<MappingHandle> {
+        let allocation_size = self.map_allocation(allocation_handle, guest_address, permission)?;
+
+        let mapping_handle = MappingHandle(self.mapping_counter.get_next_value());
+
+        let virtual_mapping = VirtualMachineMapping {
+            allocation_handle,
+            mapping_handle,
+            address: guest_address,
+            size: allocation_size,
+            permission,
+        };
+
+        self.mapping_list.push(virtual_mapping);
+
+        Ok(mapping_handle)
+    }
+
+    /// Map an allocation in the guest address space, returning the size mapped.
+    fn map_allocation(
+        &self,
+        allocation_handle: AllocationHandle,
+        guest_address: hv_ipa_t,
+        permission: MemoryPermission,
+    ) -> Result<usize> {
         let (_, allocation) = self.find_allocation_by_handle(allocation_handle)?;
 
         let allocation_size = allocation.layout.size();
@@ -328,6 +668,10 @@ impl VirtualMachine {
             return Err(HypervisorError::MisalignedAddress);
         }
 
+        if permission.execute {
+            self.maintain_instruction_cache(allocation_handle)?;
+        }
+
         let ret = unsafe {
             hv_vm_map(
                 allocation.base_address as *mut c_void,
@@ -340,19 +684,7 @@ impl VirtualMachine {
         // Ensure no error got reported
         convert_hv_return(ret)?;
 
-        let mapping_handle = MappingHandle(self.mapping_counter.get_next_value());
-
-        let virtual_mapping = VirtualMachineMapping {
-            allocation_handle,
-            mapping_handle,
-            address: guest_address,
-            size: allocation_size,
-            permission,
-        };
-
-        self.mapping_list.push(virtual_mapping);
-
-        Ok(mapping_handle)
+        Ok(allocation_size)
     }
 
     /// Unmap a given mapping in the Virtual Machine.
@@ -365,6 +697,8 @@ impl VirtualMachine {
         convert_hv_return(ret)?;
 
         self.mapping_list.remove(index);
+        self.rom_list
+            .retain(|(handle, _)| *handle != mapping_handle);
 
         Ok(())
     }
@@ -377,6 +711,10 @@ impl VirtualMachine {
     ) -> Result<()> {
         let (index, mapping) = self.find_mapping_by_handle(mapping_handle)?;
 
+        if permission.execute {
+            self.maintain_instruction_cache(mapping.allocation_handle)?;
+        }
+
         let ret = unsafe {
             hv_vm_protect(
                 mapping.address,
@@ -398,6 +736,63 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// Enable or disable automatic cache maintenance (enabled by default).
+    ///
+    /// When enabled, the data cache is cleaned and the instruction cache invalidated for an
+    /// allocation whenever execute permission is granted on it by [VirtualMachine::map] or
+    /// [VirtualMachine::reprotect], so the guest never executes stale instructions. Code written
+    /// to an already executable mapping still requires [VirtualMachine::clean_instruction_cache].
+    pub fn set_cache_maintenance(&mut self, enabled: bool) {
+        self.cache_maintenance = enabled;
+    }
+
+    /// Perform the automatic cache maintenance of an allocation, if enabled.
+    fn maintain_instruction_cache(&self, allocation_handle: AllocationHandle) -> Result<()> {
+        if self.cache_maintenance {
+            self.clean_instruction_cache(allocation_handle)?;
+        }
+
+        Ok(())
+    }
+
+    /// Clean the data cache and invalidate the instruction cache of an allocation, making code
+    /// written by the host visible to guest instruction fetches.
+    pub fn clean_instruction_cache(&self, allocation_handle: AllocationHandle) -> Result<()> {
+        let (_, allocation) = self.find_allocation_by_handle(allocation_handle)?;
+
+        unsafe {
+            sys_icache_invalidate(
+                allocation.base_address as *mut c_void,
+                allocation.layout.size(),
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Clean the data cache and invalidate the instruction cache of a guest memory range, making
+    /// code patched by the host (breakpoints, trampolines...) visible to guest instruction fetches.
+    pub fn clean_instruction_cache_range(&self, address: hv_ipa_t, size: usize) -> Result<()> {
+        let mut offset = 0;
+
+        while offset < size {
+            let current_address = address + offset as u64;
+            let mapping = self.find_mapping_by_address(current_address)?;
+            let (_, allocation) = self.find_allocation_by_handle(mapping.allocation_handle)?;
+
+            let start = (current_address - mapping.address) as usize;
+            let length = (mapping.size - start).min(size - offset);
+
+            unsafe {
+                sys_icache_invalidate(allocation.base_address.add(start) as *mut c_void, length)
+            };
+
+            offset += length;
+        }
+
+        Ok(())
+    }
+
     /// Create a new vCPU.
     ///
     /// **This should be called in the thread that will run the vCPU as it's resident inside it.**
@@ -414,12 +809,38 @@ impl VirtualMachine {
 
         let ret = unsafe { hv_vcpu_create(&mut vcpu_handle, &mut vcpu_exit, handle) };
 
-        convert_hv_return(ret).map(|_| VirtualCpu {
+        convert_hv_return(ret)?;
+
+        self.notify(VmEvent::VcpuCreated(vcpu_handle));
+
+        Ok(VirtualCpu {
             handle: vcpu_handle,
             vcpu_exit,
+            owner: unsafe { libc::pthread_self() },
+            hooks: VirtualCpuHooks::default(),
+            el2_enabled: self.el2_enabled,
+            has_gic: self.has_gic,
+            reset_pc: self.get_reset_pc(),
         })
     }
 
+    /// Register a callback called on every lifecycle event of the Virtual Machine.
+    pub fn on_event<F>(&mut self, callback: F)
+    where
+        F: FnMut(&VmEvent) + 'static,
+    {
+        self.event_registry
+            .register(alloc::boxed::Box::new(callback));
+    }
+
+    /// Deliver a lifecycle event to all registered callbacks.
+    ///
+    /// This is used by the embedder to report events the crate can't observe by itself (guest
+    /// power requests, stopped vCPUs, fatal exits).
+    pub fn notify(&mut self, event: VmEvent) {
+        self.event_registry.notify(&event);
+    }
+
     /// Exits given vCPUs.
     pub fn exit_vcpus(&mut self, vcpus: &mut [hv_vcpu_t]) -> Result<()> {
         let ret = unsafe { hv_vcpus_exit(vcpus.as_mut_ptr(), vcpus.len() as u32) };
@@ -437,6 +858,68 @@ impl VirtualMachine {
     pub fn get_all_mapping_infos(&self) -> Vec<VirtualMachineMapping> {
         self.mapping_list.clone()
     }
+
+    /// Gets the handles and sizes of all allocations, and whether they are external.
+    pub(crate) fn get_all_allocation_infos(&self) -> Vec<(AllocationHandle, usize, bool)> {
+        self.allocation_list
+            .iter()
+            .map(|allocation| {
+                (
+                    allocation.handle,
+                    allocation.layout.size(),
+                    !allocation.owned,
+                )
+            })
+            .collect()
+    }
+
+    /// Recreate an allocation with a given handle, previously returned by
+    /// [VirtualMachine::allocate].
+    pub(crate) fn allocate_with_handle(
+        &mut self,
+        allocation_handle: AllocationHandle,
+        size: usize,
+    ) -> Result<()> {
+        if self.find_allocation_by_handle(allocation_handle).is_ok() {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let mut allocation = VirtualMachineAllocation::new(size);
+
+        allocation.handle = allocation_handle;
+
+        self.allocation_list.push(allocation);
+
+        Ok(())
+    }
+
+    /// Recreate a mapping with its handle, previously returned by [VirtualMachine::map].
+    pub(crate) fn map_with_handle(&mut self, mapping: VirtualMachineMapping) -> Result<()> {
+        if self.find_mapping_by_handle(mapping.mapping_handle).is_ok() {
+            return Err(HypervisorError::BadArgument);
+        }
+
+        let size = self.map_allocation(
+            mapping.allocation_handle,
+            mapping.address,
+            mapping.permission,
+        )?;
+
+        self.mapping_list
+            .push(VirtualMachineMapping { size, ..mapping });
+
+        Ok(())
+    }
+
+    /// Gets the ROM mappings with their original contents.
+    pub(crate) fn get_roms(&self) -> Vec<(MappingHandle, Vec<u8>)> {
+        self.rom_list.clone()
+    }
+
+    /// Replace the ROM mappings with their original contents.
+    pub(crate) fn set_roms(&mut self, roms: Vec<(MappingHandle, Vec<u8>)>) {
+        self.rom_list = roms;
+    }
 }
 
 impl Drop for VirtualMachine {
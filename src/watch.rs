@@ -0,0 +1,126 @@
+//! Software memory watches.
+//!
+//! A watch snapshots a guest memory range and reports the bytes that changed since the last
+//! check. This is a fallback for when all hardware watchpoint slots are in use: changes are only
+//! detected at the points where the caller checks (after each run, at breakpoints).
+
+use crate::bindings::hv_ipa_t;
+use crate::err::{HypervisorError, Result};
+use crate::virtual_machine::VirtualMachine;
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Identifier of a memory watch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WatchHandle(pub u64);
+
+/// A byte changed in a watched range.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryChange {
+    /// The watch that detected the change.
+    pub watch: WatchHandle,
+
+    /// The guest address of the byte.
+    pub address: hv_ipa_t,
+
+    /// The previous value.
+    pub old: u8,
+
+    /// The new value.
+    pub new: u8,
+}
+
+/// A watched memory range.
+#[derive(Clone, Debug)]
+struct MemoryWatch {
+    /// Associated handle.
+    handle: WatchHandle,
+
+    /// The guest address of the range.
+    address: hv_ipa_t,
+
+    /// The contents of the range at the last check.
+    snapshot: Vec<u8>,
+}
+
+/// A set of software memory watches.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryWatcher {
+    /// List of all watches.
+    watches: Vec<MemoryWatch>,
+
+    /// Last handle given.
+    last_handle: u64,
+}
+
+impl MemoryWatcher {
+    /// Create a new watcher without any watch.
+    pub fn new() -> Self {
+        MemoryWatcher::default()
+    }
+
+    /// Start watching a guest memory range, from its current contents.
+    pub fn watch_memory(
+        &mut self,
+        vm: &VirtualMachine,
+        address: hv_ipa_t,
+        size: usize,
+    ) -> Result<WatchHandle> {
+        let mut snapshot = vec![0; size];
+
+        vm.read_memory(address, &mut snapshot)?;
+
+        self.last_handle += 1;
+
+        let handle = WatchHandle(self.last_handle);
+
+        self.watches.push(MemoryWatch {
+            handle,
+            address,
+            snapshot,
+        });
+
+        Ok(handle)
+    }
+
+    /// Stop watching a range.
+    pub fn unwatch(&mut self, handle: WatchHandle) -> Result<()> {
+        let index = self
+            .watches
+            .iter()
+            .position(|watch| watch.handle == handle)
+            .ok_or(HypervisorError::InvalidHandle)?;
+
+        self.watches.remove(index);
+
+        Ok(())
+    }
+
+    /// Report all bytes changed since the last check, and take a new snapshot.
+    pub fn check(&mut self, vm: &VirtualMachine) -> Result<Vec<MemoryChange>> {
+        let mut result = Vec::new();
+
+        for watch in self.watches.iter_mut() {
+            let mut current = vec![0; watch.snapshot.len()];
+
+            vm.read_memory(watch.address, &mut current)?;
+
+            for (offset, (old, new)) in watch.snapshot.iter().zip(current.iter()).enumerate() {
+                if old != new {
+                    result.push(MemoryChange {
+                        watch: watch.handle,
+                        address: watch.address + offset as u64,
+                        old: *old,
+                        new: *new,
+                    });
+                }
+            }
+
+            watch.snapshot = current;
+        }
+
+        Ok(result)
+    }
+}